@@ -0,0 +1,62 @@
+//! Summarize the folio sizes of the cached pages of a file.
+use anyhow::Result;
+use colored::Colorize;
+use ff::pagemap::PageMapExt;
+use humansize::{BINARY, format_size};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::ops::Range;
+
+/// The number of folios of each order.
+#[derive(Debug, Default)]
+pub struct Histogram {
+    /// Number of complete folios by order.
+    pub orders: BTreeMap<u32, u64>,
+    /// Number of folios that are cut by the inspected range, their order is unknown.
+    pub partial: u64,
+}
+
+impl Histogram {
+    /// Group the cached pages of `file` into folios and count them by order.
+    pub fn new(file: &File, cached_ranges: &[Range<u64>]) -> Result<Self> {
+        let mut histogram = Histogram::default();
+        for range in cached_ranges {
+            for folio in file.folios(range.clone())? {
+                if folio.partial {
+                    histogram.partial += 1;
+                } else {
+                    *histogram.orders.entry(folio.order()).or_default() += 1;
+                }
+            }
+        }
+        Ok(histogram)
+    }
+
+    /// Print a row per order, with a bar for the share of the cached bytes.
+    pub fn print(&self, vm_page_size: u64) {
+        let formatter = BINARY.space_after_value(false);
+        let bytes = |order: u32, count: u64| count * (vm_page_size << order);
+        let total_bytes = self
+            .orders
+            .iter()
+            .map(|(order, count)| bytes(*order, *count))
+            .sum::<u64>();
+        let folios = self.orders.values().sum::<u64>() + self.partial;
+
+        println!(
+            "\t\tFolios: {} ({} partial)",
+            folios.to_string().bold(),
+            self.partial.to_string().bold()
+        );
+        for (order, count) in &self.orders {
+            // 40 cells for all the cached bytes.
+            let cells = (bytes(*order, *count) * 40).div_ceil(total_bytes.max(1));
+            println!(
+                "\t\t  order {order:<2} {:>8} {:>8}  {}",
+                format_size(vm_page_size << order, formatter),
+                count,
+                "█".repeat(cells as usize).cyan()
+            );
+        }
+    }
+}
@@ -0,0 +1,84 @@
+//! Estimate the working set of a file with idle page tracking.
+use anyhow::Result;
+use colored::Colorize;
+use ff::args::{coalesce_ranges, fmt_page_ranges, intersect_ranges, ranges_len, subtract_ranges};
+use ff::pagemap::PageMapExt;
+use humansize::{BINARY, format_size};
+use std::fs::File;
+use std::ops::Range;
+use std::time::Duration;
+
+/// The pages of a file that were accessed over an interval, see [`WorkingSet::track`].
+#[derive(Debug)]
+pub struct WorkingSet {
+    pub interval: Duration,
+    /// Number of cached pages that were marked idle.
+    pub tracked: u64,
+    /// Tracked pages that were accessed and are still cached.
+    pub accessed: Vec<Range<u64>>,
+    /// Pages that were read into the page cache during the interval.
+    pub added: Vec<Range<u64>>,
+    /// Tracked pages that were evicted during the interval.
+    pub evicted: Vec<Range<u64>>,
+}
+
+impl WorkingSet {
+    /// Mark the cached pages in `pages` idle, wait for `interval`, and collect the pages that
+    /// were accessed or read in since.
+    pub fn track(file: &File, pages: Range<u64>, interval: Duration) -> Result<Self> {
+        let idle = file.mark_idle(pages.clone())?;
+        std::thread::sleep(interval);
+        let accessed = coalesce_ranges(&idle.accessed()?);
+        let cached = file.cached_ranges(pages)?.collect::<Result<Vec<_>>>()?;
+
+        let tracked = coalesce_ranges(&idle.pages);
+        Ok(Self {
+            interval,
+            tracked: idle.pages.len() as u64,
+            accessed: intersect_ranges(&accessed, &cached),
+            // pages that could not be marked idle were cached all along.
+            added: subtract_ranges(
+                &subtract_ranges(&cached, &tracked),
+                &coalesce_ranges(&idle.untracked),
+            ),
+            evicted: subtract_ranges(&tracked, &cached),
+        })
+    }
+
+    /// The accessed and added pages, in order.
+    pub fn pages(&self) -> Vec<Range<u64>> {
+        let mut all = [self.accessed.as_slice(), self.added.as_slice()].concat();
+        all.sort_unstable_by_key(|range| range.start);
+        // accessed and added pages don't overlap, but their ranges can be adjacent.
+        let mut pages: Vec<Range<u64>> = Vec::with_capacity(all.len());
+        for range in all {
+            match pages.last_mut() {
+                Some(last) if last.end == range.start => last.end = range.end,
+                _ => pages.push(range),
+            }
+        }
+        pages
+    }
+
+    /// Print the working set of a range of `range_pages` pages and `range_len` bytes.
+    pub fn print(&self, range_pages: u64, range_len: u64, vm_page_size: u64) {
+        let formatter = BINARY.space_after_value(false);
+        let pages = self.pages();
+        let nr_pages = ranges_len(&pages);
+        let size = (nr_pages * vm_page_size).min(range_len);
+
+        println!(
+            "\t\tWorking Set: {}/{} {}/{} in {} ({} of {} accessed, {} read in, {} evicted)",
+            nr_pages.to_string().bold(),
+            range_pages.to_string().bold(),
+            format_size(size, formatter).bold(),
+            format_size(range_len, formatter).bold(),
+            humantime::format_duration(self.interval),
+            ranges_len(&self.accessed),
+            self.tracked,
+            ranges_len(&self.added),
+            ranges_len(&self.evicted),
+        );
+        println!("\t\t             {}", fmt_page_ranges(&pages));
+    }
+}
@@ -1,33 +1,219 @@
 //! Show information about the cached pages of a specific file.
-use anyhow::{Context, Result};
+mod folios;
+mod idle;
+mod numa;
+mod owners;
+mod physical;
+mod process;
+mod report;
+mod snapshot;
+mod sparse;
+mod summary;
+mod verbose;
+mod walk;
+mod warm;
+mod watch;
+
+use anyhow::{Context, Result, bail, ensure};
 use clap::Parser;
 use colored::Colorize;
-use ff::args::fmt_ranges;
-use ff::pagemap::{KPageFlags, PageMapExt, vm_page_size};
+use ff::KernelVersion;
+use ff::args::{fmt_page_ranges, parse_as_page_range, ranges_len};
+use ff::pagemap::{
+    CacheStat, EvictOptions, EvictionReport, EvictionStrategy, PageMapExt, PageState, TouchMethod,
+    cachestat_supported, page_idle_supported, vm_page_size,
+};
+use folios::Histogram;
 use humansize::{BINARY, format_size};
+use idle::WorkingSet;
 use log::{LevelFilter, debug};
+use nix::unistd::Pid;
+use numa::NodeUsage;
+use owners::Owners;
+use physical::{physical_runs, print_physical_runs};
+use report::{CachedPages, Eviction, FileReport, Layout, PageReport, Totals};
+use sparse::print_layout;
 use std::cmp::min;
-use std::fs::OpenOptions;
+use std::fs::{File, OpenOptions};
 use std::io::Write;
+use std::ops::Range;
+use std::os::unix::fs::FileTypeExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use verbose::{page_details, print_page_details};
+use walk::{WalkOptions, collect_files, parse_patterns};
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
+#[command(group(clap::ArgGroup::new("eviction").args(["evict", "evict_with"]).multiple(true)))]
 /// Show information about cached pages for a file.
+///
+/// If more than one file, or a directory, is passed, a summary row is shown for each file.
 struct Args {
-    filename: String,
+    /// Files or directories to inspect, directories are walked recursively.
+    #[arg(required_unless_present = "pid")]
+    paths: Vec<PathBuf>,
+    /// Inspect the files mapped by this process instead, with a row per mapping showing how
+    /// many of its pages are cached and how many are mapped by the process.
+    #[arg(
+        short = 'p',
+        long,
+        conflicts_with_all = [
+            "paths", "range", "eviction", "touch", "lock", "save", "diff", "restore", "watch",
+            "dirty", "stats", "verbose", "folios"
+        ]
+    )]
+    pid: Option<i32>,
+    /// Clear the soft-dirty bits of the process (see --pid), wait for this long (e.g. `10s`),
+    /// and show the file pages it wrote through shared mappings meanwhile.
+    ///
+    /// This resets the soft-dirty bits for other trackers of the process too, and requires a
+    /// kernel with CONFIG_MEM_SOFT_DIRTY.
+    #[arg(
+        long,
+        value_name = "INTERVAL",
+        value_parser = humantime::parse_duration,
+        requires = "pid"
+    )]
+    soft_dirty: Option<Duration>,
+    /// Follow symbolic links when walking directories.
+    #[arg(short = 'L', long, default_value_t = false)]
+    follow_symlinks: bool,
+    /// Don't cross filesystem boundaries when walking directories.
+    #[arg(short = 'x', long, default_value_t = false)]
+    one_file_system: bool,
+    /// Skip files and directories matching this glob pattern (can be repeated), e.g. `*.log`.
+    #[arg(long)]
+    exclude: Vec<String>,
+    /// Annotate the cached pages of a block device with the files (or the files in the
+    /// directories) whose data they hold, from their physical extents (can be repeated).
+    ///
+    /// The files must be on a filesystem on the inspected device.
+    #[arg(long, value_name = "PATH")]
+    owner: Vec<PathBuf>,
+    /// Only inspect or evict the pages in this range, e.g. `10-20`, `1M-2M`.
+    ///
+    /// Bounds without a unit (or with a `p` suffix) are page indices. Bounds with a size
+    /// unit (B, K, M, G) are byte offsets, and the range covers the bytes [start, end).
+    #[arg(short = 'r', long)]
+    range: Option<String>,
     /// Evict all cached pages.
     #[arg(short = 'e', long, default_value_t = false)]
     evict: bool,
-    /// Show dirty pages
+    /// Evict cached pages with these methods, one after the other, and report how many pages
+    /// each method removed, e.g. `fadvise,pageout`.
+    #[arg(long, value_enum, value_delimiter = ',')]
+    evict_with: Vec<EvictWith>,
+    /// The cgroup v2 to reclaim from with `--evict-with memcg`, the one the pages of the file are
+    /// charged to (see `-v`). Everything charged to it is reclaimed, not only the file (requires
+    /// root).
+    #[arg(long, requires = "evict_with")]
+    cgroup: Option<PathBuf>,
+    /// Write back dirty pages and wait for writeback to finish before evicting.
+    #[arg(long, default_value_t = false, requires = "eviction")]
+    sync_first: bool,
+    /// Retry evicting pages that are still cached this many times.
+    #[arg(long, default_value_t = 0, requires = "eviction")]
+    retries: u32,
+    /// Bring the pages into the page cache first, with `read` unless another method is given.
+    #[arg(
+        short = 't',
+        long,
+        value_enum,
+        num_args = 0..=1,
+        default_missing_value = "read",
+        conflicts_with = "eviction"
+    )]
+    touch: Option<TouchWith>,
+    /// Lock the pages in memory and keep them locked until SIGINT or SIGTERM is received.
+    #[arg(
+        short = 'l',
+        long,
+        default_value_t = false,
+        conflicts_with = "eviction"
+    )]
+    lock: bool,
+    /// Keep the pages locked from a detached background process, see --lock.
+    #[arg(long, default_value_t = false, requires = "lock")]
+    daemon: bool,
+    /// Save a snapshot of the cached, dirty and writeback pages to this file.
+    #[arg(long, value_name = "SNAPSHOT")]
+    save: Option<PathBuf>,
+    /// Show the pages that were added, removed or dirtied since this snapshot was saved.
+    #[arg(long, value_name = "SNAPSHOT")]
+    diff: Option<PathBuf>,
+    /// Bring the pages recorded in this snapshot back into the page cache, and evict the rest.
+    #[arg(long, value_name = "SNAPSHOT", conflicts_with_all = ["eviction", "touch"])]
+    restore: Option<PathBuf>,
+    /// Redraw a heat-map of the page states (absent, clean, dirty, writeback) at this interval,
+    /// e.g. `500ms`, `1s`.
+    #[arg(
+        short = 'w',
+        long,
+        value_name = "INTERVAL",
+        value_parser = humantime::parse_duration,
+        conflicts_with_all = ["eviction", "lock", "verbose"]
+    )]
+    watch: Option<Duration>,
+    /// Stop watching after this many redraws.
+    #[arg(long, requires = "watch")]
+    count: Option<u64>,
+    /// Estimate the working set: mark the cached pages idle, wait for this long (e.g. `10s`),
+    /// and show the pages that were accessed or read in meanwhile.
+    ///
+    /// Requires root and a kernel with idle page tracking (CONFIG_IDLE_PAGE_TRACKING).
+    #[arg(
+        long,
+        value_name = "INTERVAL",
+        value_parser = humantime::parse_duration,
+        conflicts_with_all = ["eviction", "watch", "lock", "diff", "pid"]
+    )]
+    idle: Option<Duration>,
+    /// Show dirty pages, or count the dirty pages of every file when summarizing many files.
     #[arg(short = 'd', long, default_value_t = false)]
     dirty: bool,
+    /// Show page cache statistics (dirty, writeback and evicted pages), requires cachestat(2).
+    #[arg(short = 's', long, default_value_t = false)]
+    stats: bool,
+    /// Don't use cachestat(2) even if the kernel supports it.
+    ///
+    /// Dirty pages, and the page states of --watch, --save and --diff, are then found through
+    /// /proc/kpageflags, which requires root and faults every cached page into a temporary
+    /// mapping.
+    #[arg(long, default_value_t = false)]
+    no_cachestat: bool,
+    /// Show a histogram of the folio sizes of the cached pages, requires root (see --verbose).
+    #[arg(short = 'f', long, default_value_t = false)]
+    folios: bool,
+    /// Show how many of the cached pages each NUMA node holds.
+    #[arg(long, default_value_t = false)]
+    numa: bool,
+    /// Show the data, unwritten extents and holes of a sparse file, and the cached share of
+    /// its data. This is shown with `--range` too, for the pages in the range.
+    #[arg(long, default_value_t = false)]
+    layout: bool,
+    /// Show the sectors that the cached pages are stored in, on the device of the filesystem.
+    ///
+    /// The extents are read with the FS_IOC_FIEMAP ioctl, or with FIBMAP (which requires root)
+    /// on filesystems without it.
+    #[arg(long, default_value_t = false)]
+    physical: bool,
     /// Verbose output.
     ///
     /// This will show the PageMapEntry flags (see man 5 proc_pid_pagemap), and
     ///
-    /// physical page frame flags (see man 5 proc_kpageflags) for each cached page.
+    /// physical page frame flags (see man 5 proc_kpageflags) for each cached page, grouped by
+    /// large folio.
     #[arg(short = 'v', long, default_value_t = false)]
     verbose: bool,
+    /// Output format, `json` and `ndjson` are meant for scripts.
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = Format::Text,
+        conflicts_with_all = ["watch", "diff", "lock"]
+    )]
+    format: Format,
 }
 
 fn main() -> Result<()> {
@@ -54,106 +240,502 @@ fn main() -> Result<()> {
         )
         .init();
 
+    let vm_page_size = vm_page_size()?;
+
+    // cachestat(2) is available since 6.5, older kernels fall back to mincore(2) and kpageflags.
+    let use_cachestat = !args.no_cachestat && cachestat_supported();
+
+    debug!("vm page size: {}", vm_page_size);
+    debug!("using cachestat(2): {}", use_cachestat);
+
+    if args.stats && !use_cachestat {
+        bail!("--stats requires cachestat(2) (Linux 6.5+)");
+    }
+    if args.idle.is_some() && !page_idle_supported() {
+        bail!("--idle requires idle page tracking (CONFIG_IDLE_PAGE_TRACKING)");
+    }
+
+    if let Some(pid) = args.pid {
+        return process::show_process(&args, Pid::from_raw(pid));
+    }
+
+    // a single file gets a detailed report, anything else is summarized per file.
+    let single = matches!(args.paths.as_slice(), [path] if !path.is_dir());
+    let files = if single {
+        args.paths.clone()
+    } else {
+        ensure!(!args.verbose, "--verbose only supports a single file");
+        ensure!(!args.stats, "--stats only supports a single file");
+        ensure!(!args.folios, "--folios only supports a single file");
+        ensure!(args.idle.is_none(), "--idle only supports a single file");
+        ensure!(!args.numa, "--numa only supports a single file");
+        ensure!(!args.physical, "--physical only supports a single file");
+        ensure!(
+            args.owner.is_empty(),
+            "--owner only supports a single device"
+        );
+
+        collect_files(
+            &args.paths,
+            &WalkOptions {
+                follow_symlinks: args.follow_symlinks,
+                one_file_system: args.one_file_system,
+                exclude: parse_patterns(&args.exclude)?,
+            },
+        )
+    };
+
+    if let Some(snapshot) = &args.restore {
+        snapshot::restore(&files, snapshot)?;
+    }
+    if let Some(method) = &args.touch {
+        warm::touch(&args, &files, method.into())?;
+    }
+    let locked = if args.lock {
+        warm::lock(&args, &files)?
+    } else {
+        vec![]
+    };
+
+    if let Some(interval) = args.watch {
+        return watch::watch(&args, &files, interval, args.count, use_cachestat);
+    }
+
+    if single {
+        show_file(&args, &files[0], use_cachestat)?;
+    } else {
+        summary::show_files(&args, &files, use_cachestat)?;
+    }
+
+    if let Some(snapshot) = &args.diff {
+        snapshot::diff(&files, snapshot, use_cachestat)?;
+    }
+    if let Some(snapshot) = &args.save {
+        snapshot::save(&files, snapshot, use_cachestat)?;
+    }
+
+    if args.lock {
+        warm::hold(locked, args.daemon)?;
+    }
+    Ok(())
+}
+
+/// Returns the pages to inspect in a file of `len` bytes, the whole file unless `--range` was
+/// passed.
+fn page_range(args: &Args, len: u64, vm_page_size: u64) -> Result<Range<u64>> {
+    let number_of_pages = len.div_ceil(vm_page_size);
+    Ok(match &args.range {
+        Some(range) => {
+            let range = parse_as_page_range(range, vm_page_size)?;
+            // a range past the end of the file is clamped to it.
+            let end = min(range.end().saturating_add(1), number_of_pages);
+            min(*range.start(), end)..end
+        }
+        None => 0..number_of_pages,
+    })
+}
+
+/// Evict `pages` with every method passed through `--evict-with` (or fadvise), returning a
+/// report per method.
+fn evict(
+    args: &Args,
+    file: &File,
+    pages: Range<u64>,
+) -> Result<Vec<(EvictionStrategy, EvictionReport)>> {
+    let methods = if args.evict_with.is_empty() {
+        vec![EvictWith::Fadvise]
+    } else {
+        args.evict_with.clone()
+    };
+
+    // all the methods are checked before any of them evicts.
+    let strategies = methods
+        .iter()
+        .map(|method| {
+            Ok(match method {
+                EvictWith::Fadvise => EvictionStrategy::Fadvise,
+                EvictWith::Pageout => EvictionStrategy::PageOut,
+                EvictWith::Cold => EvictionStrategy::Cold,
+                EvictWith::Memcg => EvictionStrategy::MemcgReclaim(
+                    args.cgroup
+                        .clone()
+                        .context("--evict-with memcg requires --cgroup")?,
+                ),
+                EvictWith::DropCaches => EvictionStrategy::DropCaches,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut reports = Vec::with_capacity(strategies.len());
+    for strategy in strategies {
+        debug!("evicting with {strategy:?}");
+
+        let report = file.evict_range_verified(
+            pages.clone(),
+            &EvictOptions {
+                strategy: strategy.clone(),
+                sync_first: args.sync_first,
+                retries: args.retries,
+            },
+        )?;
+        debug!("eviction attempts: {}", report.attempts);
+        reports.push((strategy, report));
+    }
+
+    Ok(reports)
+}
+
+/// Show a detailed report of the cached pages of a single file.
+fn show_file(args: &Args, path: &Path, use_cachestat: bool) -> Result<()> {
     let file = OpenOptions::new()
         .read(true)
-        .open(&args.filename)
-        .context(format!("failed to open {}", args.filename))?;
+        .open(path)
+        .context(format!("failed to open {}", path.display()))?;
 
     let fs_block_size = file.fs_block_size()?;
     let vm_page_size = vm_page_size()?;
-
     debug!("fs block size: {}", fs_block_size);
-    debug!("vm page size: {}", vm_page_size);
 
-    let cached_pages = file.cached_pages()?;
+    let len = file.size()?;
 
-    let len = file
-        .metadata()
-        .context("failed getting metadata for the file")?
-        .len();
+    let pages = page_range(args, len, vm_page_size)?;
+    let range_len = min(pages.end * vm_page_size, len) - min(pages.start * vm_page_size, len);
+    let range_pages = pages.end - pages.start;
+    debug!("pages: {}..{}", pages.start, pages.end);
 
-    let number_of_pages = len.div_ceil(vm_page_size as u64);
+    // the cached pages are scanned again by every report that needs them, rather than kept.
+    let cached_ranges =
+        || -> Result<Vec<Range<u64>>> { file.cached_ranges(pages.clone())?.collect() };
+
+    // only shmem files can have swapped out pages.
+    let swapped_pages = file.swapped_pages(pages.clone())?;
+    // finding the layout walks the extents of the range, so it is only done on request.
+    let layout = (args.layout || args.range.is_some())
+        .then(|| file.data_layout(pages.clone()))
+        .transpose()?;
+
+    let cachestat = if !use_cachestat {
+        None
+    } else if pages.is_empty() {
+        // an empty range means the whole file to cachestat(2).
+        Some(CacheStat::default())
+    } else {
+        Some(file.cachestat(pages.start * vm_page_size..pages.end * vm_page_size)?)
+    };
+    // evicting and --idle change the cache before a report is printed, its cached pages are
+    // collected first and counted from the same scan.
+    let changes_cache = args.evict || !args.evict_with.is_empty() || args.idle.is_some();
+    let cached_before = (args.format != Format::Text && changes_cache)
+        .then(cached_ranges)
+        .transpose()?;
+    let nr_cached = match (&cached_before, cachestat) {
+        (Some(ranges), _) => ranges_len(ranges),
+        (None, Some(stat)) => stat.nr_cache,
+        (None, None) => file.cached_ranges(pages.clone())?.nr_pages()?,
+    };
+
+    if args.format != Format::Text {
+        let mut report = FileReport {
+            path: path.to_owned(),
+            range: pages.clone(),
+            totals: Totals {
+                pages: range_pages,
+                size: range_len,
+                cached: Some(nr_cached),
+                cached_size: Some(min(nr_cached * vm_page_size, range_len)),
+                dirty: cachestat.map(|stat| stat.nr_dirty),
+                writeback: cachestat.map(|stat| stat.nr_writeback),
+                ..Default::default()
+            },
+            cached_pages: match cached_before {
+                Some(ranges) => CachedPages::Collected(ranges),
+                None => CachedPages::new(file.cached_ranges(pages.clone())?),
+            },
+            swapped_pages,
+            layout: match layout {
+                Some(layout) => Layout::new(layout, &cached_ranges()?),
+                None => None,
+            },
+            stats: cachestat.filter(|_| args.stats).map(Into::into),
+            dirty_pages: None,
+            folios: None,
+            numa: None,
+            physical: None,
+            owners: None,
+            working_set: None,
+            evictions: vec![],
+            page_info: vec![],
+        };
+
+        if args.evict || !args.evict_with.is_empty() {
+            report.evictions = evict(args, &file, pages.clone())?
+                .iter()
+                .map(|(strategy, r)| Eviction::new(strategy, r, range_len, vm_page_size))
+                .collect();
+            return report.print(args.format);
+        }
+        if args.dirty {
+            let dirty_pages = dirty_pages(&file, pages.clone(), use_cachestat)?;
+            report.dirty_pages = Some(dirty_pages);
+        }
+        if args.folios {
+            report.folios = Some(Histogram::new(&file, &cached_ranges()?)?.into());
+        }
+        if args.numa {
+            report.numa = Some(NodeUsage::new(&file, pages.clone())?.into());
+        }
+        if args.physical {
+            let runs = physical_runs(&file, &cached_ranges()?, vm_page_size)?;
+            report.physical = Some(runs.into_iter().map(Into::into).collect());
+        }
+        if !args.owner.is_empty() {
+            report.owners = Some(owners(args, &file, &cached_ranges()?, vm_page_size)?.into());
+        }
+        if let Some(interval) = args.idle {
+            report.working_set = Some(WorkingSet::track(&file, pages.clone(), interval)?.into());
+        }
+        if args.verbose {
+            let kernel = KernelVersion::current();
+            for page in page_details(&file, &cached_ranges()?)? {
+                report.page_info.push(PageReport::new(page, &kernel)?);
+            }
+        }
+        return report.print(args.format);
+    }
 
     let formatter = BINARY.space_after_value(false);
 
-    if args.evict {
-        file.evict_pages()?;
-        println!(
-            "\t\tEvicted {cached}/{total} {cached_size}/{total_size}",
-            cached = cached_pages.len().to_string().bold(),
-            total = number_of_pages.to_string().bold(),
-            cached_size = format_size(
-                min((cached_pages.len() as u64) * vm_page_size, len),
-                formatter
-            )
-            .bold(),
-            total_size = format_size(len, formatter).bold()
-        );
+    if args.evict || !args.evict_with.is_empty() {
+        let reports = evict(args, &file, pages.clone())?;
+        for (strategy, report) in &reports {
+            // only label the rows when comparing methods.
+            let label = if reports.len() > 1 {
+                format!("{:<12}", strategy.to_string())
+            } else {
+                String::new()
+            };
+            println!(
+                "\t\t{label}Evicted {evicted}/{total} {evicted_size}/{total_size}",
+                evicted = report.evicted.to_string().bold(),
+                total = range_pages.to_string().bold(),
+                evicted_size =
+                    format_size(min(report.evicted * vm_page_size, range_len), formatter).bold(),
+                total_size = format_size(range_len, formatter).bold()
+            );
+        }
+
+        // pages that survived the last method
+        let (_, report) = reports.last().expect("there is at least one method");
+        print_survivors(report, range_pages);
 
         return Ok(());
     }
 
     println!(
         "\t\tCached Pages: {}/{} {}/{}",
-        cached_pages.len().to_string().bold(),
-        number_of_pages.to_string().bold(),
-        format_size(
-            min((cached_pages.len() as u64) * vm_page_size, len),
-            formatter
-        )
-        .bold(),
-        format_size(len, formatter).bold()
+        nr_cached.to_string().bold(),
+        range_pages.to_string().bold(),
+        format_size(min(nr_cached * vm_page_size, range_len), formatter).bold(),
+        format_size(range_len, formatter).bold()
     );
 
+    if !swapped_pages.is_empty() {
+        println!(
+            "\t\tSwapped Pages: {}/{}",
+            ranges_len(&swapped_pages).to_string().bold(),
+            range_pages.to_string().bold(),
+        );
+        println!("\t\t               {}", fmt_page_ranges(&swapped_pages));
+    }
+
+    if let Some(layout) = &layout {
+        print_layout(layout, &cached_ranges()?, range_pages);
+    }
+
+    if let Some(stat) = cachestat
+        && args.stats
+    {
+        println!(
+            "\t\tDirty: {}  Writeback: {}  Evicted: {}  Recently Evicted: {}",
+            stat.nr_dirty.to_string().bold(),
+            stat.nr_writeback.to_string().bold(),
+            stat.nr_evicted.to_string().bold(),
+            stat.nr_recently_evicted.to_string().bold(),
+        );
+    }
+
     // show dirty pages in cache
     if args.dirty {
-        let dirty_pages = cached_pages
-            .iter()
-            .map(|page| {
-                let (_, kflags) = file.page_info(*page)?;
-                if kflags.contains(KPageFlags::DIRTY) {
-                    Ok(Some(*page))
-                } else {
-                    Ok(None)
-                }
-            })
-            .filter_map(|r| r.transpose())
-            .collect::<Result<Vec<_>>>()?;
+        let dirty_pages = dirty_pages(&file, pages.clone(), use_cachestat)?;
 
         println!(
             "\t\tDirty Pages: {}/{}",
-            dirty_pages.len().to_string().bold(),
-            number_of_pages.to_string().bold(),
+            ranges_len(&dirty_pages).to_string().bold(),
+            range_pages.to_string().bold(),
         );
         // show which pages are dirty
-        println!("\t\t             {}", fmt_ranges(dirty_pages.as_slice()));
+        println!("\t\t             {}", fmt_page_ranges(&dirty_pages));
+    }
+
+    if args.folios {
+        Histogram::new(&file, &cached_ranges()?)?.print(vm_page_size);
+    }
+
+    if args.numa {
+        NodeUsage::new(&file, pages.clone())?.print(vm_page_size);
+    }
+
+    if args.physical {
+        print_physical_runs(
+            &physical_runs(&file, &cached_ranges()?, vm_page_size)?,
+            vm_page_size,
+        );
+    }
+
+    if !args.owner.is_empty() {
+        owners(args, &file, &cached_ranges()?, vm_page_size)?.print();
+    }
+
+    if let Some(interval) = args.idle {
+        WorkingSet::track(&file, pages.clone(), interval)?.print(
+            range_pages,
+            range_len,
+            vm_page_size,
+        );
     }
 
     if args.verbose {
-        for i in cached_pages {
-            let (pagemap, kflags) = file.page_info(i)?;
-            println!("{} {}", "PAGE".bold(), i.to_string().cyan());
+        print_page_details(&page_details(&file, &cached_ranges()?)?, vm_page_size);
+    }
 
-            println!(
-                " {}\t  {}",
-                "pagemap (PageMapEntry)".dimmed(),
-                pagemap.to_string().dimmed()
-            );
-            println!(
-                " {}\t  {}",
-                "kflags (KPageFlags)".dimmed(),
-                kflags.to_string().dimmed()
-            );
-            println!();
+    Ok(())
+}
+
+/// Find the files passed with `--owner` that own the cached pages of the block device `file`.
+fn owners(
+    args: &Args,
+    file: &File,
+    cached_ranges: &[Range<u64>],
+    vm_page_size: u64,
+) -> Result<Owners> {
+    ensure!(
+        file.metadata()
+            .context("failed getting metadata for the file")?
+            .file_type()
+            .is_block_device(),
+        "--owner requires a block device"
+    );
+    let files = collect_files(
+        &args.owner,
+        &WalkOptions {
+            follow_symlinks: args.follow_symlinks,
+            // the physical offsets only make sense on the device.
+            one_file_system: true,
+            exclude: parse_patterns(&args.exclude)?,
+        },
+    );
+    Owners::new(file, &files, cached_ranges, vm_page_size)
+}
+
+/// Find the runs of dirty pages in `pages`, with [`PageMapExt::page_states`], which reads the
+/// state of a run of pages through a few `cachestat(2)` calls with `use_cachestat`.
+fn dirty_pages(file: &File, pages: Range<u64>, use_cachestat: bool) -> Result<Vec<Range<u64>>> {
+    let mut dirty_pages: Vec<Range<u64>> = Vec::new();
+    for (range, state) in file.page_states(pages, use_cachestat)? {
+        if state != PageState::Dirty {
+            continue;
+        }
+        match dirty_pages.last_mut() {
+            Some(last) if last.end == range.start => last.end = range.end,
+            _ => dirty_pages.push(range),
         }
     }
+    Ok(dirty_pages)
+}
 
-    Ok(())
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Format {
+    /// colored text
+    Text,
+    /// a single JSON document
+    Json,
+    /// a JSON object per line, for each file (and each page with --verbose)
+    Ndjson,
+}
+
+#[derive(clap::ValueEnum, Clone, Debug)]
+enum EvictWith {
+    /// posix_fadvise(POSIX_FADV_DONTNEED)
+    Fadvise,
+    /// madvise(MADV_PAGEOUT) on a mapping of the cached pages
+    Pageout,
+    /// madvise(MADV_COLD) on a mapping of the cached pages
+    Cold,
+    /// write to memory.reclaim of a cgroup v2 (see --cgroup)
+    Memcg,
+    /// write to /proc/sys/vm/drop_caches
+    DropCaches,
+}
+
+#[derive(clap::ValueEnum, Clone, Debug)]
+enum TouchWith {
+    /// pread(2) every page
+    Read,
+    /// readahead(2), asynchronous
+    Readahead,
+    /// posix_fadvise(POSIX_FADV_WILLNEED), asynchronous
+    Fadvise,
+    /// madvise(MADV_WILLNEED) on a mapping of the pages, asynchronous
+    Madvise,
+    /// mmap(2) with MAP_POPULATE
+    Populate,
+}
+
+impl From<&TouchWith> for TouchMethod {
+    fn from(method: &TouchWith) -> Self {
+        match method {
+            TouchWith::Read => TouchMethod::Read,
+            TouchWith::Readahead => TouchMethod::Readahead,
+            TouchWith::Fadvise => TouchMethod::Fadvise,
+            TouchWith::Madvise => TouchMethod::Madvise,
+            TouchWith::Populate => TouchMethod::Populate,
+        }
+    }
+}
+
+/// Print the pages that are still cached after eviction, grouped by reason.
+fn print_survivors(report: &EvictionReport, range_pages: u64) {
+    if report.survivors.is_empty() {
+        return;
+    }
+
+    println!(
+        "\t\tNot Evicted: {}/{}",
+        report.nr_survivors().to_string().bold(),
+        range_pages.to_string().bold(),
+    );
+
+    let mut reasons = report.survivors.iter().map(|(_, r)| *r).collect::<Vec<_>>();
+    reasons.sort();
+    reasons.dedup();
+    for reason in reasons {
+        let survivors = report
+            .survivors
+            .iter()
+            .filter(|(_, r)| *r == reason)
+            .map(|(pages, _)| pages.clone())
+            .collect::<Vec<_>>();
+        println!(
+            "\t\t  {:<12} {}",
+            reason.to_string().yellow(),
+            fmt_page_ranges(&survivors)
+        );
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use super::fmt_ranges;
+    use ff::args::fmt_ranges;
 
     #[test]
     fn fmt_range_test() {
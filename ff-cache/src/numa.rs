@@ -0,0 +1,43 @@
+//! Summarize the NUMA nodes that hold the cached pages of a file.
+use anyhow::Result;
+use colored::Colorize;
+use ff::pagemap::PageMapExt;
+use humansize::{BINARY, format_size};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::ops::Range;
+
+/// The number of cached pages on each NUMA node.
+#[derive(Debug, Default)]
+pub struct NodeUsage {
+    pub nodes: BTreeMap<u32, u64>,
+}
+
+impl NodeUsage {
+    /// Look up the node of every cached page in `pages` of `file`.
+    pub fn new(file: &File, pages: Range<u64>) -> Result<Self> {
+        let mut usage = NodeUsage::default();
+        for (pages, node) in file.numa_nodes(pages)? {
+            *usage.nodes.entry(node).or_default() += pages.end - pages.start;
+        }
+        Ok(usage)
+    }
+
+    /// Print a row per node, with a bar for its share of the cached pages.
+    pub fn print(&self, vm_page_size: u64) {
+        let formatter = BINARY.space_after_value(false);
+        let total = self.nodes.values().sum::<u64>();
+
+        println!("\t\tNUMA Nodes: {}", self.nodes.len().to_string().bold());
+        for (node, pages) in &self.nodes {
+            // 40 cells for all the cached pages.
+            let cells = (pages * 40).div_ceil(total.max(1));
+            println!(
+                "\t\t  node {node:<3} {:>8} {:>8}  {}",
+                pages,
+                format_size(pages * vm_page_size, formatter),
+                "█".repeat(cells as usize).cyan()
+            );
+        }
+    }
+}
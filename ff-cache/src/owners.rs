@@ -0,0 +1,152 @@
+//! Annotate the cached pages of a block device with the files whose extents they hold.
+use anyhow::{Context, Result};
+use colored::Colorize;
+use ff::args::{fmt_page_ranges, intersect_ranges, ranges_len, subtract_ranges};
+use ff::pagemap::PageMapExt;
+use log::warn;
+use std::fs::File;
+use std::ops::Range;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+/// The cached pages of a block device that hold the data of a file.
+#[derive(Debug)]
+pub struct Owner {
+    pub path: PathBuf,
+    pub pages: Vec<Range<u64>>,
+}
+
+/// The cached pages of a block device split by the file they belong to, see [`Owners::new`].
+#[derive(Debug, Default)]
+pub struct Owners {
+    /// Files that own at least one cached page, in the order they were passed.
+    pub files: Vec<Owner>,
+    /// Cached pages that are in none of the files, i.e. filesystem metadata (superblocks,
+    /// inode tables, the journal), free space, or files that were not passed.
+    pub other: Vec<Range<u64>>,
+}
+
+impl Owners {
+    /// Find which of `files` own the `cached_ranges` of the block device `device`, from the
+    /// physical offsets of their extents.
+    ///
+    /// The offsets are relative to the device that the filesystem is on, so files on other
+    /// devices (including the partitions of `device`) are skipped with a warning, as are files
+    /// that cannot be opened or mapped.
+    pub fn new(
+        device: &File,
+        files: &[PathBuf],
+        cached_ranges: &[Range<u64>],
+        vm_page_size: u64,
+    ) -> Result<Self> {
+        let rdev = device
+            .metadata()
+            .context("failed getting metadata for the device")?
+            .rdev();
+
+        let mut owned = Vec::new();
+        let mut owners = Self::default();
+        for path in files {
+            let pages = match owned_pages(path, rdev, vm_page_size) {
+                Ok(Some(pages)) => intersect_ranges(&pages, cached_ranges),
+                Ok(None) => continue,
+                Err(e) => {
+                    warn!("skipping {}: {e:#}", path.display());
+                    continue;
+                }
+            };
+            if !pages.is_empty() {
+                owned.extend(pages.iter().cloned());
+                owners.files.push(Owner {
+                    path: path.clone(),
+                    pages,
+                });
+            }
+        }
+
+        owned.sort_unstable_by_key(|range| range.start);
+        owners.other = subtract_ranges(cached_ranges, &owned);
+        Ok(owners)
+    }
+
+    /// Print a row per file and a row for the other pages, each followed by its ranges.
+    pub fn print(&self) {
+        println!("\t\tOwners: {} files", self.files.len().to_string().bold());
+        let rows = self
+            .files
+            .iter()
+            .map(|owner| (owner.path.display().to_string(), &owner.pages))
+            .chain((!self.other.is_empty()).then(|| {
+                (
+                    "other (metadata, free or other files)".to_owned(),
+                    &self.other,
+                )
+            }));
+        for (name, pages) in rows {
+            println!("\t\t  {:>8}  {}", ranges_len(pages), name);
+            println!("\t\t            {}", fmt_page_ranges(pages).dimmed());
+        }
+    }
+}
+
+/// Returns the pages of the device `rdev` that hold the data of `path`, or `None` if it is on
+/// another device.
+fn owned_pages(path: &Path, rdev: u64, vm_page_size: u64) -> Result<Option<Vec<Range<u64>>>> {
+    let file = File::open(path).context("failed to open the file")?;
+    let dev = file
+        .metadata()
+        .context("failed getting metadata for the file")?
+        .dev();
+    if dev != rdev {
+        warn!("skipping {}: it is not on the device", path.display());
+        return Ok(None);
+    }
+    device_pages(&file, vm_page_size)
+        .context("failed to map the extents")
+        .map(Some)
+}
+
+/// Returns the pages of the device that hold the data of `file`, sorted and coalesced.
+fn device_pages(file: &File, vm_page_size: u64) -> Result<Vec<Range<u64>>> {
+    let mut pages = file
+        .physical_extents(0..file.vm_pages_count()?)?
+        .iter()
+        .filter_map(|extent| {
+            let physical = extent.physical?;
+            Some(physical / vm_page_size..(physical + extent.length).div_ceil(vm_page_size))
+        })
+        .collect::<Vec<_>>();
+    // the extents are in file order, not in device order.
+    pages.sort_unstable_by_key(|range| range.start);
+
+    let mut coalesced: Vec<Range<u64>> = Vec::with_capacity(pages.len());
+    for range in pages {
+        match coalesced.last_mut() {
+            Some(last) if last.end >= range.start => last.end = last.end.max(range.end),
+            _ => coalesced.push(range),
+        }
+    }
+    Ok(coalesced)
+}
+
+#[cfg(test)]
+#[allow(clippy::single_range_in_vec_init)]
+mod test {
+    use super::Owners;
+    use std::fs::File;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_skips_files_it_cannot_open() {
+        // the device number of a regular file is 0, no file is on it.
+        let device = File::open(std::env::current_exe().unwrap()).unwrap();
+        let files = [
+            PathBuf::from("/nonexistent/ff-cache-owner"),
+            std::env::current_exe().unwrap(),
+        ];
+
+        let owners = Owners::new(&device, &files, &[0..8], 4096).unwrap();
+        assert!(owners.files.is_empty());
+        assert_eq!(owners.other, vec![0..8]);
+    }
+}
@@ -0,0 +1,115 @@
+//! Map the cached pages of a file to the sectors they are stored in.
+use anyhow::Result;
+use colored::Colorize;
+use ff::pagemap::{FiemapExtentFlags, PageMapExt};
+use humansize::{BINARY, format_size};
+use std::fs::File;
+use std::ops::Range;
+
+/// The size of a sector, the unit of block device addresses (LBAs) in Linux.
+const SECTOR_SIZE: u64 = 512;
+
+/// A run of cached pages in a single extent, or in a hole.
+#[derive(Debug)]
+pub struct PhysicalRun {
+    pub pages: Range<u64>,
+    /// The sector of the first page on the device of the filesystem, `None` in a hole or in an
+    /// extent whose location is not known yet (e.g. delayed allocation).
+    pub sector: Option<u64>,
+    /// The flags of the extent, `None` in a hole.
+    pub flags: Option<FiemapExtentFlags>,
+}
+
+impl PhysicalRun {
+    /// Returns the names of the extent flags, without `LAST` which only ends the extent map.
+    pub fn flag_names(&self) -> Vec<&'static str> {
+        self.flags
+            .iter()
+            .flat_map(|flags| flags.iter_names())
+            .map(|(name, _)| name)
+            .filter(|name| *name != "LAST")
+            .collect()
+    }
+}
+
+/// Split the cached ranges into runs by the extent they are in.
+///
+/// A page can be in more than one extent if the filesystem block size is smaller than a page.
+pub fn physical_runs(
+    file: &File,
+    cached_ranges: &[Range<u64>],
+    vm_page_size: u64,
+) -> Result<Vec<PhysicalRun>> {
+    let (Some(first), Some(last)) = (cached_ranges.first(), cached_ranges.last()) else {
+        return Ok(vec![]);
+    };
+    let extents = file.physical_extents(first.start..last.end)?;
+
+    let hole = |pages| PhysicalRun {
+        pages,
+        sector: None,
+        flags: None,
+    };
+    let mut runs = Vec::new();
+    for range in cached_ranges.iter().cloned() {
+        // the first page of `range` that is not in a run yet.
+        let mut page = range.start;
+        let bytes = range.start * vm_page_size..range.end * vm_page_size;
+        let overlapping = extents
+            .iter()
+            .skip_while(|e| e.logical_range().end <= bytes.start)
+            .take_while(|e| e.logical < bytes.end);
+
+        for extent in overlapping {
+            let logical = extent.logical_range();
+            let start = (logical.start / vm_page_size).max(range.start);
+            let end = logical.end.div_ceil(vm_page_size).min(range.end);
+            if start > page {
+                runs.push(hole(page..start));
+            }
+            runs.push(PhysicalRun {
+                pages: start..end,
+                sector: extent.physical.map(|physical| {
+                    (physical + (start * vm_page_size).saturating_sub(logical.start)) / SECTOR_SIZE
+                }),
+                flags: Some(extent.flags),
+            });
+            page = page.max(end);
+        }
+        if page < range.end {
+            runs.push(hole(page..range.end));
+        }
+    }
+
+    Ok(runs)
+}
+
+/// Print a row per run, with its first sector, size and extent flags. Holes have a row too.
+pub fn print_physical_runs(runs: &[PhysicalRun], vm_page_size: u64) {
+    let formatter = BINARY.space_after_value(false);
+
+    let extents = runs.iter().filter(|run| run.flags.is_some()).count();
+    println!("\t\tPhysical Extents: {}", extents.to_string().bold());
+    for run in runs {
+        let pages = if run.pages.end - run.pages.start == 1 {
+            run.pages.start.to_string()
+        } else {
+            format!("{}-{}", run.pages.start, run.pages.end - 1)
+        };
+        let sector = match run.sector {
+            Some(sector) => format!("sector {sector}"),
+            None => "-".to_owned(),
+        };
+        let flags = match run.flags {
+            Some(_) => run.flag_names().join(" | "),
+            None => "hole".to_owned(),
+        };
+        println!(
+            "\t\t  pages {:<16} {:>18}  {:>8}  {}",
+            pages,
+            sector,
+            format_size((run.pages.end - run.pages.start) * vm_page_size, formatter),
+            flags.dimmed()
+        );
+    }
+}
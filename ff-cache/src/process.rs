@@ -0,0 +1,184 @@
+//! Inspect the page cache of the files mapped by a running process.
+use crate::report::{self, MappedFileReport, MappingReport, Totals};
+use crate::{Args, Format};
+use anyhow::{Result, ensure};
+use colored::Colorize;
+use ff::args::{fmt_page_ranges, intersect_ranges, ranges_len};
+use ff::maps::{MapEntry, read_maps};
+use ff::pagemap::{
+    PageCategory, PageMapExt, clear_soft_dirty, scan_page_map, soft_dirty_supported, vm_page_size,
+};
+use humansize::{BINARY, format_size};
+use log::{info, warn};
+use nix::unistd::Pid;
+use std::cmp::min;
+
+/// Returns the number of pages of `mapping` that are file pages in the page tables of `pid`.
+///
+/// Private mappings may also have anonymous copies of the file pages, these are not counted.
+fn mapped_pages(pid: Pid, mapping: &MapEntry, vm_page_size: u64) -> Result<u64> {
+    Ok(scan_page_map(
+        Some(pid),
+        mapping.virtual_pages(vm_page_size),
+        PageCategory::PRESENT | PageCategory::FILE,
+    )?
+    .iter()
+    .map(|run| run.end - run.start)
+    .sum())
+}
+
+/// Report the page cache usage of the file behind `mappings`, which all map the same file.
+///
+/// With `soft_dirty`, the pages written through each shared mapping are reported too.
+fn inspect(
+    pid: Pid,
+    mappings: &[&MapEntry],
+    soft_dirty: bool,
+    vm_page_size: u64,
+) -> Result<MappedFileReport> {
+    let file = mappings[0].open(pid)?;
+    let len = file.size()?;
+    let number_of_pages = len.div_ceil(vm_page_size);
+    let cached_ranges = file
+        .cached_ranges(0..number_of_pages)?
+        .collect::<Result<Vec<_>>>()?;
+    let nr_cached = ranges_len(&cached_ranges);
+
+    let mut reports = Vec::with_capacity(mappings.len());
+    for mapping in mappings {
+        let pages = mapping.file_pages(vm_page_size);
+        // the pages past the end of the file cannot be accessed.
+        let range = min(pages.start, number_of_pages)..min(pages.end, number_of_pages);
+        let cached = ranges_len(&intersect_ranges(
+            &cached_ranges,
+            std::slice::from_ref(&range),
+        ));
+
+        reports.push(MappingReport {
+            address: format!("{:x}-{:x}", mapping.start, mapping.end),
+            perms: mapping.perms.clone(),
+            range,
+            cached,
+            mapped: mapped_pages(pid, mapping, vm_page_size)?,
+            written: if soft_dirty {
+                Some(mapping.written_pages(Some(pid), vm_page_size)?)
+            } else {
+                None
+            },
+        });
+    }
+
+    Ok(MappedFileReport {
+        path: mappings[0].path().unwrap_or_default(),
+        totals: Totals {
+            pages: number_of_pages,
+            size: len,
+            cached: Some(nr_cached),
+            cached_size: Some(min(nr_cached * vm_page_size, len)),
+            ..Default::default()
+        },
+        mappings: reports,
+    })
+}
+
+/// Show the cached pages of every file mapped by `pid`, and for each mapping, how many of its
+/// pages are cached and how many are mapped by the process.
+///
+/// Files are sorted by the most cached bytes. Files that cannot be inspected are skipped with a
+/// warning.
+///
+/// With `--soft-dirty`, the soft-dirty bits of the process are cleared first, and the file pages
+/// written through shared mappings are shown after the interval.
+pub fn show_process(args: &Args, pid: Pid) -> Result<()> {
+    let vm_page_size = vm_page_size()?;
+
+    if let Some(interval) = args.soft_dirty {
+        ensure!(
+            soft_dirty_supported()?,
+            "--soft-dirty requires soft-dirty tracking (CONFIG_MEM_SOFT_DIRTY)"
+        );
+        clear_soft_dirty(Some(pid))?;
+        info!(
+            "tracking writes of pid {pid} for {}",
+            humantime::format_duration(interval)
+        );
+        std::thread::sleep(interval);
+    }
+
+    let maps = read_maps(pid)?;
+
+    // group the mappings by file, in the order they are first mapped.
+    let mut files: Vec<Vec<&MapEntry>> = Vec::new();
+    for mapping in maps.iter().filter(|m| m.is_file()) {
+        match files
+            .iter_mut()
+            .find(|f| (f[0].dev, f[0].inode) == (mapping.dev, mapping.inode))
+        {
+            Some(file) => file.push(mapping),
+            None => files.push(vec![mapping]),
+        }
+    }
+
+    let mut reports = Vec::with_capacity(files.len());
+    for mappings in &files {
+        match inspect(pid, mappings, args.soft_dirty.is_some(), vm_page_size) {
+            Ok(report) => reports.push(report),
+            Err(e) => warn!(
+                "skipping {}: {e:#}",
+                mappings[0].path().unwrap_or_default().display()
+            ),
+        }
+    }
+    let cached_size = |r: &MappedFileReport| r.totals.cached_size.unwrap_or_default();
+    reports.sort_by(|a, b| {
+        cached_size(b)
+            .cmp(&cached_size(a))
+            .then_with(|| a.path.cmp(&b.path))
+    });
+
+    if args.format != Format::Text {
+        return report::print_mapped_files(pid.as_raw(), &reports, args.format);
+    }
+
+    let formatter = BINARY.space_after_value(false);
+    for report in &reports {
+        let cached = report.totals.cached.unwrap_or_default();
+        println!("{}", report.path.display().to_string().bold());
+        println!(
+            "\t\tCached Pages: {}/{} {}/{}",
+            cached.to_string().bold(),
+            report.totals.pages.to_string().bold(),
+            format_size(cached_size(report), formatter).bold(),
+            format_size(report.totals.size, formatter).bold()
+        );
+        for mapping in &report.mappings {
+            let pages = mapping.range.end - mapping.range.start;
+            let range = match pages {
+                0 => "-".to_owned(),
+                1 => mapping.range.start.to_string(),
+                _ => format!("{}-{}", mapping.range.start, mapping.range.end - 1),
+            };
+            let mut row = format!(
+                "\t\t{} {}  pages {:<12} cached {:<14} mapped {}/{pages}",
+                mapping.address.dimmed(),
+                mapping.perms,
+                range,
+                format!("{}/{pages}", mapping.cached),
+                mapping.mapped,
+            );
+            let written = mapping.written.as_deref().unwrap_or_default();
+            if !written.is_empty() {
+                row.push_str(&format!(
+                    "  written {}",
+                    ranges_len(written).to_string().yellow()
+                ));
+            }
+            println!("{row}");
+            if !written.is_empty() {
+                println!("\t\t  written pages {}", fmt_page_ranges(written));
+            }
+        }
+    }
+
+    Ok(())
+}
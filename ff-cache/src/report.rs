@@ -0,0 +1,567 @@
+//! Machine-readable reports, printed with `--format json` or `--format ndjson`.
+//!
+//! Page lists are coalesced into ranges of page indices, `{"start": 0, "end": 4}` is the pages
+//! `[0, 4)`. Flag bits are hex strings because they do not fit in a JSON (double) number.
+use crate::Format;
+use crate::folios::Histogram;
+use crate::idle::WorkingSet;
+use crate::numa::NodeUsage;
+use crate::owners::Owners;
+use crate::physical::PhysicalRun;
+use crate::verbose::PageDetails;
+use anyhow::Result;
+use ff::KernelVersion;
+use ff::pagemap::{
+    CacheStat, CachedRanges, DataLayout, EvictionReport, EvictionStrategy, KPageFlags,
+    PageLocation, PageMapEntry,
+};
+use serde::ser::{Error, SerializeSeq};
+use serde::{Serialize, Serializer};
+use std::cell::RefCell;
+use std::cmp::min;
+use std::io::Write;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+/// Page and byte counts shared by a file report, a summary row and the total row.
+#[derive(Serialize, Debug, Default)]
+pub struct Totals {
+    /// Number of pages in the inspected range.
+    pub pages: u64,
+    /// Size in bytes of the inspected range.
+    pub size: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cached: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cached_size: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub evicted: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub evicted_size: Option<u64>,
+    /// Dirty and writeback pages, only known with cachestat(2).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dirty: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub writeback: Option<u64>,
+}
+
+/// `cachestat(2)` statistics, see [`CacheStat`].
+#[derive(Serialize, Debug)]
+pub struct Stats {
+    pub nr_cache: u64,
+    pub nr_dirty: u64,
+    pub nr_writeback: u64,
+    pub nr_evicted: u64,
+    pub nr_recently_evicted: u64,
+}
+
+impl From<CacheStat> for Stats {
+    fn from(stat: CacheStat) -> Self {
+        Self {
+            nr_cache: stat.nr_cache,
+            nr_dirty: stat.nr_dirty,
+            nr_writeback: stat.nr_writeback,
+            nr_evicted: stat.nr_evicted,
+            nr_recently_evicted: stat.nr_recently_evicted,
+        }
+    }
+}
+
+/// Pages that are still cached after eviction, for a single reason.
+#[derive(Serialize, Debug)]
+pub struct Survivors {
+    pub reason: String,
+    pub pages: Vec<Range<u64>>,
+}
+
+/// The result of evicting a file with a single method.
+#[derive(Serialize, Debug)]
+pub struct Eviction {
+    pub strategy: String,
+    pub evicted: u64,
+    pub evicted_size: u64,
+    pub attempts: u32,
+    pub survivors: Vec<Survivors>,
+}
+
+impl Eviction {
+    pub fn new(
+        strategy: &EvictionStrategy,
+        report: &EvictionReport,
+        range_len: u64,
+        vm_page_size: u64,
+    ) -> Self {
+        let mut reasons = report.survivors.iter().map(|(_, r)| *r).collect::<Vec<_>>();
+        reasons.sort();
+        reasons.dedup();
+
+        Self {
+            strategy: strategy.to_string(),
+            evicted: report.evicted,
+            evicted_size: min(report.evicted * vm_page_size, range_len),
+            attempts: report.attempts,
+            survivors: reasons
+                .into_iter()
+                .map(|reason| Survivors {
+                    reason: reason.to_string(),
+                    pages: report
+                        .survivors
+                        .iter()
+                        .filter(|(_, r)| *r == reason)
+                        .map(|(pages, _)| pages.clone())
+                        .collect(),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// The raw bits of a flags value and the names of the known flags that are set.
+#[derive(Serialize, Debug)]
+pub struct Flags {
+    pub bits: String,
+    pub flags: Vec<String>,
+}
+
+/// A swap entry, see [`PageLocation::Swapped`].
+#[derive(Serialize, Debug)]
+pub struct Swap {
+    #[serde(rename = "type")]
+    pub ty: u8,
+    pub offset: u64,
+}
+
+/// A decoded [`PageMapEntry`].
+#[derive(Serialize, Debug)]
+pub struct PageMap {
+    #[serde(flatten)]
+    pub flags: Flags,
+    pub pfn: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub swap: Option<Swap>,
+}
+
+impl TryFrom<&PageMapEntry> for PageMap {
+    type Error = anyhow::Error;
+
+    fn try_from(entry: &PageMapEntry) -> Result<Self> {
+        Ok(Self {
+            flags: Flags {
+                bits: format!("{:#x}", entry.bits()),
+                flags: entry
+                    .iter_names()
+                    .map(|(name, _)| name.to_owned())
+                    .collect(),
+            },
+            pfn: entry.pfn()?,
+            swap: match entry.location()? {
+                PageLocation::Swapped { ty, offset } => Some(Swap { ty, offset }),
+                _ => None,
+            },
+        })
+    }
+}
+
+impl Flags {
+    /// The kernel page flags, named as `kernel` defines them, see [`KPageFlags::describe`].
+    pub fn from_kflags(kflags: KPageFlags, kernel: &KernelVersion) -> Self {
+        Self {
+            bits: format!("{:#x}", kflags.bits()),
+            flags: kflags
+                .describe(kernel)
+                .into_iter()
+                .map(|(name, _)| name)
+                .collect(),
+        }
+    }
+}
+
+/// The memory cgroup a page is charged to.
+#[derive(Serialize, Debug, Clone)]
+pub struct Cgroup {
+    pub inode: u64,
+    /// Path relative to the mountpoint of the memory controller, unknown if the cgroup was not
+    /// found.
+    pub path: Option<PathBuf>,
+}
+
+/// The page map entry and kernel page flags of a single cached page.
+#[derive(Serialize, Debug)]
+pub struct PageReport {
+    pub page: u64,
+    /// The pages of the folio this page belongs to.
+    pub folio: Range<u64>,
+    pub pagemap: PageMap,
+    pub kflags: Flags,
+    /// Number of times the page frame is mapped, from `/proc/kpagecount`.
+    pub kpagecount: u64,
+    /// `null` if the page is not charged to a cgroup, or `/proc/kpagecgroup` is missing.
+    pub cgroup: Option<Cgroup>,
+}
+
+impl PageReport {
+    pub fn new(details: PageDetails, kernel: &KernelVersion) -> Result<Self> {
+        Ok(Self {
+            page: details.info.page,
+            folio: details.folio.pages,
+            pagemap: PageMap::try_from(&details.info.pagemap)?,
+            kflags: Flags::from_kflags(details.info.kflags, kernel),
+            kpagecount: details.map_count,
+            cgroup: details.cgroup,
+        })
+    }
+}
+
+/// The number of folios of a single order.
+#[derive(Serialize, Debug)]
+pub struct FolioCount {
+    pub order: u32,
+    pub count: u64,
+}
+
+/// The folio sizes of the cached pages, see [`Histogram`].
+#[derive(Serialize, Debug)]
+pub struct Folios {
+    pub orders: Vec<FolioCount>,
+    /// Folios that are cut by the inspected range.
+    pub partial: u64,
+}
+
+impl From<Histogram> for Folios {
+    fn from(histogram: Histogram) -> Self {
+        Self {
+            orders: histogram
+                .orders
+                .into_iter()
+                .map(|(order, count)| FolioCount { order, count })
+                .collect(),
+            partial: histogram.partial,
+        }
+    }
+}
+
+/// The number of cached pages on a single NUMA node.
+#[derive(Serialize, Debug)]
+pub struct NodeCount {
+    pub node: u32,
+    pub pages: u64,
+}
+
+impl From<NodeUsage> for Vec<NodeCount> {
+    fn from(usage: NodeUsage) -> Self {
+        usage
+            .nodes
+            .into_iter()
+            .map(|(node, pages)| NodeCount { node, pages })
+            .collect()
+    }
+}
+
+/// A run of cached pages in a single extent or in a hole, see [`PhysicalRun`].
+#[derive(Serialize, Debug)]
+pub struct PhysicalRunReport {
+    pub pages: Range<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sector: Option<u64>,
+    pub hole: bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub flags: Vec<&'static str>,
+}
+
+impl From<PhysicalRun> for PhysicalRunReport {
+    fn from(run: PhysicalRun) -> Self {
+        Self {
+            flags: run.flag_names(),
+            hole: run.flags.is_none(),
+            sector: run.sector,
+            pages: run.pages,
+        }
+    }
+}
+
+/// The cached pages of a block device that hold the data of a file.
+#[derive(Serialize, Debug)]
+pub struct OwnerReport {
+    pub path: PathBuf,
+    pub pages: Vec<Range<u64>>,
+}
+
+/// The cached pages of a block device split by the file they belong to, see [`Owners`].
+#[derive(Serialize, Debug)]
+pub struct OwnersReport {
+    pub files: Vec<OwnerReport>,
+    /// Cached pages that are in none of the files.
+    pub other: Vec<Range<u64>>,
+}
+
+impl From<Owners> for OwnersReport {
+    fn from(owners: Owners) -> Self {
+        Self {
+            files: owners
+                .files
+                .into_iter()
+                .map(|owner| OwnerReport {
+                    path: owner.path,
+                    pages: owner.pages,
+                })
+                .collect(),
+            other: owners.other,
+        }
+    }
+}
+
+/// The working set of a file over an interval, see [`WorkingSet`].
+#[derive(Serialize, Debug)]
+pub struct WorkingSetReport {
+    pub interval_ms: u128,
+    /// Number of cached pages that were marked idle.
+    pub tracked: u64,
+    pub accessed: Vec<Range<u64>>,
+    /// Pages read into the page cache during the interval.
+    pub added: Vec<Range<u64>>,
+    pub evicted: Vec<Range<u64>>,
+}
+
+impl From<WorkingSet> for WorkingSetReport {
+    fn from(set: WorkingSet) -> Self {
+        Self {
+            interval_ms: set.interval.as_millis(),
+            tracked: set.tracked,
+            accessed: set.accessed,
+            added: set.added,
+            evicted: set.evicted,
+        }
+    }
+}
+
+/// The data, unwritten extents and holes of a sparse file, see [`ff::pagemap::DataLayout`].
+#[derive(Serialize, Debug)]
+pub struct Layout {
+    pub data_pages: u64,
+    /// Cached pages with data, the residency of a sparse file is `cached_data / data_pages`.
+    pub cached_data: u64,
+    pub data: Vec<Range<u64>>,
+    pub unwritten: Vec<Range<u64>>,
+    pub holes: Vec<Range<u64>>,
+}
+
+impl Layout {
+    /// Returns `None` if the file has no holes or unwritten extents.
+    pub fn new(layout: DataLayout, cached_ranges: &[Range<u64>]) -> Option<Self> {
+        layout.is_sparse().then(|| Self {
+            data_pages: layout.nr_data(),
+            cached_data: layout.count_data(cached_ranges),
+            data: layout.data,
+            unwritten: layout.unwritten,
+            holes: layout.holes,
+        })
+    }
+}
+
+/// The runs of cached pages of a file.
+pub enum CachedPages<'a> {
+    /// Serialized while they are scanned so that they are never all in memory. They can only be
+    /// serialized once.
+    Scan(RefCell<Option<CachedRanges<'a>>>),
+    /// Collected before the report changes the page cache (e.g. by evicting).
+    Collected(Vec<Range<u64>>),
+}
+
+impl<'a> CachedPages<'a> {
+    pub fn new(ranges: CachedRanges<'a>) -> Self {
+        CachedPages::Scan(RefCell::new(Some(ranges)))
+    }
+}
+
+impl std::fmt::Debug for CachedPages<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("CachedPages")
+    }
+}
+
+impl Serialize for CachedPages<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let scan = match self {
+            CachedPages::Scan(scan) => scan,
+            CachedPages::Collected(ranges) => return ranges.serialize(serializer),
+        };
+        let ranges = scan
+            .borrow_mut()
+            .take()
+            .ok_or_else(|| S::Error::custom("the cached pages were already serialized"))?;
+        let mut seq = serializer.serialize_seq(None)?;
+        for range in ranges {
+            seq.serialize_element(&range.map_err(|e| S::Error::custom(format!("{e:#}")))?)?;
+        }
+        seq.end()
+    }
+}
+
+/// A detailed report of a single file.
+#[derive(Serialize, Debug)]
+pub struct FileReport<'a> {
+    pub path: PathBuf,
+    /// The inspected pages, the whole file unless `--range` was passed.
+    pub range: Range<u64>,
+    #[serde(flatten)]
+    pub totals: Totals,
+    pub cached_pages: CachedPages<'a>,
+    /// Swapped out pages of a shmem file, see [`ff::pagemap::PageMapExt::swapped_pages`].
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub swapped_pages: Vec<Range<u64>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub layout: Option<Layout>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stats: Option<Stats>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dirty_pages: Option<Vec<Range<u64>>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub folios: Option<Folios>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub numa: Option<Vec<NodeCount>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub physical: Option<Vec<PhysicalRunReport>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub owners: Option<OwnersReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub working_set: Option<WorkingSetReport>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub evictions: Vec<Eviction>,
+    /// Only with `--verbose`, and only in a JSON document. NDJSON has a line per page instead.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub page_info: Vec<PageReport>,
+}
+
+/// A summary row of a single file, see [`crate::summary`].
+#[derive(Serialize, Debug)]
+pub struct SummaryRow {
+    pub path: PathBuf,
+    #[serde(flatten)]
+    pub totals: Totals,
+}
+
+/// The sum of all summary rows.
+#[derive(Serialize, Debug)]
+pub struct Total {
+    pub files: usize,
+    #[serde(flatten)]
+    pub totals: Totals,
+}
+
+/// A file mapping of a process, see [`crate::process`].
+#[derive(Serialize, Debug)]
+pub struct MappingReport {
+    /// Address range of the mapping, as in `/proc/PID/maps`.
+    pub address: String,
+    pub perms: String,
+    /// The file pages covered by the mapping, up to the end of the file.
+    pub range: Range<u64>,
+    /// Pages of the range in the page cache.
+    pub cached: u64,
+    /// Pages of the range mapped in the page tables of the process.
+    pub mapped: u64,
+    /// File pages written through the mapping, only with `--soft-dirty`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub written: Option<Vec<Range<u64>>>,
+}
+
+/// The page cache usage of a file mapped by a process.
+#[derive(Serialize, Debug)]
+pub struct MappedFileReport {
+    pub path: PathBuf,
+    #[serde(flatten)]
+    pub totals: Totals,
+    pub mappings: Vec<MappingReport>,
+}
+
+/// A line of NDJSON output, tagged with its `type`.
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Record<'a, 'f> {
+    File(&'a FileReport<'f>),
+    #[serde(rename = "file")]
+    MappedFile {
+        pid: i32,
+        #[serde(flatten)]
+        file: &'a MappedFileReport,
+    },
+    Page {
+        path: &'a Path,
+        #[serde(flatten)]
+        page: &'a PageReport,
+    },
+    Summary(&'a SummaryRow),
+    Total(&'a Total),
+}
+
+/// Print `value` as a line of JSON, written to stdout as it is serialized.
+fn print_line<T: Serialize>(value: &T) -> Result<()> {
+    let mut stdout = std::io::stdout().lock();
+    serde_json::to_writer(&mut stdout, value)?;
+    writeln!(stdout)?;
+    Ok(())
+}
+
+impl FileReport<'_> {
+    /// Print the report as a single JSON document, or as a `file` line followed by a `page`
+    /// line per page.
+    pub fn print(mut self, format: Format) -> Result<()> {
+        match format {
+            Format::Text => unreachable!("text reports are printed by the caller"),
+            Format::Json => print_line(&self),
+            Format::Ndjson => {
+                let pages = std::mem::take(&mut self.page_info);
+                print_line(&Record::File(&self))?;
+                for page in &pages {
+                    print_line(&Record::Page {
+                        path: &self.path,
+                        page,
+                    })?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Print the summary rows and their total as a single JSON document (`{"files": [..],
+/// "total": {..}}`), or as a `summary` line per file followed by a `total` line.
+pub fn print_summary(rows: &[SummaryRow], total: &Total, format: Format) -> Result<()> {
+    #[derive(Serialize)]
+    struct Document<'a> {
+        files: &'a [SummaryRow],
+        total: &'a Total,
+    }
+
+    match format {
+        Format::Text => unreachable!("text reports are printed by the caller"),
+        Format::Json => print_line(&Document { files: rows, total }),
+        Format::Ndjson => {
+            for row in rows {
+                print_line(&Record::Summary(row))?;
+            }
+            print_line(&Record::Total(total))
+        }
+    }
+}
+
+/// Print the files mapped by process `pid` as a single JSON document (`{"pid": .., "files":
+/// [..]}`), or as a `file` line per file.
+pub fn print_mapped_files(pid: i32, files: &[MappedFileReport], format: Format) -> Result<()> {
+    #[derive(Serialize)]
+    struct Document<'a> {
+        pid: i32,
+        files: &'a [MappedFileReport],
+    }
+
+    match format {
+        Format::Text => unreachable!("text reports are printed by the caller"),
+        Format::Json => print_line(&Document { pid, files }),
+        Format::Ndjson => {
+            for file in files {
+                print_line(&Record::MappedFile { pid, file })?;
+            }
+            Ok(())
+        }
+    }
+}
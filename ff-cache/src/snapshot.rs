@@ -0,0 +1,107 @@
+//! Save, diff and restore page cache snapshots of the inspected files.
+use anyhow::{Result, ensure};
+use colored::Colorize;
+use ff::args::{fmt_page_ranges, ranges_len};
+use ff::pagemap::{PageCacheSnapshot, TouchMethod};
+use log::warn;
+use std::path::{Path, PathBuf};
+
+fn canonical(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or(path.to_owned())
+}
+
+/// Capture a snapshot of every file.
+///
+/// Every file that cannot be inspected is reported with a warning, and then an error is returned
+/// so that a partial snapshot is never used.
+fn capture(files: &[PathBuf], use_cachestat: bool) -> Result<Vec<PageCacheSnapshot>> {
+    let mut snapshots = Vec::with_capacity(files.len());
+    let mut failed = 0;
+    for path in files {
+        match PageCacheSnapshot::capture(path, use_cachestat) {
+            Ok(snapshot) => snapshots.push(snapshot),
+            Err(e) => {
+                warn!("failed to snapshot {}: {e:#}", path.display());
+                failed += 1;
+            }
+        }
+    }
+    ensure!(
+        failed == 0,
+        "failed to snapshot {failed} of {} files",
+        files.len()
+    );
+    Ok(snapshots)
+}
+
+/// Save a snapshot of `files` to `snapshot`.
+pub fn save(files: &[PathBuf], snapshot: &Path, use_cachestat: bool) -> Result<()> {
+    PageCacheSnapshot::save(&capture(files, use_cachestat)?, snapshot)
+}
+
+/// Re-warm the pages recorded in `snapshot` for the files in `files`.
+///
+/// Files that are not in the snapshot, and snapshot entries that match none of the files, are
+/// reported with a warning.
+pub fn restore(files: &[PathBuf], snapshot: &Path) -> Result<()> {
+    let files = files.iter().map(|f| canonical(f)).collect::<Vec<_>>();
+    let saved = PageCacheSnapshot::load(snapshot)?;
+    for path in &files {
+        if !saved.iter().any(|s| s.path == *path) {
+            warn!(
+                "{} is not in {}, not restoring it",
+                path.display(),
+                snapshot.display()
+            );
+        }
+    }
+    for saved in saved {
+        if files.contains(&saved.path) {
+            saved.restore(TouchMethod::Read)?;
+        } else {
+            warn!(
+                "{} in {} matches none of the files",
+                saved.path.display(),
+                snapshot.display()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Print the pages that were added, removed or dirtied since `snapshot` was saved.
+///
+/// Files that are not in the snapshot are compared against an empty snapshot.
+pub fn diff(files: &[PathBuf], snapshot: &Path, use_cachestat: bool) -> Result<()> {
+    let saved = PageCacheSnapshot::load(snapshot)?;
+    let show_path = files.len() > 1;
+
+    for current in capture(files, use_cachestat)? {
+        let diff = saved
+            .iter()
+            .find(|s| s.path == current.path)
+            .cloned()
+            .unwrap_or_default()
+            .diff(&current);
+
+        if show_path {
+            if diff.is_empty() {
+                continue;
+            }
+            println!("{}", current.path.display().to_string().bold());
+        }
+        for (label, pages) in [
+            ("Added", &diff.added),
+            ("Removed", &diff.removed),
+            ("Dirtied", &diff.dirtied),
+        ] {
+            println!(
+                "\t\t{:<9}{:<6} {}",
+                format!("{label}:"),
+                ranges_len(pages).to_string().bold(),
+                fmt_page_ranges(pages)
+            );
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,46 @@
+//! Show the data, unwritten extents and holes of a sparse file.
+use colored::Colorize;
+use ff::args::{fmt_page_ranges, ranges_len};
+use ff::pagemap::DataLayout;
+use std::ops::Range;
+
+/// Print the data, unwritten and hole pages of `layout`, and the share of the data that is
+/// cached. Nothing is printed for a file without holes or unwritten extents.
+pub fn print_layout(layout: &DataLayout, cached_ranges: &[Range<u64>], range_pages: u64) {
+    if !layout.is_sparse() {
+        return;
+    }
+
+    let nr_data = layout.nr_data();
+    let cached_data = layout.count_data(cached_ranges);
+    // the residency is a share of the data, which a range of holes does not have.
+    let residency = match nr_data {
+        0 => String::new(),
+        _ => format!(
+            ", {:.2}% of data",
+            cached_data as f64 * 100.0 / nr_data as f64
+        ),
+    };
+    println!(
+        "\t\tData Pages: {}/{} ({} cached{residency})",
+        nr_data.to_string().bold(),
+        range_pages.to_string().bold(),
+        cached_data.to_string().bold(),
+    );
+    print_runs("data", &layout.data);
+    print_runs("unwritten", &layout.unwritten);
+    print_runs("hole", &layout.holes);
+}
+
+/// Print a row of runs, unless there are none.
+fn print_runs(label: &str, runs: &[Range<u64>]) {
+    if runs.is_empty() {
+        return;
+    }
+    println!(
+        "\t\t  {:<10} {:>8}  {}",
+        label,
+        ranges_len(runs),
+        fmt_page_ranges(runs)
+    );
+}
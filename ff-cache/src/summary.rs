@@ -0,0 +1,226 @@
+//! Summarize the cached pages of many files, one row per file.
+use crate::report::{self, SummaryRow, Total, Totals};
+use crate::{Args, Format, dirty_pages, evict, page_range};
+use anyhow::{Context, Result};
+use colored::Colorize;
+use ff::args::ranges_len;
+use ff::pagemap::{PageMapExt, vm_page_size};
+use humansize::{BINARY, format_size};
+use log::warn;
+use std::cmp::min;
+use std::fs::OpenOptions;
+use std::path::{Path, PathBuf};
+
+/// The page cache usage of a single file.
+struct FileSummary {
+    path: PathBuf,
+    /// Number of pages in the inspected range.
+    pages: u64,
+    /// Size in bytes of the inspected range.
+    len: u64,
+    /// Cached pages, or evicted pages when evicting.
+    resident: u64,
+    /// Dirty and writeback pages, only known with cachestat(2). Dirty pages are also counted
+    /// through kpageflags with `--dirty`.
+    dirty: Option<u64>,
+    writeback: Option<u64>,
+}
+
+impl FileSummary {
+    fn resident_size(&self, vm_page_size: u64) -> u64 {
+        min(self.resident * vm_page_size, self.len)
+    }
+
+    fn totals(&self, evicting: bool, vm_page_size: u64) -> Totals {
+        resident_totals(
+            evicting,
+            self.pages,
+            self.len,
+            self.resident,
+            self.resident_size(vm_page_size),
+            self.dirty,
+            self.writeback,
+        )
+    }
+}
+
+/// Totals with `resident` as either the cached or the evicted pages.
+fn resident_totals(
+    evicting: bool,
+    pages: u64,
+    size: u64,
+    resident: u64,
+    resident_size: u64,
+    dirty: Option<u64>,
+    writeback: Option<u64>,
+) -> Totals {
+    Totals {
+        pages,
+        size,
+        cached: (!evicting).then_some(resident),
+        cached_size: (!evicting).then_some(resident_size),
+        evicted: evicting.then_some(resident),
+        evicted_size: evicting.then_some(resident_size),
+        dirty,
+        writeback,
+    }
+}
+
+fn summarize(
+    args: &Args,
+    path: &Path,
+    use_cachestat: bool,
+    vm_page_size: u64,
+) -> Result<FileSummary> {
+    let file = OpenOptions::new()
+        .read(true)
+        .open(path)
+        .context(format!("failed to open {}", path.display()))?;
+    let len = file.size()?;
+
+    let pages = page_range(args, len, vm_page_size)?;
+    let mut summary = FileSummary {
+        path: path.to_owned(),
+        pages: pages.end - pages.start,
+        len: min(pages.end * vm_page_size, len) - min(pages.start * vm_page_size, len),
+        resident: 0,
+        dirty: None,
+        writeback: None,
+    };
+
+    if pages.is_empty() {
+        if use_cachestat || args.dirty {
+            summary.dirty = Some(0);
+        }
+        if use_cachestat {
+            summary.writeback = Some(0);
+        }
+        return Ok(summary);
+    }
+
+    if args.evict || !args.evict_with.is_empty() {
+        summary.resident = evict(args, &file, pages.clone())?
+            .iter()
+            .map(|(_, report)| report.evicted)
+            .sum();
+    } else if use_cachestat {
+        let stat = file.cachestat(pages.start * vm_page_size..pages.end * vm_page_size)?;
+        summary.resident = stat.nr_cache;
+        summary.dirty = Some(stat.nr_dirty);
+        summary.writeback = Some(stat.nr_writeback);
+    } else {
+        summary.resident = file.cached_ranges(pages.clone())?.nr_pages()?;
+    }
+
+    // the dirty pages that are left, when cachestat(2) did not count them.
+    if args.dirty && summary.dirty.is_none() {
+        let dirty = dirty_pages(&file, pages, use_cachestat)?;
+        summary.dirty = Some(ranges_len(&dirty));
+    }
+
+    Ok(summary)
+}
+
+/// Print a row per file, sorted by the most resident bytes, followed by a total.
+///
+/// When evicting, the rows show the evicted pages instead of the cached pages. Files that
+/// cannot be inspected are skipped with a warning.
+pub fn show_files(args: &Args, files: &[PathBuf], use_cachestat: bool) -> Result<()> {
+    let vm_page_size = vm_page_size()?;
+    let formatter = BINARY.space_after_value(false);
+    let evicting = args.evict || !args.evict_with.is_empty();
+
+    let mut summaries = Vec::with_capacity(files.len());
+    for path in files {
+        match summarize(args, path, use_cachestat, vm_page_size) {
+            Ok(summary) => summaries.push(summary),
+            Err(e) => warn!("skipping {}: {e:#}", path.display()),
+        }
+    }
+    summaries.sort_by(|a, b| {
+        b.resident_size(vm_page_size)
+            .cmp(&a.resident_size(vm_page_size))
+            .then_with(|| a.path.cmp(&b.path))
+    });
+
+    if args.format != Format::Text {
+        let rows = summaries
+            .iter()
+            .map(|s| SummaryRow {
+                path: s.path.clone(),
+                totals: s.totals(evicting, vm_page_size),
+            })
+            .collect::<Vec<_>>();
+        let sum = |f: fn(&FileSummary) -> Option<u64>| summaries.iter().map(f).sum();
+        let total = Total {
+            files: rows.len(),
+            totals: resident_totals(
+                evicting,
+                summaries.iter().map(|s| s.pages).sum(),
+                summaries.iter().map(|s| s.len).sum(),
+                summaries.iter().map(|s| s.resident).sum(),
+                summaries
+                    .iter()
+                    .map(|s| s.resident_size(vm_page_size))
+                    .sum(),
+                sum(|s| s.dirty),
+                sum(|s| s.writeback),
+            ),
+        };
+        return report::print_summary(&rows, &total, args.format);
+    }
+
+    let optional = |n: Option<u64>| n.map_or("-".to_owned(), |n| n.to_string());
+    let row = |resident: u64, pages: u64, size: u64, len: u64, dirty, writeback, name: &str| {
+        println!(
+            "{:>16} {:>20} {:>8} {:>10}  {name}",
+            format!("{resident}/{pages}"),
+            format!(
+                "{}/{}",
+                format_size(size, formatter),
+                format_size(len, formatter)
+            ),
+            optional(dirty),
+            optional(writeback),
+        );
+    };
+
+    println!(
+        "{}",
+        format!(
+            "{:>16} {:>20} {:>8} {:>10}  FILE",
+            if evicting { "EVICTED" } else { "CACHED" },
+            "SIZE",
+            "DIRTY",
+            "WRITEBACK"
+        )
+        .bold()
+    );
+    for s in &summaries {
+        row(
+            s.resident,
+            s.pages,
+            s.resident_size(vm_page_size),
+            s.len,
+            s.dirty,
+            s.writeback,
+            &s.path.to_string_lossy(),
+        );
+    }
+
+    let sum = |f: fn(&FileSummary) -> Option<u64>| summaries.iter().map(f).sum::<Option<u64>>();
+    row(
+        summaries.iter().map(|s| s.resident).sum(),
+        summaries.iter().map(|s| s.pages).sum(),
+        summaries
+            .iter()
+            .map(|s| s.resident_size(vm_page_size))
+            .sum(),
+        summaries.iter().map(|s| s.len).sum(),
+        sum(|s| s.dirty),
+        sum(|s| s.writeback),
+        &format!("total ({} files)", summaries.len()),
+    );
+
+    Ok(())
+}
@@ -0,0 +1,171 @@
+//! Per-page details shown with `--verbose`.
+use crate::report::Cgroup;
+use anyhow::Result;
+use colored::Colorize;
+use ff::KernelVersion;
+use ff::cgroup::{cgroup_inodes, memory_cgroup_mountpoint};
+use ff::pagemap::{
+    Folio, PageInfo, PageMapExt, get_kernel_page_cgroups, get_kernel_page_counts, group_folios,
+};
+use humansize::{BINARY, format_size};
+use log::debug;
+use std::collections::HashMap;
+use std::fs::File;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+/// A cached page, the folio it belongs to, and how its frame is mapped and charged.
+pub struct PageDetails {
+    pub info: PageInfo,
+    pub folio: Folio,
+    /// Number of times the frame is mapped, from `/proc/kpagecount`.
+    ///
+    /// The pages are mapped by ff-cache to read their page map entries, but they are unmapped
+    /// again before the count is read.
+    pub map_count: u64,
+    /// The memory cgroup the frame is charged to, from `/proc/kpagecgroup`.
+    pub cgroup: Option<Cgroup>,
+}
+
+/// Resolves the cgroup inodes of `/proc/kpagecgroup` to paths relative to the mountpoint of the
+/// memory controller.
+#[derive(Default)]
+struct CgroupNames {
+    root: PathBuf,
+    inodes: HashMap<u64, PathBuf>,
+}
+
+impl CgroupNames {
+    /// Walk the memory cgroup hierarchy, the paths are unknown if it is not mounted.
+    fn new() -> Self {
+        match memory_cgroup_mountpoint().and_then(|root| Ok((root, cgroup_inodes()?))) {
+            Ok((root, inodes)) => Self { root, inodes },
+            Err(e) => {
+                debug!("cgroup paths are unknown: {e:#}");
+                Self::default()
+            }
+        }
+    }
+
+    /// Returns the cgroup with inode `ino`, or `None` if the page is not charged to a cgroup.
+    fn get(&self, ino: u64) -> Option<Cgroup> {
+        (ino != 0).then(|| Cgroup {
+            inode: ino,
+            path: self.inodes.get(&ino).map(|path| {
+                // the root cgroup is `/`, like in /proc/PID/cgroup.
+                Path::new("/").join(path.strip_prefix(&self.root).unwrap_or(path))
+            }),
+        })
+    }
+}
+
+/// Collect the details of every page in `cached_ranges`, this requires root.
+///
+/// The cgroups are left out if `/proc/kpagecgroup` cannot be read, it only exists on kernels
+/// built with `CONFIG_MEMCG`.
+pub fn page_details(file: &File, cached_ranges: &[Range<u64>]) -> Result<Vec<PageDetails>> {
+    let cgroups = CgroupNames::new();
+    let mut details = Vec::new();
+
+    for range in cached_ranges.iter().cloned() {
+        let infos = file.pages_info(range)?.collect::<Vec<_>>();
+        let folios = group_folios(&infos)?;
+
+        let mut page_folios = Vec::with_capacity(infos.len());
+        for folio in &folios {
+            page_folios.extend(std::iter::repeat_n(folio, folio.nr_pages() as usize));
+        }
+        let pfns = infos
+            .iter()
+            .zip(&page_folios)
+            .map(|(info, folio)| folio.pfn + (info.page - folio.pages.start))
+            .collect::<Vec<_>>();
+
+        let counts = get_kernel_page_counts(&pfns)?;
+        let inodes = get_kernel_page_cgroups(&pfns)
+            .inspect_err(|e| debug!("unable to read the page cgroups: {e:#}"))
+            .ok();
+
+        for (i, (info, folio)) in infos.into_iter().zip(page_folios).enumerate() {
+            details.push(PageDetails {
+                info,
+                folio: folio.clone(),
+                map_count: counts[i],
+                cgroup: inodes.as_ref().and_then(|inodes| cgroups.get(inodes[i])),
+            });
+        }
+    }
+
+    Ok(details)
+}
+
+/// Print a block per page, and a header before the pages of each large folio.
+///
+/// The kernel page flags are named as the running kernel defines them, with a description of
+/// each flag.
+pub fn print_page_details(details: &[PageDetails], vm_page_size: u64) {
+    let kernel = KernelVersion::current();
+    for page in details {
+        // only large folios get a header, a single page is its own folio.
+        if page.folio.pages.start == page.info.page && page.folio.nr_pages() > 1 {
+            print_folio(&page.folio, vm_page_size);
+        }
+        println!("{} {}", "PAGE".bold(), page.info.page.to_string().cyan());
+
+        println!(
+            " {}\t  {}",
+            "pagemap (PageMapEntry)".dimmed(),
+            page.info.pagemap.to_string().dimmed()
+        );
+        let kflags = page.info.kflags.describe(&kernel);
+        println!(
+            " {}\t  {}",
+            "kflags (KPageFlags)".dimmed(),
+            kflags
+                .iter()
+                .map(|(name, _)| name.as_str())
+                .collect::<Vec<_>>()
+                .join(" | ")
+                .dimmed()
+        );
+        for (name, description) in &kflags {
+            println!("   {:<16}{}", name.dimmed(), description.dimmed());
+        }
+        println!(
+            " {}\t\t  {}",
+            "kpagecount".dimmed(),
+            page.map_count.to_string().dimmed()
+        );
+        let cgroup = match &page.cgroup {
+            Some(Cgroup {
+                inode,
+                path: Some(path),
+            }) => format!("{} (ino {inode})", path.display()),
+            Some(Cgroup { inode, path: None }) => format!("? (ino {inode})"),
+            None => "-".to_owned(),
+        };
+        println!(" {}\t\t  {}", "kpagecgroup".dimmed(), cgroup.dimmed());
+        println!();
+    }
+}
+
+/// Print the header of a large folio, its pages are printed right after it.
+fn print_folio(folio: &Folio, vm_page_size: u64) {
+    let formatter = BINARY.space_after_value(false);
+    let mut header = format!(
+        "{} {}-{}  order {}  {}  pfn {:#x}",
+        "FOLIO".bold(),
+        folio.pages.start.to_string().cyan(),
+        (folio.pages.end - 1).to_string().cyan(),
+        folio.order(),
+        format_size(folio.nr_pages() * vm_page_size, formatter),
+        folio.pfn,
+    );
+    if folio.is_thp() {
+        header.push_str("  THP");
+    }
+    if folio.partial {
+        header.push_str(&"  partial".yellow().to_string());
+    }
+    println!("{header}");
+}
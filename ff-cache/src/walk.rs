@@ -0,0 +1,165 @@
+//! Collect the files to inspect from the paths passed on the command line.
+use anyhow::{Context, Result};
+use glob::Pattern;
+use log::warn;
+use std::collections::HashSet;
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+#[derive(Debug, Default)]
+pub struct WalkOptions {
+    /// Follow symbolic links to files and directories.
+    pub follow_symlinks: bool,
+    /// Don't descend into directories on other filesystems.
+    pub one_file_system: bool,
+    /// Skip files and directories whose name or path matches any of these patterns.
+    pub exclude: Vec<Pattern>,
+}
+
+impl WalkOptions {
+    fn is_excluded(&self, path: &Path) -> bool {
+        let name = path.file_name().map(Path::new).unwrap_or(path);
+        self.exclude
+            .iter()
+            .any(|p| p.matches_path(name) || p.matches_path(path))
+    }
+}
+
+/// Parse the `--exclude` glob patterns.
+pub fn parse_patterns(patterns: &[String]) -> Result<Vec<Pattern>> {
+    patterns
+        .iter()
+        .map(|p| Pattern::new(p).context(format!("`{p}` is not a valid glob pattern")))
+        .collect()
+}
+
+/// Return all regular files in `paths`, walking directories recursively, and the block devices
+/// in `paths`.
+///
+/// Every file is returned once, even if it is reachable through multiple paths (e.g. hard
+/// links). Entries that cannot be read are skipped with a warning.
+pub fn collect_files<P: AsRef<Path>>(paths: &[P], options: &WalkOptions) -> Vec<PathBuf> {
+    let mut seen = HashSet::new();
+    let mut files = Vec::new();
+
+    for path in paths {
+        let walker = WalkDir::new(path)
+            .follow_links(options.follow_symlinks)
+            // paths passed on the command line are always followed.
+            .follow_root_links(true)
+            .same_file_system(options.one_file_system)
+            .sort_by_file_name()
+            .into_iter()
+            .filter_entry(|e| e.depth() == 0 || !options.is_excluded(e.path()));
+
+        for entry in walker {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    warn!("skipping: {e}");
+                    continue;
+                }
+            };
+
+            // block devices are only inspected when they are passed on the command line.
+            let is_device = entry.depth() == 0 && entry.file_type().is_block_device();
+            if !entry.file_type().is_file() && !is_device {
+                continue;
+            }
+
+            match entry.metadata() {
+                Ok(metadata) if !seen.insert((metadata.dev(), metadata.ino())) => continue,
+                Ok(_) => files.push(entry.into_path()),
+                Err(e) => warn!("skipping: {e}"),
+            }
+        }
+    }
+
+    files
+}
+
+#[cfg(test)]
+mod test {
+    use super::{WalkOptions, collect_files, parse_patterns};
+    use std::ops::Deref;
+    use std::path::{Path, PathBuf};
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    /// A directory under the target directory (not on tmpfs, like the test files of the other
+    /// crates), removed on drop.
+    struct TestDir(PathBuf);
+    impl TestDir {
+        fn new() -> Self {
+            let nanos = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_nanos();
+            // unit tests run from the crate directory, and CARGO_TARGET_TMPDIR is only set for
+            // integration tests.
+            let dir = Path::new("..").join(format!("target/test-walk-{nanos}"));
+            std::fs::create_dir_all(&dir).unwrap();
+            TestDir(dir.canonicalize().unwrap())
+        }
+    }
+    impl Deref for TestDir {
+        type Target = Path;
+
+        fn deref(&self) -> &Self::Target {
+            &self.0
+        }
+    }
+    impl Drop for TestDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn test_collect_files() {
+        let test_dir = TestDir::new();
+        let dir: &Path = &test_dir;
+        std::fs::create_dir_all(dir.join("sub/skip")).unwrap();
+        std::fs::write(dir.join("a.txt"), "a").unwrap();
+        std::fs::write(dir.join("b.log"), "b").unwrap();
+        std::fs::write(dir.join("sub/c.txt"), "c").unwrap();
+        std::fs::write(dir.join("sub/skip/d.txt"), "d").unwrap();
+        std::fs::hard_link(dir.join("a.txt"), dir.join("sub/a-link.txt")).unwrap();
+        std::os::unix::fs::symlink(dir.join("sub"), dir.join("sub-link")).unwrap();
+
+        let files = collect_files(&[dir], &WalkOptions::default());
+        let relative = |files: Vec<PathBuf>| {
+            files
+                .iter()
+                .map(|f| f.strip_prefix(dir).unwrap().to_string_lossy().into_owned())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            relative(files),
+            vec!["a.txt", "b.log", "sub/c.txt", "sub/skip/d.txt"]
+        );
+
+        let options = WalkOptions {
+            exclude: parse_patterns(&["*.log".into(), "skip".into()]).unwrap(),
+            ..Default::default()
+        };
+        let files = collect_files(&[dir], &options);
+        assert_eq!(relative(files), vec!["a.txt", "sub/c.txt"]);
+
+        // the files behind the symlink were already seen
+        let options = WalkOptions {
+            follow_symlinks: true,
+            ..Default::default()
+        };
+        let files = collect_files(&[dir.join("sub-link"), dir.to_owned()], &options);
+        assert_eq!(
+            relative(files),
+            vec![
+                "sub-link/a-link.txt",
+                "sub-link/c.txt",
+                "sub-link/skip/d.txt",
+                "b.log"
+            ]
+        );
+    }
+}
@@ -0,0 +1,120 @@
+//! Warm the page cache for benchmarks: touch pages, lock them and keep them locked.
+use crate::{Args, page_range};
+use anyhow::{Context, Result, ensure};
+use colored::Colorize;
+use ff::pagemap::{LockedPages, PageMapExt, TouchMethod, vm_page_size};
+use log::debug;
+use nix::sys::signal::{SigSet, Signal};
+use nix::unistd::{ForkResult, dup2_stderr, dup2_stdin, dup2_stdout, fork, pipe, setsid};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+fn open(path: &Path) -> Result<(File, u64)> {
+    let file = OpenOptions::new()
+        .read(true)
+        .open(path)
+        .context(format!("failed to open {}", path.display()))?;
+    let len = file.size()?;
+    Ok((file, len))
+}
+
+/// Bring the pages of every file into the page cache with `method`.
+///
+/// Like [`lock`], this stops at the first file that cannot be touched, so that a benchmark
+/// does not start with a cache that is only partly warm.
+pub fn touch(args: &Args, files: &[PathBuf], method: TouchMethod) -> Result<()> {
+    let vm_page_size = vm_page_size()?;
+    for path in files {
+        let (file, len) = open(path)?;
+        let pages = page_range(args, len, vm_page_size)?;
+        debug!("touching {} pages {pages:?} with {method}", path.display());
+        file.touch_range(pages, method)
+            .context(format!("failed to touch {}", path.display()))?;
+    }
+    Ok(())
+}
+
+/// Lock the pages of every file in memory, empty files are skipped.
+pub fn lock(args: &Args, files: &[PathBuf]) -> Result<Vec<LockedPages>> {
+    let vm_page_size = vm_page_size()?;
+    let mut locked = Vec::with_capacity(files.len());
+    for path in files {
+        let (file, len) = open(path)?;
+        let pages = page_range(args, len, vm_page_size)?;
+        if pages.is_empty() {
+            continue;
+        }
+        locked.push(
+            file.lock_range(pages)
+                .context(format!("failed to lock {}", path.display()))?,
+        );
+    }
+    Ok(locked)
+}
+
+/// Keep `locked` locked until SIGINT, SIGTERM or SIGHUP is received.
+///
+/// With `daemon`, a detached child keeps the pages locked instead, and this returns once the
+/// child has locked them.
+pub fn hold(locked: Vec<LockedPages>, daemon: bool) -> Result<()> {
+    let pages = locked.iter().map(LockedPages::pages).sum::<Result<u64>>()?;
+
+    // block the signals before forking so that the child cannot miss them.
+    let mut signals = SigSet::empty();
+    signals.add(Signal::SIGINT);
+    signals.add(Signal::SIGTERM);
+    signals.add(Signal::SIGHUP);
+    signals.thread_block().context("failed to block signals")?;
+
+    if daemon {
+        // the child writes a byte once the pages are locked, or exits without writing it.
+        let (ready_rx, ready_tx) = pipe().context("pipe(2) failed")?;
+        // SAFETY: this process is single threaded.
+        match unsafe { fork() }.context("fork(2) failed")? {
+            ForkResult::Parent { child } => {
+                drop(ready_tx);
+                let mut ready = [0u8; 1];
+                ensure!(
+                    File::from(ready_rx).read(&mut ready)? == 1,
+                    "the daemon (pid {child}) failed to lock the pages"
+                );
+                println!(
+                    "\t\tLocked {} pages in {}, send SIGTERM to unlock",
+                    pages.to_string().bold(),
+                    format!("pid {child}").bold()
+                );
+                return Ok(());
+            }
+            ForkResult::Child => {
+                drop(ready_rx);
+                setsid().context("setsid(2) failed")?;
+                // memory locks are not inherited, the mappings are.
+                for l in &locked {
+                    l.relock()?;
+                }
+                File::from(ready_tx)
+                    .write_all(&[1])
+                    .context("failed to signal that the pages are locked")?;
+                let null = OpenOptions::new()
+                    .read(true)
+                    .write(true)
+                    .open("/dev/null")
+                    .context("failed to open /dev/null")?;
+                dup2_stdin(&null)?;
+                dup2_stdout(&null)?;
+                dup2_stderr(&null)?;
+            }
+        }
+    } else {
+        println!(
+            "\t\tLocked {} pages, waiting for SIGINT or SIGTERM",
+            pages.to_string().bold()
+        );
+    }
+
+    let signal = signals.wait().context("failed to wait for a signal")?;
+    debug!("received {signal}, unlocking");
+    drop(locked);
+    Ok(())
+}
@@ -0,0 +1,143 @@
+//! Redraw a heat-map of the page states of the inspected files at an interval.
+use crate::{Args, page_range};
+use anyhow::{Context, Result};
+use colored::{ColoredString, Colorize};
+use ff::pagemap::{PageMapExt, PageState, vm_page_size};
+use log::warn;
+use std::cmp::{max, min};
+use std::fs::OpenOptions;
+use std::io::IsTerminal;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Number of cells in a row of the heat-map.
+const ROW_CELLS: usize = 64;
+/// Maximum number of cells per file, larger files are bucketed into this many cells.
+const MAX_CELLS: usize = ROW_CELLS * 4;
+
+/// Render a single cell for the `len` pages starting at `start`, from the runs of cached pages
+/// in `states` that overlap them, sorted by page.
+///
+/// The shade is the fraction of cached pages, and the color is the state of the page that is
+/// furthest from clean.
+fn cell(states: &[(Range<u64>, PageState)], start: u64, len: u64) -> ColoredString {
+    let end = start + len;
+    let mut cached = 0;
+    let mut state = PageState::Absent;
+    for (pages, s) in states {
+        if pages.start >= end {
+            break;
+        }
+        let overlap = min(pages.end, end).saturating_sub(max(pages.start, start));
+        if overlap > 0 {
+            cached += overlap;
+            state = max(state, *s);
+        }
+    }
+    if cached == 0 {
+        return "·".dimmed();
+    }
+
+    let shade = ["░", "▒", "▓", "█"][((cached * 4).div_ceil(len) - 1) as usize];
+    match state {
+        PageState::Writeback => shade.red(),
+        PageState::Dirty => shade.yellow(),
+        _ => shade.green(),
+    }
+}
+
+fn draw(args: &Args, path: &Path, use_cachestat: bool, vm_page_size: u64) -> Result<()> {
+    let file = OpenOptions::new()
+        .read(true)
+        .open(path)
+        .context(format!("failed to open {}", path.display()))?;
+    let len = file.size()?;
+
+    let pages = page_range(args, len, vm_page_size)?;
+    let states = file.page_states(pages.clone(), use_cachestat)?;
+    let count = |state: Option<PageState>| {
+        states
+            .iter()
+            .filter(|(_, s)| state.is_none_or(|state| *s == state))
+            .map(|(pages, _)| pages.end - pages.start)
+            .sum::<u64>()
+    };
+
+    let nr_pages = pages.end - pages.start;
+    println!("{}", path.display().to_string().bold());
+    println!(
+        "  cached {}/{}  dirty {}  writeback {}",
+        count(None).to_string().bold(),
+        nr_pages.to_string().bold(),
+        count(Some(PageState::Dirty)).to_string().bold(),
+        count(Some(PageState::Writeback)).to_string().bold(),
+    );
+
+    if nr_pages == 0 {
+        return Ok(());
+    }
+    let cell_pages = nr_pages.div_ceil(MAX_CELLS as u64);
+    // the runs are sorted, so each cell only looks at the runs from the first one it overlaps.
+    let mut first = 0;
+    let cells = (pages.start..pages.end)
+        .step_by(cell_pages as usize)
+        .map(|start| {
+            while states.get(first).is_some_and(|(run, _)| run.end <= start) {
+                first += 1;
+            }
+            let len = min(cell_pages, pages.end - start);
+            cell(&states[first..], start, len)
+        })
+        .collect::<Vec<_>>();
+    for row in cells.chunks(ROW_CELLS) {
+        print!("  ");
+        for cell in row {
+            print!("{cell}");
+        }
+        println!();
+    }
+    Ok(())
+}
+
+/// Redraw the heat-map of `files` every `interval`, `count` times or until interrupted.
+///
+/// Files that cannot be inspected are skipped with a warning. With `use_cachestat` the page
+/// states are read without faulting the pages in, so the redraws do not disturb them.
+pub fn watch(
+    args: &Args,
+    files: &[PathBuf],
+    interval: Duration,
+    count: Option<u64>,
+    use_cachestat: bool,
+) -> Result<()> {
+    let vm_page_size = vm_page_size()?;
+    let terminal = std::io::stdout().is_terminal();
+
+    let mut redraws = 0;
+    loop {
+        if terminal {
+            // clear the screen and move to the top left.
+            print!("\x1b[2J\x1b[H");
+        }
+        println!(
+            "Every {}: {} clean  {} dirty  {} writeback  {} absent\n",
+            humantime::format_duration(interval),
+            "█".green(),
+            "█".yellow(),
+            "█".red(),
+            "·".dimmed()
+        );
+        for path in files {
+            if let Err(e) = draw(args, path, use_cachestat, vm_page_size) {
+                warn!("skipping {}: {e:#}", path.display());
+            }
+        }
+
+        redraws += 1;
+        if count.is_some_and(|count| redraws >= count) {
+            return Ok(());
+        }
+        std::thread::sleep(interval);
+    }
+}
@@ -1,5 +1,7 @@
 use assert_cmd::{cargo, prelude::*};
-use ff::pagemap::PageMapExt;
+use ff::cgroup::ScratchCgroup;
+use ff::pagemap::{PageMapExt, cachestat_supported};
+use nix::fcntl::{FallocateFlags, fallocate};
 use predicates::prelude::*;
 use std::{
     fs::{File, OpenOptions},
@@ -12,6 +14,12 @@ use std::{
 
 pub struct TestFile(std::path::PathBuf, File);
 
+impl Default for TestFile {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl TestFile {
     /// Create a temp file in the target directory that will automatically get deleted
     /// on drop.
@@ -24,16 +32,17 @@ impl TestFile {
         let filename = Path::new(env!("CARGO_TARGET_TMPDIR")).join(format!("test-{}.txt", random));
         let file = OpenOptions::new()
             .create(true)
+            .truncate(true)
             .write(true)
             .read(true)
             .open(&filename)
             .unwrap();
 
-        Self(filename.into(), file)
+        Self(filename, file)
     }
 
     pub fn path(&self) -> &Path {
-        return self.0.as_path();
+        self.0.as_path()
     }
 }
 
@@ -41,7 +50,7 @@ impl Deref for TestFile {
     type Target = File;
 
     fn deref(&self) -> &Self::Target {
-        return &self.1;
+        &self.1
     }
 }
 
@@ -174,7 +183,7 @@ fn test_verbose_output_run_as_root() -> Result<(), Box<dyn std::error::Error>> {
         .success()
         .stdout(predicate::str::contains("1/1 20B/20B"))
         .stdout(predicate::str::contains(
-            "kflags (KPageFlags)\t  UPTODATE | LRU | MMAP | 0x800000000",
+            "kflags (KPageFlags)\t  UPTODATE | LRU | MMAP | PRIVATE",
         ))
         .stdout(predicate::str::contains(
             "pagemap (PageMapEntry)\t  SOFT_DIRTY | EXCL_MAP | FILE_PAGE_OR_SHARED_ANON | PRESENT",
@@ -193,7 +202,13 @@ macro_rules! ff_assert_cmd {
             let hex_addr = Regex::new(r"0x[0-9a-fA-F]+").unwrap();
             let ws = Regex::new(r"[ \t]+").unwrap();
 
+            // the cgroup depends on where the tests run.
+            let cgroup = Regex::new(r"kpagecgroup\s+.*").unwrap();
+            let lru = Regex::new(r"(?m)^\s+LRU\s+in one of the LRU lists\n").unwrap();
+
             let s = hex_addr.replace_all(s, "<ADDR>");
+            let s = cgroup.replace_all(&s, "kpagecgroup <CGROUP>");
+            let s = lru.replace_all(&s, "");
             let s = ws.replace_all(&s, " ");
             let s = s.into_owned();
             let s = s.replace("DIRTY | LRU | MMAP", "DIRTY | <MAYBE_LRU> | MMAP");
@@ -231,8 +246,14 @@ fn test_verbose_output_dirty_pages_run_as_root() -> Result<(), Box<dyn std::erro
         r#"
 Cached Pages: 1/1 20B/20B
 PAGE 0
- pagemap (PageMapEntry)  SOFT_DIRTY | EXCL_MAP | FILE_PAGE_OR_SHARED_ANON | PRESENT | <ADDR>
- kflags (KPageFlags)   UPTODATE | DIRTY | <MAYBE_LRU> | MMAP | <ADDR>
+ pagemap (PageMapEntry)  SOFT_DIRTY | EXCL_MAP | FILE_PAGE_OR_SHARED_ANON | PRESENT | pfn <ADDR>
+ kflags (KPageFlags)   UPTODATE | DIRTY | <MAYBE_LRU> | MMAP | PRIVATE
+   UPTODATE   has up-to-date data
+   DIRTY      written to, contains new data
+   MMAP       mapped in a page table
+   PRIVATE    has private data attached, e.g. buffer heads
+ kpagecount 0
+ kpagecgroup <CGROUP>
 "#
     );
 
@@ -262,8 +283,13 @@ fn test_verbose_output_clean_pages_run_as_root() -> Result<(), Box<dyn std::erro
         r#"
 Cached Pages: 1/1 20B/20B
 PAGE 0
- pagemap (PageMapEntry)  SOFT_DIRTY | EXCL_MAP | FILE_PAGE_OR_SHARED_ANON | PRESENT | <ADDR>
- kflags (KPageFlags)   UPTODATE | <MAYBE_LRU> | MMAP | <ADDR>
+ pagemap (PageMapEntry)  SOFT_DIRTY | EXCL_MAP | FILE_PAGE_OR_SHARED_ANON | PRESENT | pfn <ADDR>
+ kflags (KPageFlags)   UPTODATE | <MAYBE_LRU> | MMAP | PRIVATE
+   UPTODATE   has up-to-date data
+   MMAP       mapped in a page table
+   PRIVATE    has private data attached, e.g. buffer heads
+ kpagecount 0
+ kpagecgroup <CGROUP>
 "#
     );
 
@@ -277,7 +303,7 @@ fn test_show_dirty_pages_run_as_root() -> Result<(), Box<dyn std::error::Error>>
     let mut file = TestFile::new();
     let mut buf = [0u8];
     // fill 10 pages
-    file.write(&[0u8; 4096 * 10])?;
+    file.write_all(&[0u8; 4096 * 10])?;
     file.sync_all()?;
     file.evict_pages()?;
 
@@ -315,7 +341,7 @@ fn test_show_dirty_pages_run_as_root() -> Result<(), Box<dyn std::error::Error>>
 fn test_show_zero_dirty_pages_run_as_root() -> Result<(), Box<dyn std::error::Error>> {
     let mut cmd = Command::new(cargo::cargo_bin!());
     let mut file = TestFile::new();
-    file.write(&[0u8; 0x1000])?;
+    file.write_all(&[0u8; 0x1000])?;
     file.sync_all()?;
 
     let output = cmd
@@ -338,3 +364,948 @@ fn test_show_zero_dirty_pages_run_as_root() -> Result<(), Box<dyn std::error::Er
 
     Ok(())
 }
+
+#[test]
+fn test_show_dirty_pages_with_cachestat() -> Result<(), Box<dyn std::error::Error>> {
+    if !cachestat_supported() {
+        return Ok(());
+    }
+    let mut cmd = Command::new(cargo::cargo_bin!());
+    let mut file = TestFile::new();
+    let mut buf = [0u8];
+    // fill 10 pages
+    file.write_all(&[0u8; 4096 * 10])?;
+    file.sync_all()?;
+    file.evict_pages()?;
+
+    // dirty two pages
+    file.write_at("dirty".as_bytes(), 0x3000)?;
+    file.write_at("dirty".as_bytes(), 0x5000)?;
+    // cache one page
+    file.read_exact_at(&mut buf, 0x9000)?;
+
+    let output = cmd
+        .arg(file.path())
+        .arg("--dirty")
+        .arg("--stats")
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+
+    let stdout = String::from_utf8(output).unwrap();
+    ff_assert_cmd!(
+        stdout.as_str(),
+        r###"
+        Cached Pages: 3/10 12KiB/40KiB
+        Dirty: 2 Writeback: 0 Evicted: 0 Recently Evicted: 0
+        Dirty Pages: 2/10
+        3, 5
+        "###
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_stats_without_cachestat() -> Result<(), Box<dyn std::error::Error>> {
+    let mut cmd = Command::new(cargo::cargo_bin!());
+    let file = TestFile::new();
+
+    cmd.arg(file.path()).arg("--stats").arg("--no-cachestat");
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("--stats requires cachestat(2)"));
+
+    Ok(())
+}
+
+#[test]
+fn test_range() -> Result<(), Box<dyn std::error::Error>> {
+    let mut file = TestFile::new();
+    // fill 10 pages one at a time, the last one is partial
+    for _ in 0..9 {
+        file.write_all(&[0u8; 4096])?;
+    }
+    file.write_all(&[0u8; 100])?;
+    file.sync_all()?;
+
+    // keep pages 1, 2 and 9 cached
+    file.evict_range(0..1)?;
+    file.evict_range(3..9)?;
+
+    let mut cmd = Command::new(cargo::cargo_bin!());
+    cmd.arg(file.path()).args(["--range", "0-4"]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("2/5 8KiB/20KiB"));
+
+    // byte ranges are [start, end)
+    let mut cmd = Command::new(cargo::cargo_bin!());
+    cmd.arg(file.path()).args(["--range", "8K-1M"]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("2/8 8KiB/28.10KiB"));
+
+    let mut cmd = Command::new(cargo::cargo_bin!());
+    cmd.arg(file.path()).args(["--range", "5-"]);
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("missing range end"));
+
+    Ok(())
+}
+
+#[test]
+fn test_evict_range() -> Result<(), Box<dyn std::error::Error>> {
+    let mut file = TestFile::new();
+    // write one page at a time, so that the pages are not cached in one large folio.
+    for _ in 0..4 {
+        file.write_all(&[0u8; 4096])?;
+    }
+    file.sync_all()?;
+
+    let mut cmd = Command::new(cargo::cargo_bin!());
+    cmd.arg(file.path()).args(["-e", "--range", "1p-2p"]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Evicted 2/2 8KiB/8KiB"));
+
+    assert_eq!(file.cached_pages()?, vec![0, 3]);
+
+    Ok(())
+}
+
+#[test]
+fn test_evict_dirty_pages() -> Result<(), Box<dyn std::error::Error>> {
+    let mut file = TestFile::new();
+    file.write_all(&[0u8; 4096 * 3])?;
+
+    // MADV_COLD does not write dirty pages back, they survive it and are classified dirty
+    // (unless the flusher started writing them back in the meantime).
+    let mut cmd = Command::new(cargo::cargo_bin!());
+    cmd.arg(file.path()).args(["--evict-with", "cold"]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Evicted 0/3 0B/12KiB"))
+        .stdout(predicate::str::is_match(
+            r"Not Evicted: 3/3\n\s+(dirty|writeback)\s+0-2\n",
+        )?);
+
+    let mut cmd = Command::new(cargo::cargo_bin!());
+    cmd.arg(file.path()).args(["-e", "--sync-first"]);
+    cmd.assert()
+        .success()
+        .stdout(predicate::str::contains("Evicted 3/3 12KiB/12KiB"))
+        .stdout(predicate::str::contains("Not Evicted").not());
+
+    assert_eq!(file.cached_pages()?, Vec::<u64>::new());
+
+    Ok(())
+}
+
+#[test]
+fn test_sync_first_requires_evict() -> Result<(), Box<dyn std::error::Error>> {
+    let file = TestFile::new();
+    let mut cmd = Command::new(cargo::cargo_bin!());
+    cmd.arg(file.path()).arg("--sync-first");
+    cmd.assert().failure().stderr(predicate::str::contains(
+        "the following required arguments were not provided",
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn test_evict_with() -> Result<(), Box<dyn std::error::Error>> {
+    let mut file = TestFile::new();
+    // write one page at a time, so that the pages are not cached in one large folio.
+    for _ in 0..4 {
+        file.write_all(&[1u8; 4096])?;
+    }
+    file.sync_all()?;
+
+    let output = Command::new(cargo::cargo_bin!())
+        .arg(file.path())
+        .args(["--evict-with", "cold,pageout", "--range", "0-2"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+
+    let stdout = String::from_utf8(output).unwrap();
+    ff_assert_cmd!(
+        stdout.as_str(),
+        r###"
+        cold Evicted 0/3 0B/12KiB
+        pageout Evicted 3/3 12KiB/12KiB
+        "###
+    );
+    assert_eq!(file.cached_pages()?, vec![3]);
+
+    Ok(())
+}
+
+#[test]
+#[ignore]
+fn test_directory_dirty_pages_run_as_root() -> Result<(), Box<dyn std::error::Error>> {
+    let random: String = (0..12).map(|_| fastrand::alphanumeric()).collect();
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join(format!("test-dirty-{}", random));
+    std::fs::create_dir_all(&dir)?;
+    std::fs::write(dir.join("clean.txt"), [1u8; 2 * 4096])?;
+    File::open(dir.join("clean.txt"))?.sync_all()?;
+    std::fs::write(dir.join("dirty.txt"), [1u8; 3 * 4096])?;
+
+    // without cachestat(2) the dirty pages are counted through kpageflags.
+    let output = Command::new(cargo::cargo_bin!())
+        .arg(&dir)
+        .args(["--dirty", "--no-cachestat"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let stdout = String::from_utf8(output)?;
+    let row = |dirty: u64, name: &str| {
+        regex::Regex::new(&format!(r"\s{dirty}\s+-\s+\S*{name}"))
+            .unwrap()
+            .is_match(&stdout)
+    };
+    assert!(row(3, "dirty.txt"), "{stdout}");
+    assert!(row(0, "clean.txt"), "{stdout}");
+
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+#[ignore]
+fn test_evict_with_memcg_run_as_root() -> Result<(), Box<dyn std::error::Error>> {
+    let mut file = TestFile::new();
+    for _ in 0..4 {
+        file.write_all(&[1u8; 4096])?;
+    }
+    file.sync_all()?;
+    file.evict_pages()?;
+
+    // the pages read from the scratch cgroup are charged to it, and are reclaimed from it.
+    let mut scratch = ScratchCgroup::create(format!("ff-cache-test-{}", std::process::id()))?;
+    scratch.enter()?;
+    let mut buf = [0u8; 3 * 4096];
+    file.read_exact_at(&mut buf, 0)?;
+
+    let output = Command::new(cargo::cargo_bin!())
+        .arg(file.path())
+        .args(["--evict-with", "memcg", "--range", "0-2", "--cgroup"])
+        .arg(scratch.path())
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+
+    let stdout = String::from_utf8(output).unwrap();
+    ff_assert_cmd!(
+        stdout.as_str(),
+        r###"
+        memcg Evicted 3/3 12KiB/12KiB
+        "###
+    );
+    assert!(file.cached_pages()?.is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn test_evict_with_memcg_requires_cgroup() {
+    let file = TestFile::new();
+    Command::new(cargo::cargo_bin!())
+        .arg(file.path())
+        .args(["--evict-with", "fadvise,memcg"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "--evict-with memcg requires --cgroup",
+        ));
+}
+
+#[test]
+fn test_directory() -> Result<(), Box<dyn std::error::Error>> {
+    let random: String = (0..12).map(|_| fastrand::alphanumeric()).collect();
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join(format!("test-dir-{}", random));
+    std::fs::create_dir_all(dir.join("sub"))?;
+    std::fs::write(dir.join("small.txt"), [1u8; 4096])?;
+    std::fs::write(dir.join("sub/large.txt"), [1u8; 4 * 4096])?;
+    std::fs::write(dir.join("skipped.log"), [1u8; 4096])?;
+    for name in ["small.txt", "sub/large.txt"] {
+        let file = File::open(dir.join(name))?;
+        file.sync_all()?;
+        file.evict_pages()?;
+    }
+    // cache the first page of each file
+    let mut buf = [0u8; 1];
+    File::open(dir.join("sub/large.txt"))?.read_exact_at(&mut buf, 0)?;
+    File::open(dir.join("small.txt"))?.read_exact_at(&mut buf, 0)?;
+
+    let output = Command::new(cargo::cargo_bin!())
+        .arg(&dir)
+        .args(["--exclude", "*.log"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let stdout = String::from_utf8(output)?;
+    let rows = stdout.lines().skip(1).collect::<Vec<_>>();
+    assert_eq!(rows.len(), 3, "{stdout}");
+    assert!(rows[0].contains("sub/large.txt"), "{stdout}");
+    assert!(rows[1].contains("small.txt"), "{stdout}");
+    assert!(rows[2].contains("total (2 files)"), "{stdout}");
+    assert!(rows[2].contains("/5 "), "{stdout}");
+
+    // the same file passed twice is only shown once
+    Command::new(cargo::cargo_bin!())
+        .arg(dir.join("small.txt"))
+        .arg(dir.join("small.txt"))
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("total (1 files)"));
+
+    Command::new(cargo::cargo_bin!())
+        .arg(&dir)
+        .arg("-v")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "--verbose only supports a single file",
+        ));
+
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn test_touch() -> Result<(), Box<dyn std::error::Error>> {
+    let mut file = TestFile::new();
+    for _ in 0..4 {
+        file.write_all(&[1u8; 4096])?;
+    }
+    file.sync_all()?;
+    file.evict_pages()?;
+
+    Command::new(cargo::cargo_bin!())
+        .arg(file.path())
+        .args(["--touch", "--range", "0-1"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Cached Pages:"));
+    let cached = file.cached_pages()?;
+    assert!(cached.contains(&0) && cached.contains(&1), "{cached:?}");
+
+    file.evict_pages()?;
+    Command::new(cargo::cargo_bin!())
+        .arg(file.path())
+        .args(["--touch", "populate"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("4/4 16KiB/16KiB"));
+
+    Command::new(cargo::cargo_bin!())
+        .arg(file.path())
+        .args(["--touch", "--evict"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("cannot be used with"));
+
+    Ok(())
+}
+
+/// The pid of a `--daemon` child, it is killed on drop.
+struct Daemon(String);
+
+impl Drop for Daemon {
+    fn drop(&mut self) {
+        let _ = Command::new("kill").arg(&self.0).status();
+        // wait for the pages to be unlocked, a zombie has released its memory already.
+        for _ in 0..100 {
+            let exited =
+                std::fs::read_to_string(format!("/proc/{}/stat", self.0)).map_or(true, |stat| {
+                    stat.rsplit_once(')')
+                        .is_some_and(|(_, s)| s.starts_with(" Z"))
+                });
+            if exited {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+    }
+}
+
+#[test]
+fn test_lock_daemon() -> Result<(), Box<dyn std::error::Error>> {
+    let mut file = TestFile::new();
+    for _ in 0..4 {
+        file.write_all(&[1u8; 4096])?;
+    }
+    file.sync_all()?;
+    file.evict_pages()?;
+
+    let output = Command::new(cargo::cargo_bin!())
+        .arg(file.path())
+        .args(["--lock", "--daemon"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let stdout = String::from_utf8(output)?;
+    assert!(stdout.contains("4/4 16KiB/16KiB"), "{stdout}");
+    let daemon = Daemon(
+        regex::Regex::new(r"pid (\d+)")?
+            .captures(&stdout)
+            .expect("the daemon pid is printed")[1]
+            .to_owned(),
+    );
+
+    // ff-cache only exits once the daemon has locked the pages.
+    file.evict_pages()?;
+    assert_eq!(file.cached_pages()?, vec![0, 1, 2, 3]);
+
+    drop(daemon);
+    file.evict_pages()?;
+    assert!(file.cached_pages()?.is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn test_snapshot_save_diff_restore() -> Result<(), Box<dyn std::error::Error>> {
+    let mut file = TestFile::new();
+    // write one page at a time, so that the pages are not cached in one large folio.
+    for _ in 0..4 {
+        file.write_all(&[1u8; 4096])?;
+    }
+    file.sync_all()?;
+    file.evict_range(2..4)?;
+    let snapshot = Path::new(env!("CARGO_TARGET_TMPDIR")).join(format!(
+        "{}.snapshot",
+        file.path().file_name().unwrap().to_string_lossy()
+    ));
+
+    Command::new(cargo::cargo_bin!())
+        .arg(file.path())
+        .arg("--save")
+        .arg(&snapshot)
+        .assert()
+        .success();
+    assert!(std::fs::read_to_string(&snapshot)?.contains("cached 0-1\n"));
+
+    file.evict_range(0..1)?;
+    file.write_at(&[2u8; 4096], 3 * 4096)?;
+    let output = Command::new(cargo::cargo_bin!())
+        .arg(file.path())
+        .arg("--diff")
+        .arg(&snapshot)
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let stdout = String::from_utf8(output)?;
+    assert!(stdout.contains("Added:   1      3"), "{stdout}");
+    assert!(stdout.contains("Removed: 1      0"), "{stdout}");
+
+    file.sync_all()?;
+    Command::new(cargo::cargo_bin!())
+        .arg(file.path())
+        .arg("--restore")
+        .arg(&snapshot)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("2/4 8KiB/16KiB"));
+    assert_eq!(file.cached_pages()?, vec![0, 1]);
+
+    // a file that is not in the snapshot, and an entry that matches nothing, are reported.
+    let other = TestFile::new();
+    Command::new(cargo::cargo_bin!())
+        .arg(other.path())
+        .arg("--restore")
+        .arg(&snapshot)
+        .assert()
+        .success()
+        .stderr(predicate::str::contains(format!(
+            "{} is not in",
+            other.path().canonicalize()?.display()
+        )))
+        .stderr(predicate::str::contains(format!(
+            "{} in {} matches none of the files",
+            file.path().canonicalize()?.display(),
+            snapshot.display()
+        )));
+
+    std::fs::remove_file(&snapshot)?;
+    Ok(())
+}
+
+#[test]
+fn test_watch() -> Result<(), Box<dyn std::error::Error>> {
+    let mut file = TestFile::new();
+    // write one page at a time, so that the pages are not cached in one large folio.
+    for _ in 0..4 {
+        file.write_all(&[1u8; 4096])?;
+    }
+    file.sync_all()?;
+    file.evict_range(0..1)?;
+    file.write_at(&[2u8; 4096], 2 * 4096)?;
+
+    let mut cmd = Command::new(cargo::cargo_bin!());
+    cmd.arg(file.path())
+        .args(["--watch", "10ms", "--count", "2"]);
+    if !cachestat_supported() {
+        cmd.assert().failure();
+        return Ok(());
+    }
+
+    let output = cmd.assert().success().get_output().stdout.clone();
+    let stdout = String::from_utf8(output)?;
+    assert_eq!(stdout.matches("Every 10ms").count(), 2, "{stdout}");
+    assert!(
+        stdout.contains("cached 3/4  dirty 1  writeback 0"),
+        "{stdout}"
+    );
+    assert!(stdout.contains("·███"), "{stdout}");
+
+    Ok(())
+}
+
+#[test]
+fn test_format_json() -> Result<(), Box<dyn std::error::Error>> {
+    let mut file = TestFile::new();
+    // write one page at a time, so that the pages are not cached in one large folio.
+    for _ in 0..4 {
+        file.write_all(&[1u8; 4096])?;
+    }
+    file.sync_all()?;
+    file.evict_range(1..2)?;
+
+    let output = Command::new(cargo::cargo_bin!())
+        .arg(file.path())
+        .args(["--format", "json", "--no-cachestat"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let report: serde_json::Value = serde_json::from_slice(&output)?;
+    assert_eq!(report["pages"], 4, "{report}");
+    assert_eq!(report["size"], 16384, "{report}");
+    assert_eq!(report["cached"], 3, "{report}");
+    assert_eq!(report["cached_size"], 12288, "{report}");
+    assert_eq!(
+        report["cached_pages"],
+        serde_json::json!([{"start": 0, "end": 1}, {"start": 2, "end": 4}]),
+        "{report}"
+    );
+
+    // a summary line per file and a total line
+    let output = Command::new(cargo::cargo_bin!())
+        .arg(file.path())
+        .arg(file.path())
+        .args(["--format", "ndjson", "--no-cachestat"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let lines = String::from_utf8(output)?
+        .lines()
+        .map(serde_json::from_str)
+        .collect::<Result<Vec<serde_json::Value>, _>>()?;
+    assert_eq!(lines.len(), 2, "{lines:?}");
+    assert_eq!(lines[0]["type"], "summary", "{lines:?}");
+    assert_eq!(lines[0]["cached"], 3, "{lines:?}");
+    assert_eq!(lines[1]["type"], "total", "{lines:?}");
+    assert_eq!(lines[1]["files"], 1, "{lines:?}");
+
+    // the cached pages are the ones before eviction, like the totals.
+    let output = Command::new(cargo::cargo_bin!())
+        .arg(file.path())
+        .args(["--format", "json", "-e"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let report: serde_json::Value = serde_json::from_slice(&output)?;
+    let cached_pages = report["cached_pages"]
+        .as_array()
+        .expect("cached_pages is an array")
+        .iter()
+        .map(|range| range["end"].as_u64().unwrap() - range["start"].as_u64().unwrap())
+        .sum::<u64>();
+    assert_eq!(report["cached"], 3, "{report}");
+    assert_eq!(report["cached"], cached_pages, "{report}");
+    assert!(file.cached_pages()?.is_empty());
+
+    Command::new(cargo::cargo_bin!())
+        .arg(file.path())
+        .args(["--format", "json", "--watch", "1s"])
+        .assert()
+        .failure();
+
+    Ok(())
+}
+
+#[test]
+fn test_pid() -> Result<(), Box<dyn std::error::Error>> {
+    let mut file = TestFile::new();
+    for _ in 0..4 {
+        file.write_all(&[1u8; 4096])?;
+    }
+    file.sync_all()?;
+    file.evict_pages()?;
+
+    // map (and fault in) pages 1 and 2 in this process
+    let locked = file.lock_range(1..3)?;
+
+    let output = Command::new(cargo::cargo_bin!())
+        .args(["--pid", &std::process::id().to_string(), "--format", "json"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let report: serde_json::Value = serde_json::from_slice(&output)?;
+    let files = report["files"].as_array().expect("files is an array");
+    let mapped = files
+        .iter()
+        .find(|f| f["path"] == file.path().to_str().unwrap())
+        .expect("the test file is mapped");
+    assert_eq!(mapped["cached"], 2, "{mapped}");
+    assert_eq!(mapped["mappings"].as_array().unwrap().len(), 1, "{mapped}");
+    assert_eq!(
+        mapped["mappings"][0]["range"],
+        serde_json::json!({"start": 1, "end": 3}),
+        "{mapped}"
+    );
+    assert_eq!(mapped["mappings"][0]["mapped"], 2, "{mapped}");
+
+    drop(locked);
+    Ok(())
+}
+
+#[test]
+#[ignore]
+fn test_folios_run_as_root() -> Result<(), Box<dyn std::error::Error>> {
+    let mut file = TestFile::new();
+    file.write_all(&[1u8; 4096 * 16])?;
+    file.sync_all()?;
+
+    let output = Command::new(cargo::cargo_bin!())
+        .arg(file.path())
+        .args(["--folios", "--format", "json"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let report: serde_json::Value = serde_json::from_slice(&output)?;
+    // whatever the folio sizes are, they cover all the cached pages
+    let pages = report["folios"]["orders"]
+        .as_array()
+        .expect("orders is an array")
+        .iter()
+        .map(|o| o["count"].as_u64().unwrap() << o["order"].as_u64().unwrap())
+        .sum::<u64>();
+    assert_eq!(pages, 16, "{report}");
+    assert_eq!(report["folios"]["partial"], 0, "{report}");
+
+    Command::new(cargo::cargo_bin!())
+        .arg(file.path())
+        .arg("--folios")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Folios:"));
+
+    Ok(())
+}
+
+#[test]
+fn test_numa() -> Result<(), Box<dyn std::error::Error>> {
+    let mut file = TestFile::new();
+    // write one page at a time, so that the pages are not cached in one large folio.
+    for _ in 0..4 {
+        file.write_all(&[1u8; 4096])?;
+    }
+    file.sync_all()?;
+    file.evict_range(1..2)?;
+
+    let output = Command::new(cargo::cargo_bin!())
+        .arg(file.path())
+        .args(["--numa", "--format", "json"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let report: serde_json::Value = serde_json::from_slice(&output)?;
+    let nodes = report["numa"].as_array().expect("a list of nodes");
+    let pages = nodes
+        .iter()
+        .map(|n| n["pages"].as_u64().unwrap())
+        .sum::<u64>();
+    assert_eq!(pages, 3, "{report}");
+
+    // everything is on node 0 of a single node machine.
+    if std::fs::read_to_string("/sys/devices/system/node/online")?.trim() == "0" {
+        assert_eq!(report["numa"], serde_json::json!([{"node": 0, "pages": 3}]));
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_sparse_file() -> Result<(), Box<dyn std::error::Error>> {
+    let file = TestFile::new();
+    // a hole, an unwritten extent, two pages of data and a hole at the end.
+    fallocate(&*file, FallocateFlags::empty(), 0x2000, 0x2000)?;
+    file.write_all_at(&[1u8; 4096], 0x4000)?;
+    file.write_all_at(&[1u8; 4096], 0x5000)?;
+    file.set_len(0x8000)?;
+    file.sync_all()?;
+    file.evict_range(5..6)?;
+
+    Command::new(cargo::cargo_bin!())
+        .arg(file.path())
+        .arg("--layout")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Cached Pages: 1/8 4KiB/32KiB"))
+        .stdout(predicate::str::contains(
+            "Data Pages: 2/8 (1 cached, 50.00% of data)",
+        ))
+        .stdout(predicate::str::is_match(r"data\s+2  4-5")?)
+        .stdout(predicate::str::is_match(r"unwritten\s+2  2-3")?)
+        .stdout(predicate::str::is_match(r"hole\s+4  0-1, 6-7")?);
+
+    let output = Command::new(cargo::cargo_bin!())
+        .arg(file.path())
+        .args(["--layout", "--format", "json"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let report: serde_json::Value = serde_json::from_slice(&output)?;
+    assert_eq!(
+        report["layout"],
+        serde_json::json!({
+            "data_pages": 2,
+            "cached_data": 1,
+            "data": [{"start": 4, "end": 6}],
+            "unwritten": [{"start": 2, "end": 4}],
+            "holes": [{"start": 0, "end": 2}, {"start": 6, "end": 8}],
+        })
+    );
+
+    // with --range, only the pages in the range.
+    Command::new(cargo::cargo_bin!())
+        .arg(file.path())
+        .args(["--range", "3-5"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "Data Pages: 2/3 (1 cached, 50.00% of data)",
+        ))
+        .stdout(predicate::str::is_match(r"unwritten\s+1  3")?)
+        .stdout(predicate::str::contains("hole").not());
+
+    // the layout is only shown on request.
+    Command::new(cargo::cargo_bin!())
+        .arg(file.path())
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Data Pages").not());
+
+    // a file without holes has no layout.
+    let mut dense = TestFile::new();
+    dense.write_all(&[1u8; 4096])?;
+    Command::new(cargo::cargo_bin!())
+        .arg(dense.path())
+        .arg("--layout")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Data Pages").not());
+
+    Ok(())
+}
+
+#[test]
+fn test_physical() -> Result<(), Box<dyn std::error::Error>> {
+    let file = TestFile::new();
+    // two pages of data, a hole and an unwritten extent.
+    file.write_all_at(&[1u8; 4096], 0)?;
+    file.write_all_at(&[1u8; 4096], 0x1000)?;
+    fallocate(&*file, FallocateFlags::empty(), 0x3000, 0x1000)?;
+    file.sync_all()?;
+    // reading the hole and the unwritten extent caches them too.
+    file.read_exact_at(&mut [0u8; 0x4000], 0)?;
+
+    let output = Command::new(cargo::cargo_bin!())
+        .arg(file.path())
+        .args(["--physical", "--format", "json"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let report: serde_json::Value = serde_json::from_slice(&output)?;
+    let runs = report["physical"].as_array().expect("a list of runs");
+
+    // the data may be split over several extents, the runs cover the pages in order.
+    let mut next = 0;
+    for run in runs {
+        assert_eq!(run["pages"]["start"], next, "{report}");
+        next = run["pages"]["end"].as_u64().unwrap();
+    }
+    assert_eq!(next, 4, "{report}");
+    let run_of = |page: u64| {
+        runs.iter()
+            .find(|run| run["pages"]["end"].as_u64().unwrap() > page)
+            .unwrap()
+    };
+    for page in 0..2 {
+        assert!(run_of(page)["sector"].is_u64(), "{report}");
+        assert_eq!(run_of(page)["hole"], false);
+    }
+    assert_eq!(
+        run_of(2),
+        &serde_json::json!({"pages": {"start": 2, "end": 3}, "hole": true})
+    );
+    assert_eq!(
+        run_of(3)["pages"],
+        serde_json::json!({"start": 3, "end": 4})
+    );
+    assert_eq!(run_of(3)["flags"], serde_json::json!(["UNWRITTEN"]));
+
+    Command::new(cargo::cargo_bin!())
+        .arg(file.path())
+        .arg("--physical")
+        .assert()
+        .success()
+        .stdout(predicate::str::is_match(r"pages 0\S*\s+sector \d+")?)
+        .stdout(predicate::str::is_match(r"pages 2\s+-\s+4KiB  hole")?)
+        .stdout(predicate::str::is_match(
+            r"pages 3\s+sector \d+\s+4KiB  UNWRITTEN",
+        )?);
+
+    Ok(())
+}
+
+/// An ext4 filesystem on a loop device, detached and removed on drop.
+struct LoopFs {
+    image: TestFile,
+    device: String,
+    mountpoint: std::path::PathBuf,
+}
+
+impl LoopFs {
+    fn new() -> Result<Self, Box<dyn std::error::Error>> {
+        let image = TestFile::new();
+        image.set_len(16 << 20)?;
+        let output = Command::new("losetup")
+            .args(["--find", "--show"])
+            .arg(image.path())
+            .output()?;
+        assert!(output.status.success(), "{output:?}");
+        let device = String::from_utf8(output.stdout)?.trim().to_owned();
+        let mountpoint = image.path().with_extension("mnt");
+        let fs = Self {
+            image,
+            device,
+            mountpoint,
+        };
+
+        std::fs::create_dir(&fs.mountpoint)?;
+        Command::new("mkfs.ext4")
+            .args(["-q", "-F", "-b", "4096", &fs.device])
+            .assert()
+            .success();
+        Command::new("mount")
+            .arg(&fs.device)
+            .arg(&fs.mountpoint)
+            .assert()
+            .success();
+        Ok(fs)
+    }
+}
+
+impl Drop for LoopFs {
+    fn drop(&mut self) {
+        let _ = Command::new("umount").arg(&self.mountpoint).status();
+        let _ = Command::new("losetup").args(["-d", &self.device]).status();
+        let _ = std::fs::remove_dir(&self.mountpoint);
+        let _ = std::fs::remove_file(self.image.path());
+    }
+}
+
+#[test]
+#[ignore]
+fn test_block_device_run_as_root() -> Result<(), Box<dyn std::error::Error>> {
+    let fs = LoopFs::new()?;
+    let mut file = File::create_new(fs.mountpoint.join("data"))?;
+    file.write_all(&[1u8; 0x4000])?;
+    file.sync_all()?;
+
+    // read the data of the file through the device, into the page cache of the device.
+    let extents = File::open(fs.mountpoint.join("data"))?.physical_extents(0..4)?;
+    let physical = extents[0].physical.expect("the data was written back");
+    let device = File::open(&fs.device)?;
+    device.read_exact_at(&mut [0u8; 0x4000], physical)?;
+
+    let start = physical / 4096;
+    let range = format!("{start}-{}", start + 3);
+    let output = Command::new(cargo::cargo_bin!())
+        .arg(&fs.device)
+        .args(["--range", &range, "--owner"])
+        .arg(&fs.mountpoint)
+        .args(["--format", "json"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    let report: serde_json::Value = serde_json::from_slice(&output)?;
+    // the device has the size of the image, not the size of its inode.
+    assert_eq!(
+        report["range"],
+        serde_json::json!({"start": start, "end": start + 4})
+    );
+    assert_eq!(report["cached"], 4, "{report}");
+    assert_eq!(
+        report["owners"]["files"][0]["path"],
+        fs.mountpoint.join("data").to_str().unwrap()
+    );
+    assert_eq!(
+        report["owners"]["files"][0]["pages"],
+        serde_json::json!([{"start": start, "end": start + 4}])
+    );
+
+    Command::new(cargo::cargo_bin!())
+        .arg(&fs.device)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("/4096 ").and(predicate::str::contains("/16MiB")));
+
+    // --owner only makes sense for a block device.
+    Command::new(cargo::cargo_bin!())
+        .arg(fs.mountpoint.join("data"))
+        .args(["--owner", "/"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("--owner requires a block device"));
+
+    Ok(())
+}
@@ -190,19 +190,18 @@ fn main() -> Result<()> {
         file
     };
 
-    for i in 0..args.pages {
-        println!("{} {}", "PAGE".bold(), i.to_string().cyan());
-        let (pagemap, kflags) = file.page_info(i)?;
+    for info in file.pages_info(0..args.pages)? {
+        println!("{} {}", "PAGE".bold(), info.page.to_string().cyan());
 
         println!(
             " {}\t  {}",
             "pagemap (PageMapEntry)".dimmed(),
-            pagemap.to_string().dimmed()
+            info.pagemap.to_string().dimmed()
         );
         println!(
             " {}\t  {}",
             "kflags (KPageFlags)".dimmed(),
-            kflags.to_string().dimmed()
+            info.kflags.to_string().dimmed()
         );
         println!();
     }
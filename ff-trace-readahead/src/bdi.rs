@@ -0,0 +1,97 @@
+//! The readahead setting of the backing device of a file.
+use anyhow::{Context, Result, ensure};
+use nix::sys::stat::{major, minor};
+use std::fs::File;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+/// The `read_ahead_kb` setting of the backing device (bdi) of a file, the value it had when it
+/// was found is restored on drop.
+#[derive(Debug)]
+pub struct ReadAheadKb {
+    /// The `major:minor` number of the device the file is on.
+    pub device: String,
+    path: PathBuf,
+    original: u64,
+}
+
+impl ReadAheadKb {
+    /// Find the backing device of `file` in sysfs.
+    pub fn of(file: &File) -> Result<Self> {
+        let dev = file.metadata()?.dev();
+        let device = format!("{}:{}", major(dev), minor(dev));
+        let path = bdi_dir(&device)?.join("read_ahead_kb");
+        let original = read_kb(&path)?;
+        Ok(Self {
+            device,
+            path,
+            original,
+        })
+    }
+
+    /// Returns the current value.
+    pub fn get(&self) -> Result<u64> {
+        read_kb(&self.path)
+    }
+
+    /// Returns the value it had when it was found.
+    pub fn original(&self) -> u64 {
+        self.original
+    }
+
+    /// Returns a shell command that restores the original value, for when this process is
+    /// killed before it can restore it.
+    pub fn restore_command(&self) -> String {
+        format!("echo {} > {}", self.original, self.path.display())
+    }
+
+    /// Set `read_ahead_kb` (requires root). Files opened afterwards start with this window,
+    /// files that are already open keep the one they were opened with.
+    pub fn set(&self, kb: u64) -> Result<()> {
+        std::fs::write(&self.path, format!("{kb}\n"))
+            .context(format!("failed to write {}", self.path.display()))
+    }
+}
+
+impl Drop for ReadAheadKb {
+    fn drop(&mut self) {
+        if self.get().is_ok_and(|kb| kb == self.original) {
+            return;
+        }
+        if let Err(e) = self.set(self.original) {
+            log::warn!(
+                "failed to restore read_ahead_kb to {}: {e:#}",
+                self.original
+            );
+        }
+    }
+}
+
+/// Returns the sysfs directory of the backing device of the device `major:minor`.
+fn bdi_dir(device: &str) -> Result<PathBuf> {
+    if let Ok(mut dir) = Path::new("/sys/dev/block").join(device).canonicalize() {
+        // a partition uses the queue, and the bdi, of its disk.
+        if dir.join("partition").exists() {
+            dir.pop();
+        }
+        if dir.join("bdi").exists() {
+            return Ok(dir.join("bdi"));
+        }
+    }
+
+    // filesystems without a block device (e.g. NFS or FUSE) register a bdi named after their
+    // anonymous device number.
+    let bdi = Path::new("/sys/class/bdi").join(device);
+    ensure!(
+        bdi.exists(),
+        "no backing device found for device {device}, is the file on tmpfs?"
+    );
+    Ok(bdi)
+}
+
+fn read_kb(path: &Path) -> Result<u64> {
+    let kb = std::fs::read_to_string(path).context(format!("failed to read {}", path.display()))?;
+    kb.trim()
+        .parse()
+        .context(format!("unexpected {}: {kb:?}", path.display()))
+}
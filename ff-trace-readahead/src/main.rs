@@ -0,0 +1,248 @@
+//! Trace readahead: evict a file, access it with a pattern, and see which pages each access
+//! brings into the page cache, under each access hint and `read_ahead_kb` setting.
+mod bdi;
+mod pattern;
+
+use anyhow::{Context, Result, ensure};
+use bdi::ReadAheadKb;
+use clap::Parser;
+use colored::Colorize;
+use ff::pagemap::{PageMapExt, vm_page_size};
+use humansize::{BINARY, format_size};
+use log::debug;
+use pattern::{Accesses, Hint, Pattern, Run, run};
+use serde::Serialize;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+#[derive(Parser, Debug)]
+#[command(version, about, long_about = None)]
+/// Trace how the kernel reads ahead a file under different access patterns, hints and
+/// `read_ahead_kb` settings.
+///
+/// Before each run the file is evicted and opened again, then it is accessed step by step. The
+/// pages that became resident after every access show how the readahead window grows.
+struct Args {
+    /// File to trace, it is created with `--pages` pages of data if it does not exist.
+    file: PathBuf,
+    /// Number of pages of the file to create.
+    #[arg(long, default_value_t = 16384)]
+    pages: u64,
+    /// Access patterns to run.
+    #[arg(
+        short,
+        long,
+        value_enum,
+        value_delimiter = ',',
+        default_values_t = [Pattern::Single, Pattern::Sequential, Pattern::Strided, Pattern::Mmap]
+    )]
+    pattern: Vec<Pattern>,
+    /// Hints to give with posix_fadvise(2), and madvise(2) for mmap, before the accesses.
+    #[arg(
+        long,
+        value_enum,
+        value_delimiter = ',',
+        default_values_t = [Hint::Normal, Hint::Sequential, Hint::Random, Hint::Noreuse]
+    )]
+    hint: Vec<Hint>,
+    /// Settings of `read_ahead_kb` of the backing device to run with (requires root). By default
+    /// the current setting is used. It is restored afterwards, and a command to restore it is
+    /// printed in case the run is interrupted.
+    #[arg(long, value_delimiter = ',')]
+    read_ahead_kb: Vec<u64>,
+    /// Number of accesses of each pattern.
+    #[arg(short, long, default_value_t = 16)]
+    steps: u64,
+    /// Pages read, or faulted in, by each access.
+    #[arg(long, default_value_t = 1)]
+    access_pages: u64,
+    /// Pages from the start of a strided access to the start of the next one.
+    #[arg(long, default_value_t = 8)]
+    stride: u64,
+    /// First page to access.
+    #[arg(long, default_value_t = 0)]
+    offset: u64,
+    /// Show the pages read in by every access.
+    #[arg(short, long)]
+    verbose: bool,
+    /// Output format.
+    #[arg(long, value_enum, default_value_t = Format::Text)]
+    format: Format,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Format {
+    /// colored text
+    Text,
+    /// a single JSON document
+    Json,
+}
+
+/// All the runs over a file, for `--format json`.
+#[derive(Serialize, Debug)]
+struct Report<'a> {
+    path: &'a Path,
+    pages: u64,
+    size: u64,
+    /// The `major:minor` number of the device the file is on, if its bdi was found.
+    device: Option<&'a str>,
+    runs: &'a [Run],
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    env_logger::init();
+
+    ensure!(args.steps > 0, "--steps must be at least 1");
+    ensure!(args.access_pages > 0, "--access-pages must be at least 1");
+    ensure!(args.stride > 0, "--stride must be at least 1");
+
+    let formatter = BINARY.space_after_value(false);
+    let vm_page_size = vm_page_size()?;
+    if !args.file.exists() {
+        create(&args.file, args.pages * vm_page_size)?;
+        if args.format == Format::Text {
+            println!(
+                "=> created {} with {} pages",
+                args.file.display(),
+                args.pages
+            );
+        }
+    }
+
+    let file = File::open(&args.file).context(format!("failed to open {}", args.file.display()))?;
+    let (pages, size) = (file.vm_pages_count()?, file.size()?);
+    let read_ahead = match ReadAheadKb::of(&file) {
+        Ok(read_ahead) => Some(read_ahead),
+        // without --read-ahead-kb the setting is only shown.
+        Err(e) if args.read_ahead_kb.is_empty() => {
+            debug!("{e:#}");
+            None
+        }
+        Err(e) => return Err(e),
+    };
+    drop(file);
+
+    let settings = if args.read_ahead_kb.is_empty() {
+        vec![read_ahead.as_ref().map(ReadAheadKb::get).transpose()?]
+    } else {
+        args.read_ahead_kb.iter().copied().map(Some).collect()
+    };
+
+    if args.format == Format::Text {
+        println!(
+            "=> {}: {pages} pages {}{}",
+            args.file.display(),
+            format_size(size, formatter),
+            read_ahead
+                .as_ref()
+                .map(|ra| format!(" on device {}", ra.device))
+                .unwrap_or_default()
+        );
+        println!(
+            "{}",
+            format!(
+                "{:>13}  {:<10}  {:<10}  {:>8}  {:>8}  READAHEAD WINDOWS (pages)",
+                "READ_AHEAD_KB", "HINT", "PATTERN", "ACCESSED", "RESIDENT"
+            )
+            .bold()
+        );
+    }
+
+    let accesses = Accesses {
+        offset: args.offset,
+        steps: args.steps,
+        pages: args.access_pages,
+        stride: args.stride,
+    };
+    if let Some(read_ahead) = &read_ahead
+        && !args.read_ahead_kb.is_empty()
+    {
+        // it is restored on exit, but not if this process is killed.
+        eprintln!(
+            "=> read_ahead_kb of device {} is {}, if interrupted restore it with: {}",
+            read_ahead.device,
+            read_ahead.original(),
+            read_ahead.restore_command()
+        );
+    }
+
+    let mut runs = Vec::new();
+    for kb in settings {
+        if let Some(read_ahead) = &read_ahead
+            && let Some(kb) = kb
+            && !args.read_ahead_kb.is_empty()
+        {
+            read_ahead.set(kb)?;
+        }
+
+        for &hint in &args.hint {
+            for &pattern in &args.pattern {
+                let run = run(&args.file, kb, hint, pattern, &accesses)?;
+                if args.format == Format::Text {
+                    print_run(&run, args.verbose);
+                }
+                runs.push(run);
+            }
+        }
+    }
+
+    if args.format == Format::Json {
+        let report = Report {
+            path: &args.file,
+            pages,
+            size,
+            device: read_ahead.as_ref().map(|ra| ra.device.as_str()),
+            runs: &runs,
+        };
+        println!("{}", serde_json::to_string(&report)?);
+    }
+
+    Ok(())
+}
+
+/// Print a row for `run`, and a row per access with `verbose`.
+fn print_run(run: &Run, verbose: bool) {
+    let windows = run
+        .windows
+        .iter()
+        .map(u64::to_string)
+        .collect::<Vec<_>>()
+        .join(" → ");
+    println!(
+        "{:>13}  {:<10}  {:<10}  {:>8}  {:>8}  {}",
+        run.read_ahead_kb
+            .map_or("-".to_owned(), |kb| kb.to_string()),
+        run.hint.to_string(),
+        run.pattern.to_string(),
+        run.accessed(),
+        run.resident(),
+        windows.cyan()
+    );
+    if verbose {
+        for step in &run.steps {
+            step.print();
+        }
+    }
+}
+
+/// Create `path` with `size` bytes of data, and write it back so it can be evicted.
+fn create(path: &Path, size: u64) -> Result<()> {
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .context(format!("failed to create {}", path.display()))?;
+
+    let chunk = vec![0xffu8; 1 << 20];
+    let mut left = size;
+    while left > 0 {
+        let len = left.min(chunk.len() as u64);
+        file.write_all(&chunk[..len as usize])
+            .context(format!("failed to write {}", path.display()))?;
+        left -= len;
+    }
+    file.sync_all()
+        .context(format!("failed to sync {}", path.display()))
+}
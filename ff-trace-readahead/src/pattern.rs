@@ -0,0 +1,268 @@
+//! Access a file with a pattern, and record the pages that each access brings into the page
+//! cache.
+use anyhow::{Context, Result, ensure};
+use ff::args::{fmt_page_ranges, ranges_len, subtract_ranges};
+use ff::pagemap::{EvictOptions, Mapping, PageMapExt, vm_page_size};
+use nix::fcntl::{PosixFadviseAdvice, posix_fadvise};
+use nix::libc::{MADV_NORMAL, MADV_RANDOM, MADV_SEQUENTIAL, MAP_SHARED, PROT_READ, c_int};
+use serde::Serialize;
+use std::fs::File;
+use std::ops::Range;
+use std::os::unix::fs::FileExt;
+use std::path::Path;
+
+/// How a file is accessed, one access per step.
+#[derive(clap::ValueEnum, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Pattern {
+    /// a single read
+    Single,
+    /// reads one after another
+    Sequential,
+    /// reads that skip pages between them, see `--stride`
+    Strided,
+    /// page faults on a shared mapping, one after another
+    Mmap,
+}
+
+impl std::fmt::Display for Pattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Pattern::Single => "single",
+            Pattern::Sequential => "sequential",
+            Pattern::Strided => "strided",
+            Pattern::Mmap => "mmap",
+        })
+    }
+}
+
+/// The access hint given for a file before it is accessed.
+#[derive(clap::ValueEnum, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Hint {
+    /// POSIX_FADV_NORMAL, and MADV_NORMAL on mappings
+    Normal,
+    /// POSIX_FADV_SEQUENTIAL, and MADV_SEQUENTIAL on mappings
+    Sequential,
+    /// POSIX_FADV_RANDOM, and MADV_RANDOM on mappings
+    Random,
+    /// POSIX_FADV_NOREUSE, it has no madvise(2) equivalent
+    Noreuse,
+}
+
+impl Hint {
+    fn fadvise(self) -> PosixFadviseAdvice {
+        match self {
+            Hint::Normal => PosixFadviseAdvice::POSIX_FADV_NORMAL,
+            Hint::Sequential => PosixFadviseAdvice::POSIX_FADV_SEQUENTIAL,
+            Hint::Random => PosixFadviseAdvice::POSIX_FADV_RANDOM,
+            Hint::Noreuse => PosixFadviseAdvice::POSIX_FADV_NOREUSE,
+        }
+    }
+
+    /// The `madvise(2)` advice for a mapping, there is none for NOREUSE.
+    fn madvise(self) -> Option<c_int> {
+        match self {
+            Hint::Normal => Some(MADV_NORMAL),
+            Hint::Sequential => Some(MADV_SEQUENTIAL),
+            Hint::Random => Some(MADV_RANDOM),
+            Hint::Noreuse => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Hint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Hint::Normal => "normal",
+            Hint::Sequential => "sequential",
+            Hint::Random => "random",
+            Hint::Noreuse => "noreuse",
+        })
+    }
+}
+
+/// Where the accesses of a pattern land, in pages.
+#[derive(Debug, Clone)]
+pub struct Accesses {
+    /// The first page accessed.
+    pub offset: u64,
+    /// Number of accesses, [`Pattern::Single`] makes one.
+    pub steps: u64,
+    /// Pages read (or faulted in) by each access.
+    pub pages: u64,
+    /// Pages from the start of a strided access to the start of the next one.
+    pub stride: u64,
+}
+
+impl Accesses {
+    /// Returns the pages of each access of `pattern`, in order, up to the end of a file of
+    /// `file_pages` pages.
+    fn of(&self, pattern: Pattern, file_pages: u64) -> Vec<Range<u64>> {
+        let (steps, distance) = match pattern {
+            Pattern::Single => (1, 0),
+            Pattern::Sequential | Pattern::Mmap => (self.steps, self.pages),
+            Pattern::Strided => (self.steps, self.stride),
+        };
+        (0..steps)
+            .map(|i| self.offset + i * distance)
+            .take_while(|&start| start < file_pages)
+            .map(|start| start..(start + self.pages).min(file_pages))
+            .collect()
+    }
+}
+
+/// An access, and the pages it brought into the page cache.
+#[derive(Serialize, Debug)]
+pub struct Step {
+    /// The pages that were accessed.
+    pub access: Range<u64>,
+    /// The pages that became resident during the access, including the accessed ones.
+    pub read_in: Vec<Range<u64>>,
+    /// Number of resident pages after the access.
+    pub resident: u64,
+}
+
+impl Step {
+    /// Returns the number of pages that became resident during the access.
+    pub fn window(&self) -> u64 {
+        ranges_len(&self.read_in)
+    }
+
+    /// Print the step as a row under its run.
+    pub fn print(&self) {
+        let row = format!(
+            "    access {:<12} read in {:<8} resident {:<8} {}",
+            fmt_page_ranges(std::slice::from_ref(&self.access)),
+            self.window(),
+            self.resident,
+            fmt_page_ranges(&self.read_in),
+        );
+        println!("{}", row.trim_end());
+    }
+}
+
+/// The accesses of a pattern under a hint and a `read_ahead_kb` setting, see [`run`].
+#[derive(Serialize, Debug)]
+pub struct Run {
+    /// The `read_ahead_kb` of the backing device, if it was found.
+    pub read_ahead_kb: Option<u64>,
+    pub hint: Hint,
+    pub pattern: Pattern,
+    pub steps: Vec<Step>,
+    /// The number of pages read in by each access that read in any, this is how the readahead
+    /// window grows.
+    pub windows: Vec<u64>,
+}
+
+impl Run {
+    /// Returns the number of pages that were accessed.
+    pub fn accessed(&self) -> u64 {
+        self.steps
+            .iter()
+            .map(|s| s.access.end - s.access.start)
+            .sum()
+    }
+
+    /// Returns the number of resident pages after the last access.
+    pub fn resident(&self) -> u64 {
+        self.steps.last().map_or(0, |s| s.resident)
+    }
+}
+
+/// Evict `path`, open it again (the readahead state is kept in the open file), give it `hint`
+/// and access it with `pattern`, recording which pages became resident after every access.
+pub fn run(
+    path: &Path,
+    read_ahead_kb: Option<u64>,
+    hint: Hint,
+    pattern: Pattern,
+    accesses: &Accesses,
+) -> Result<Run> {
+    let vm_page = vm_page_size()?;
+    evict(path)?;
+
+    let file = File::open(path).context(format!("failed to open {}", path.display()))?;
+    let file_pages = file.vm_pages_count()?;
+    posix_fadvise(&file, 0, 0, hint.fadvise()).context(format!("posix_fadvise({hint}) failed"))?;
+
+    let mapping = match pattern {
+        Pattern::Mmap => {
+            let mapping = Mapping::new(&file, 0, file_pages * vm_page, PROT_READ, MAP_SHARED)?;
+            if let Some(advice) = hint.madvise() {
+                mapping
+                    .advise(advice)
+                    .context(format!("madvise({hint}) failed"))?;
+            }
+            Some(mapping)
+        }
+        _ => None,
+    };
+    let mut buf = vec![0u8; (accesses.pages * vm_page) as usize];
+    let mut resident = Vec::new();
+    let mut steps = Vec::new();
+
+    for access in accesses.of(pattern, file_pages) {
+        match &mapping {
+            Some(mapping) => {
+                for page in access.clone() {
+                    // SAFETY: the page is within the mapping, the accesses are clamped to the
+                    // file.
+                    unsafe { mapping.page_ptr(page, vm_page).read_volatile() };
+                }
+            }
+            None => {
+                let len = ((access.end - access.start) * vm_page) as usize;
+                file.read_at(&mut buf[..len], access.start * vm_page)
+                    .context(format!(
+                        "failed to read pages {}..{}",
+                        access.start, access.end
+                    ))?;
+            }
+        }
+
+        let now = file
+            .cached_ranges(0..file_pages)?
+            .collect::<Result<Vec<_>>>()?;
+        steps.push(Step {
+            access,
+            read_in: subtract_ranges(&now, &resident),
+            resident: ranges_len(&now),
+        });
+        resident = now;
+    }
+
+    let windows = steps
+        .iter()
+        .map(Step::window)
+        .filter(|&window| window > 0)
+        .collect();
+    Ok(Run {
+        read_ahead_kb,
+        hint,
+        pattern,
+        steps,
+        windows,
+    })
+}
+
+/// Evict every page of `path`, so that each run starts with nothing cached.
+fn evict(path: &Path) -> Result<()> {
+    let file = File::open(path).context(format!("failed to open {}", path.display()))?;
+    let report = file.evict_range_verified(
+        0..file.vm_pages_count()?,
+        &EvictOptions {
+            sync_first: true,
+            retries: 3,
+            ..Default::default()
+        },
+    )?;
+    ensure!(
+        report.survivors.is_empty(),
+        "{} pages of {} are still cached after eviction ({}), is it mapped or on tmpfs?",
+        report.nr_survivors(),
+        path.display(),
+        report.survivors[0].1,
+    );
+    Ok(())
+}
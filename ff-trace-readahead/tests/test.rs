@@ -0,0 +1,242 @@
+use assert_cmd::{cargo, prelude::*};
+use serde_json::Value;
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// A path in the target directory for the traced file, it is removed on drop.
+///
+/// The file is not created in `/tmp`, tmpfs has no readahead.
+struct TestPath(PathBuf);
+
+impl TestPath {
+    fn new(name: &str) -> Self {
+        let path = Path::new(env!("CARGO_TARGET_TMPDIR")).join(format!("readahead-{name}.img"));
+        let _ = std::fs::remove_file(&path);
+        Self(path)
+    }
+}
+
+impl Drop for TestPath {
+    // cleanup
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+/// Run the tracer on `path` with `args`, and return its JSON report.
+fn trace(path: &Path, args: &[&str]) -> Value {
+    let output = Command::new(cargo::cargo_bin!())
+        .arg(path)
+        .args(args)
+        .args(["--format", "json"])
+        .assert()
+        .success()
+        .get_output()
+        .stdout
+        .clone();
+    serde_json::from_slice(&output).unwrap()
+}
+
+#[test]
+fn test_creates_file() {
+    let path = TestPath::new("create");
+    let report = trace(&path.0, &["--pages", "64", "--pattern", "single"]);
+
+    assert_eq!(report["pages"], 64);
+    assert_eq!(
+        std::fs::metadata(&path.0).unwrap().len(),
+        report["size"].as_u64().unwrap()
+    );
+    // one run per hint.
+    let runs = report["runs"].as_array().unwrap();
+    assert_eq!(runs.len(), 4);
+    for run in runs {
+        assert_eq!(run["pattern"], "single");
+        assert_eq!(run["steps"].as_array().unwrap().len(), 1);
+    }
+}
+
+#[test]
+fn test_sequential_reads_ahead() {
+    let path = TestPath::new("sequential");
+    let report = trace(
+        &path.0,
+        &[
+            "--pages",
+            "256",
+            "--pattern",
+            "sequential",
+            "--hint",
+            "normal",
+            "-s",
+            "8",
+        ],
+    );
+
+    let run = &report["runs"][0];
+    let steps = run["steps"].as_array().unwrap();
+    assert_eq!(steps.len(), 8);
+    // the first read starts a window past the page it reads.
+    assert_eq!(
+        steps[0]["access"],
+        serde_json::json!({"start": 0, "end": 1})
+    );
+    assert!(steps[0]["resident"].as_u64().unwrap() > 1);
+    assert!(steps[7]["resident"].as_u64().unwrap() > 8);
+    assert!(!run["windows"].as_array().unwrap().is_empty());
+}
+
+#[test]
+fn test_random_hint_disables_readahead() {
+    let path = TestPath::new("random");
+    let report = trace(
+        &path.0,
+        &[
+            "--pages",
+            "256",
+            "--pattern",
+            "sequential,strided",
+            "--hint",
+            "random",
+            "-s",
+            "8",
+        ],
+    );
+
+    for run in report["runs"].as_array().unwrap() {
+        // every read brings in only the page it reads.
+        assert_eq!(run["windows"], serde_json::json!([1, 1, 1, 1, 1, 1, 1, 1]));
+        assert_eq!(run["steps"][7]["resident"], 8);
+    }
+}
+
+#[test]
+fn test_strided_accesses() {
+    let path = TestPath::new("strided");
+    let report = trace(
+        &path.0,
+        &[
+            "--pages",
+            "64",
+            "--pattern",
+            "strided",
+            "--hint",
+            "random",
+            "--stride",
+            "4",
+            "--offset",
+            "2",
+            "-s",
+            "3",
+        ],
+    );
+
+    let steps = report["runs"][0]["steps"].as_array().unwrap();
+    let accesses = steps.iter().map(|s| &s["access"]).collect::<Vec<_>>();
+    assert_eq!(
+        accesses,
+        [
+            &serde_json::json!({"start": 2, "end": 3}),
+            &serde_json::json!({"start": 6, "end": 7}),
+            &serde_json::json!({"start": 10, "end": 11}),
+        ]
+    );
+}
+
+#[test]
+fn test_mmap_accesses() {
+    let path = TestPath::new("mmap");
+    let report = trace(
+        &path.0,
+        &[
+            "--pages",
+            "64",
+            "--pattern",
+            "mmap",
+            "--hint",
+            "random,noreuse",
+            "-s",
+            "8",
+        ],
+    );
+
+    let range = |value: &Value| value["start"].as_u64().unwrap()..value["end"].as_u64().unwrap();
+    let runs = report["runs"].as_array().unwrap();
+    assert_eq!(runs.len(), 2);
+    for run in runs {
+        let steps = run["steps"].as_array().unwrap();
+        assert_eq!(steps.len(), 8);
+        let mut read_in = Vec::new();
+        let mut resident = 0;
+        for step in steps {
+            let access = range(&step["access"]);
+            let step_read_in = step["read_in"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(range)
+                .collect::<Vec<_>>();
+            // MADV_RANDOM faults in only the page that is read. There is no madvise(2) advice
+            // for NOREUSE, its page may have been read in by fault-around in an earlier step.
+            if run["hint"] == "random" {
+                assert_eq!(step_read_in, std::slice::from_ref(&access), "{run}");
+            }
+            read_in.extend(step_read_in);
+            assert!(
+                read_in
+                    .iter()
+                    .any(|r| r.start <= access.start && access.end <= r.end),
+                "{run}"
+            );
+
+            let now = step["resident"].as_u64().unwrap();
+            assert!(now >= resident, "{run}");
+            resident = now;
+        }
+    }
+}
+
+#[test]
+#[ignore]
+fn test_read_ahead_kb_run_as_root() {
+    let path = TestPath::new("read-ahead-kb");
+    // 4MiB, more than the largest window.
+    let original = trace(
+        &path.0,
+        &["--pages", "1024", "--pattern", "single", "--hint", "normal"],
+    )["runs"][0]["read_ahead_kb"]
+        .clone();
+    let report = trace(
+        &path.0,
+        &[
+            "--pattern",
+            "sequential",
+            "--hint",
+            "normal",
+            "--read-ahead-kb",
+            "16,512",
+        ],
+    );
+
+    let runs = report["runs"].as_array().unwrap();
+    assert_eq!(runs[0]["read_ahead_kb"], 16);
+    assert_eq!(runs[1]["read_ahead_kb"], 512);
+    // a larger setting lets the window grow further.
+    let max = |run: &Value| {
+        run["windows"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|w| w.as_u64().unwrap())
+            .max()
+    };
+    assert!(max(&runs[0]) < max(&runs[1]));
+
+    // the setting is restored.
+    let after =
+        trace(&path.0, &["--pattern", "single", "--hint", "normal"])["runs"][0]["read_ahead_kb"]
+            .clone();
+    assert_eq!(original, after);
+}
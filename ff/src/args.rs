@@ -1,5 +1,5 @@
-use anyhow::{Context, Result, ensure};
-use std::ops::RangeInclusive;
+use anyhow::{Context, Result, bail, ensure};
+use std::ops::{Range, RangeInclusive};
 
 /// Parse argument as a range.
 ///
@@ -11,28 +11,108 @@ use std::ops::RangeInclusive;
 /// assert_eq!(parse_as_range("10-15").unwrap(), 10..=15); // [10, 15]
 /// ```
 pub fn parse_as_range<S: AsRef<str>>(range: S) -> Result<RangeInclusive<u64>> {
-    let mut parts = range.as_ref().splitn(2, '-');
-    let first = parts.next().expect("split has at least one item");
-    let first = first.parse::<u64>().context(format!(
-        "{first} is not a number. `{}` is not a valid range",
-        range.as_ref()
-    ))?;
+    let (first, second) = split_range(range.as_ref())?;
+    let parse = |num: &str| {
+        num.parse::<u64>().context(format!(
+            "{num} is not a number. `{}` is not a valid range",
+            range.as_ref()
+        ))
+    };
+
+    let first = parse(first)?;
+    Ok(match second {
+        Some(second) => first..=parse(second)?,
+        None => first..=first,
+    })
+}
 
-    Ok(match parts.next() {
-        Some(second) => {
+/// Parse argument as an inclusive range of pages.
+///
+/// A bound without a unit (or with a `p` suffix) is a page index, as printed by
+/// [`fmt_ranges`]. A bound with a size unit (`B`, `K`, `M` or `G`, in powers of 1024) is a byte
+/// offset: the range covers the bytes `[start, end)`, i.e. all pages overlapping them.
+///
+/// # Examples
+/// ```rust
+/// use ff::args::parse_as_page_range;
+///
+/// assert_eq!(parse_as_page_range("10-15", 4096).unwrap(), 10..=15);
+/// assert_eq!(parse_as_page_range("3p", 4096).unwrap(), 3..=3);
+/// assert_eq!(parse_as_page_range("0-8K", 4096).unwrap(), 0..=1); // the first 8KiB
+/// assert_eq!(parse_as_page_range("1M-2M", 4096).unwrap(), 256..=511);
+/// ```
+pub fn parse_as_page_range<S: AsRef<str>>(range: S, page_size: u64) -> Result<RangeInclusive<u64>> {
+    ensure!(page_size > 0, "page size cannot be zero");
+    let (first, second) = split_range(range.as_ref())?;
+
+    let start = match parse_bound(first, range.as_ref())? {
+        Bound::Page(page) => page,
+        Bound::Byte(offset) => offset / page_size,
+    };
+    let end = match second.map(|s| parse_bound(s, range.as_ref())).transpose()? {
+        Some(Bound::Page(page)) => page,
+        Some(Bound::Byte(offset)) => {
             ensure!(
-                !second.is_empty(),
-                "missing range end. `{}` is not a valid range",
+                offset > 0,
+                "the range end is exclusive. `{}` is an empty range",
                 range.as_ref()
             );
-            let second = second.parse::<u64>().context(format!(
-                "{second} is not a number. `{}` is not a valid range",
-                range.as_ref()
-            ))?;
-            first..=second
+            (offset - 1) / page_size
         }
-        None => first..=first,
-    })
+        None => start,
+    };
+
+    ensure!(
+        start <= end,
+        "the range start is after the range end. `{}` is not a valid range",
+        range.as_ref()
+    );
+
+    Ok(start..=end)
+}
+
+/// Split `range` into its start and an optional end.
+fn split_range(range: &str) -> Result<(&str, Option<&str>)> {
+    let mut parts = range.splitn(2, '-');
+    let first = parts.next().expect("split has at least one item");
+    let second = parts.next();
+    if let Some(second) = second {
+        ensure!(
+            !second.is_empty(),
+            "missing range end. `{}` is not a valid range",
+            range
+        );
+    }
+
+    Ok((first, second))
+}
+
+enum Bound {
+    Page(u64),
+    Byte(u64),
+}
+
+fn parse_bound(bound: &str, range: &str) -> Result<Bound> {
+    let split = bound
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(bound.len());
+    let (num, unit) = bound.split_at(split);
+    let num = num.parse::<u64>().context(format!(
+        "{bound} is not a number. `{range}` is not a valid range"
+    ))?;
+
+    let multiplier = match unit {
+        "" | "p" | "P" => return Ok(Bound::Page(num)),
+        "b" | "B" => 1,
+        "k" | "K" => 1 << 10,
+        "m" | "M" => 1 << 20,
+        "g" | "G" => 1 << 30,
+        _ => bail!("unknown unit `{unit}` in `{range}`, expected one of p, B, K, M, G"),
+    };
+
+    Ok(Bound::Byte(num.checked_mul(multiplier).context(
+        format!("{bound} is too large. `{range}` is not a valid range"),
+    )?))
 }
 
 /// Returns a string representation of the the range `nums`.
@@ -44,44 +124,144 @@ pub fn parse_as_range<S: AsRef<str>>(range: S) -> Result<RangeInclusive<u64>> {
 /// assert_eq!(fmt_ranges(&[1, 2, 3, 4, 9]), "1-4, 9");
 /// ```
 pub fn fmt_ranges(nums: &[u64]) -> String {
-    if nums.is_empty() {
-        return String::new();
-    }
-
-    let mut ranges = Vec::new();
-    let mut start = nums[0];
-    let mut end = nums[0];
+    fmt_page_ranges(&coalesce_ranges(nums))
+}
 
-    for &num in &nums[1..] {
-        if num == end + 1 {
-            // Extend the current range
-            end = num;
-        } else {
-            // Close current range
-            if start == end {
-                ranges.push(format!("{}", start));
+/// Format sorted half-open ranges the way [`fmt_ranges`] formats the numbers in them.
+///
+/// ```rust
+/// use ff::args::fmt_page_ranges;
+///
+/// assert_eq!(fmt_page_ranges(&[1..5, 9..10]), "1-4, 9");
+/// ```
+pub fn fmt_page_ranges(ranges: &[Range<u64>]) -> String {
+    ranges
+        .iter()
+        .map(|r| {
+            if r.end - r.start == 1 {
+                format!("{}", r.start)
             } else {
-                ranges.push(format!("{}-{}", start, end));
+                format!("{}-{}", r.start, r.end - 1)
             }
-            // Start a new range
-            start = num;
-            end = num;
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Parse the output of [`fmt_page_ranges`] back into sorted half-open ranges.
+///
+/// ```rust
+/// use ff::args::parse_page_ranges;
+///
+/// assert_eq!(parse_page_ranges("1-4, 9").unwrap(), vec![1..5, 9..10]);
+/// assert_eq!(parse_page_ranges("").unwrap(), vec![]);
+/// ```
+pub fn parse_page_ranges<S: AsRef<str>>(ranges: S) -> Result<Vec<Range<u64>>> {
+    let mut parsed: Vec<Range<u64>> = Vec::new();
+    for range in ranges.as_ref().split(',').map(str::trim) {
+        if range.is_empty() {
+            continue;
         }
+        let range = parse_as_range(range)?;
+        let end = range
+            .end()
+            .checked_add(1)
+            .context(format!("`{}` is too large", range.end()))?;
+        ensure!(
+            parsed.last().is_none_or(|last| last.end <= *range.start()),
+            "`{}` is not sorted",
+            ranges.as_ref()
+        );
+        parsed.push(*range.start()..end);
     }
+    Ok(parsed)
+}
+
+/// Coalesce a sorted list of numbers into half-open ranges of consecutive numbers.
+///
+/// ```rust
+/// use ff::args::coalesce_ranges;
+///
+/// assert_eq!(coalesce_ranges(&[1, 2, 3, 4, 9]), vec![1..5, 9..10]);
+/// ```
+pub fn coalesce_ranges(nums: &[u64]) -> Vec<Range<u64>> {
+    nums.chunk_by(|a, b| a.checked_add(1) == Some(*b))
+        .map(|run| run[0]..run[run.len() - 1] + 1)
+        .collect()
+}
 
-    // Push the last range
-    if start == end {
-        ranges.push(format!("{}", start));
-    } else {
-        ranges.push(format!("{}-{}", start, end));
+/// Returns the number of numbers in `ranges`.
+///
+/// ```rust
+/// use ff::args::ranges_len;
+///
+/// assert_eq!(ranges_len(&[1..5, 9..10]), 5);
+/// ```
+pub fn ranges_len(ranges: &[Range<u64>]) -> u64 {
+    ranges.iter().map(|r| r.end - r.start).sum()
+}
+
+/// Returns the parts of the sorted ranges `ranges` that are also in the sorted ranges `other`.
+///
+/// ```rust
+/// use ff::args::intersect_ranges;
+///
+/// assert_eq!(intersect_ranges(&[0..4, 6..10], &[2..7, 9..12]), vec![2..4, 6..7, 9..10]);
+/// ```
+pub fn intersect_ranges(ranges: &[Range<u64>], other: &[Range<u64>]) -> Vec<Range<u64>> {
+    let mut both = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < ranges.len() && j < other.len() {
+        let start = ranges[i].start.max(other[j].start);
+        let end = ranges[i].end.min(other[j].end);
+        if start < end {
+            both.push(start..end);
+        }
+        // the range that ends first has no more overlaps.
+        if ranges[i].end < other[j].end {
+            i += 1;
+        } else {
+            j += 1;
+        }
     }
+    both
+}
 
-    ranges.join(", ")
+/// Returns the parts of the sorted ranges `ranges` that are not in the sorted ranges `other`.
+///
+/// ```rust
+/// use ff::args::subtract_ranges;
+///
+/// assert_eq!(subtract_ranges(&[0..4, 6..10], &[2..7, 9..12]), vec![0..2, 7..9]);
+/// ```
+pub fn subtract_ranges(ranges: &[Range<u64>], other: &[Range<u64>]) -> Vec<Range<u64>> {
+    let mut left = Vec::new();
+    let mut j = 0;
+    for range in ranges {
+        // ranges of `other` that end before this one can't overlap the next ones either.
+        while j < other.len() && other[j].end <= range.start {
+            j += 1;
+        }
+        let mut start = range.start;
+        for o in other[j..].iter().take_while(|o| o.start < range.end) {
+            if o.start > start {
+                left.push(start..o.start);
+            }
+            start = start.max(o.end);
+        }
+        if start < range.end {
+            left.push(start..range.end);
+        }
+    }
+    left
 }
 
 #[cfg(test)]
 mod test {
-    use super::parse_as_range;
+    use super::{
+        coalesce_ranges, fmt_page_ranges, intersect_ranges, parse_as_page_range, parse_as_range,
+        parse_page_ranges, subtract_ranges,
+    };
 
     #[test]
     fn test_parser() {
@@ -89,4 +269,64 @@ mod test {
         assert_eq!(parse_as_range("20-27").unwrap(), 20..=27);
         assert_eq!(parse_as_range("0-1").unwrap(), 0..=1);
     }
+
+    #[test]
+    fn test_page_range_parser() {
+        assert_eq!(parse_as_page_range("7", 4096).unwrap(), 7..=7);
+        assert_eq!(parse_as_page_range("2p-4P", 4096).unwrap(), 2..=4);
+        assert_eq!(parse_as_page_range("0-4096B", 4096).unwrap(), 0..=0);
+        assert_eq!(parse_as_page_range("0-4097b", 4096).unwrap(), 0..=1);
+        assert_eq!(parse_as_page_range("4K-12k", 4096).unwrap(), 1..=2);
+        assert_eq!(
+            parse_as_page_range("1G-1g", 4096).unwrap_err().to_string(),
+            "the range start is after the range end. `1G-1g` is not a valid range"
+        );
+        assert_eq!(parse_as_page_range("2-1M", 4096).unwrap(), 2..=255);
+        // a byte offset selects the page that contains it
+        assert_eq!(parse_as_page_range("5000B", 4096).unwrap(), 1..=1);
+
+        assert!(parse_as_page_range("0-0K", 4096).is_err());
+        assert!(parse_as_page_range("1T", 4096).is_err());
+        assert!(parse_as_page_range("1-", 4096).is_err());
+        assert!(parse_as_page_range("K", 4096).is_err());
+    }
+
+    #[test]
+    fn test_coalesce_ranges() {
+        assert_eq!(coalesce_ranges(&[]), vec![]);
+        assert_eq!(coalesce_ranges(&[7]), vec![7..8]);
+        assert_eq!(
+            coalesce_ranges(&[1, 2, 4, 8, 9, 15]),
+            vec![1..3, 4..5, 8..10, 15..16]
+        );
+    }
+
+    #[test]
+    #[allow(clippy::single_range_in_vec_init)]
+    fn test_parse_page_ranges() {
+        for ranges in [vec![], vec![7..8], vec![1..3, 4..5, 8..10, 15..16]] {
+            assert_eq!(parse_page_ranges(fmt_page_ranges(&ranges)).unwrap(), ranges);
+        }
+        assert_eq!(parse_page_ranges(" 3 ,5-6").unwrap(), vec![3..4, 5..7]);
+        assert!(parse_page_ranges("5, 1").is_err());
+        assert!(parse_page_ranges("1-3, 3").is_err());
+        assert!(parse_page_ranges("18446744073709551615").is_err());
+        assert!(parse_page_ranges("a").is_err());
+    }
+
+    #[test]
+    #[allow(clippy::single_range_in_vec_init)]
+    fn test_intersect_and_subtract_ranges() {
+        assert_eq!(intersect_ranges(&[], &[0..4]), vec![]);
+        assert_eq!(intersect_ranges(&[0..10], &[1..2, 4..6]), vec![1..2, 4..6]);
+        assert_eq!(intersect_ranges(&[0..2, 2..4], &[1..3]), vec![1..2, 2..3]);
+        assert_eq!(intersect_ranges(&[0..2], &[2..4]), vec![]);
+
+        assert_eq!(subtract_ranges(&[0..4], &[]), vec![0..4]);
+        assert_eq!(
+            subtract_ranges(&[0..10], &[1..2, 4..6]),
+            vec![0..1, 2..4, 6..10]
+        );
+        assert_eq!(subtract_ranges(&[0..4], &[0..4]), vec![]);
+    }
 }
@@ -0,0 +1,156 @@
+//! cgroup v2 helpers.
+use anyhow::{Context, Result, anyhow};
+use std::collections::HashMap;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+/// Return the mountpoint of the cgroup v2 hierarchy.
+pub fn cgroup2_mountpoint() -> Result<PathBuf> {
+    std::fs::read_to_string("/proc/self/mounts")
+        .context("unable to read /proc/self/mounts")?
+        .lines()
+        .find_map(|l| {
+            let mut parts = l.split_whitespace();
+            let mountpoint = parts.nth(1)?;
+            (parts.next()? == "cgroup2").then(|| PathBuf::from(mountpoint))
+        })
+        .ok_or(anyhow!("cgroup v2 is not mounted"))
+}
+
+/// Return the mountpoint of the hierarchy the memory controller is attached to.
+///
+/// That is the cgroup v1 `memory` hierarchy on hybrid setups, and cgroup v2 otherwise.
+pub fn memory_cgroup_mountpoint() -> Result<PathBuf> {
+    let v1 = std::fs::read_to_string("/proc/self/mounts")
+        .context("unable to read /proc/self/mounts")?
+        .lines()
+        .find_map(|l| {
+            let mut parts = l.split_whitespace();
+            let mountpoint = parts.nth(1)?;
+            let fs_type = parts.next()?;
+            let options = parts.next()?;
+            (fs_type == "cgroup" && options.split(',').any(|o| o == "memory"))
+                .then(|| PathBuf::from(mountpoint))
+        });
+
+    match v1 {
+        Some(mountpoint) => Ok(mountpoint),
+        None => cgroup2_mountpoint(),
+    }
+}
+
+/// Return the path of the cgroup v2 that this process belongs to.
+pub fn current_cgroup() -> Result<PathBuf> {
+    let cgroup = std::fs::read_to_string("/proc/self/cgroup")
+        .context("unable to read /proc/self/cgroup")?
+        .lines()
+        // the unified hierarchy has the format `0::<path>`
+        .find_map(|l| l.strip_prefix("0::").map(str::to_owned))
+        .ok_or(anyhow!("this process is not in a cgroup v2"))?;
+
+    Ok(cgroup2_mountpoint()?.join(cgroup.trim_start_matches('/')))
+}
+
+/// Return the path of every memory cgroup by its inode, as reported by `/proc/kpagecgroup`.
+///
+/// The hierarchy under [`memory_cgroup_mountpoint`] is walked, cgroups that cannot be read are
+/// skipped.
+pub fn cgroup_inodes() -> Result<HashMap<u64, PathBuf>> {
+    let mut inodes = HashMap::new();
+    let mut dirs = vec![memory_cgroup_mountpoint()?];
+
+    while let Some(dir) = dirs.pop() {
+        let Ok(metadata) = std::fs::metadata(&dir) else {
+            continue;
+        };
+        inodes.insert(metadata.ino(), dir.clone());
+
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+        dirs.extend(
+            entries
+                .flatten()
+                .filter(|e| e.file_type().is_ok_and(|t| t.is_dir()))
+                .map(|e| e.path()),
+        );
+    }
+
+    Ok(inodes)
+}
+
+/// Ask the kernel to reclaim `bytes` of memory from `cgroup` by writing to `memory.reclaim`.
+///
+/// Reclaim is proactive and best effort, it targets all the memory charged to the cgroup (page
+/// cache and anonymous memory), not only a specific file. It is not an error if less than
+/// `bytes` could be reclaimed.
+pub fn reclaim<P: AsRef<Path>>(cgroup: P, bytes: u64) -> Result<()> {
+    let path = cgroup.as_ref().join("memory.reclaim");
+    match std::fs::write(&path, format!("{bytes}\n")) {
+        // the kernel returns EAGAIN when it reclaimed less than requested.
+        Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => Ok(()),
+        res => res.context(format!("failed to write to {}", path.display())),
+    }
+}
+
+/// A temporary cgroup that is removed on drop.
+///
+/// Page cache pages are charged to the cgroup of the process that brought them into cache, so
+/// a workload that runs inside a scratch cgroup can have its pages reclaimed through
+/// [`reclaim`] without touching the rest of the system.
+pub struct ScratchCgroup {
+    path: PathBuf,
+    /// The cgroup this process was in before it entered the scratch cgroup.
+    previous: Option<PathBuf>,
+}
+
+impl ScratchCgroup {
+    /// Create a new cgroup at the root of the cgroup v2 hierarchy, with the memory controller
+    /// enabled.
+    pub fn create<S: AsRef<str>>(name: S) -> Result<Self> {
+        let root = cgroup2_mountpoint()?;
+        std::fs::write(root.join("cgroup.subtree_control"), "+memory")
+            .context("failed to enable the memory controller")?;
+
+        let path = root.join(name.as_ref());
+        std::fs::create_dir(&path).context(format!("failed to create {}", path.display()))?;
+
+        Ok(ScratchCgroup {
+            path,
+            previous: None,
+        })
+    }
+
+    /// Move this process into the cgroup, it is moved back to its current cgroup on drop.
+    pub fn enter(&mut self) -> Result<()> {
+        let previous = current_cgroup()?;
+        std::fs::write(
+            self.path.join("cgroup.procs"),
+            std::process::id().to_string(),
+        )
+        .context(format!("failed to move into {}", self.path.display()))?;
+        self.previous = Some(previous);
+        Ok(())
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for ScratchCgroup {
+    fn drop(&mut self) {
+        // a cgroup cannot be removed while it has processes, move back to where we were.
+        if let Some(previous) = &self.previous
+            && let Err(e) = std::fs::write(
+                previous.join("cgroup.procs"),
+                std::process::id().to_string(),
+            )
+        {
+            log::warn!("failed to leave {}: {e}", self.path.display());
+        }
+        if let Err(e) = std::fs::remove_dir(&self.path) {
+            log::warn!("failed to remove {}: {e}", self.path.display());
+        }
+    }
+}
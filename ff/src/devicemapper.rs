@@ -1,58 +1,591 @@
-use std::{ops::Range, path::PathBuf};
+//! Build device-mapper tables, see `Documentation/admin-guide/device-mapper/` in the kernel.
+use anyhow::{Context, Result, bail, ensure};
+use std::{fmt, ops::Range, path::PathBuf, str::FromStr};
 
-/// format: (offset, number of blocks (length), linear|error, <dev> <dev offset>)
+/// A line of a table in the form `devicemapper::DM::table_load` takes:
+/// (offset, number of sectors (length), target type, target arguments)
 ///
 /// # Example
-/// Map 10 blocks at offset 5 in the mapped device to 10 blocks in /dev/test at
+/// Map 10 sectors at offset 5 in the mapped device to 10 sectors in /dev/test at
 /// offset 3
 /// (5, 10, "linear", "/dev/test 3")
-type Segment = (u64, u64, String, String);
+pub type TableLine = (u64, u64, String, String);
+
+/// What a segment of a mapped device is mapped to.
+///
+/// Devices are given as a path, or as `major:minor` the way `dmsetup table` prints them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    /// Map the segment to `device` from sector `offset`.
+    Linear { device: String, offset: u64 },
+    /// Fail every I/O.
+    Error,
+    /// Read zeros and discard writes.
+    Zero,
+    /// Pass I/O through to a device, and fail (or drop) it for a while, see [`Flakey`].
+    Flakey(Flakey),
+    /// Delay I/O to a device, see [`Delay`].
+    Delay(Delay),
+    /// Map the segment to `device` from sector `offset`, and fail reads of the blocks (of
+    /// `block_size` bytes) added to the bad block list with `dmsetup message`.
+    Dust {
+        device: String,
+        offset: u64,
+        block_size: u32,
+    },
+}
+
+/// The flakey target: I/O is passed through to `device` for `up` seconds, then for `down`
+/// seconds it fails, or is handled as `features` say.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Flakey {
+    pub device: String,
+    pub offset: u64,
+    pub up: u32,
+    pub down: u32,
+    pub features: Vec<FlakeyFeature>,
+}
+
+/// How a [`Flakey`] target handles I/O while it is down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlakeyFeature {
+    /// Drop writes silently, reads are passed through.
+    DropWrites,
+    /// Fail writes, reads are passed through.
+    ErrorWrites,
+    /// Fail reads, writes are passed through.
+    ErrorReads,
+    /// Replace the `nth_byte` byte (counting from 1) of the data of every bio in `direction`,
+    /// that has all the `flags` of `bi_opf` set, with `value`.
+    CorruptBioByte {
+        nth_byte: u32,
+        direction: BioDirection,
+        value: u8,
+        flags: u32,
+    },
+}
+
+/// The bios that [`FlakeyFeature::CorruptBioByte`] corrupts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BioDirection {
+    Read,
+    Write,
+}
+
+/// The delay target: reads, writes and flushes are delayed, and can go to different devices.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Delay {
+    /// Where reads go, and writes and flushes too without `write`.
+    pub read: DelayedDevice,
+    /// Where writes go, and flushes too without `flush`.
+    pub write: Option<DelayedDevice>,
+    /// Where flushes go, it needs `write`.
+    pub flush: Option<DelayedDevice>,
+}
+
+/// A device of the [`Delay`] target, and how long the I/O sent to it is delayed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DelayedDevice {
+    pub device: String,
+    pub offset: u64,
+    pub delay_ms: u32,
+}
+
+impl Target {
+    /// Returns the name of the target type.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Target::Linear { .. } => "linear",
+            Target::Error => "error",
+            Target::Zero => "zero",
+            Target::Flakey(_) => "flakey",
+            Target::Delay(_) => "delay",
+            Target::Dust { .. } => "dust",
+        }
+    }
+
+    /// Returns the arguments of the target, as they appear in a table.
+    pub fn args(&self) -> String {
+        match self {
+            Target::Linear { device, offset } => format!("{device} {offset}"),
+            Target::Error | Target::Zero => String::new(),
+            Target::Flakey(flakey) => {
+                let mut args = format!(
+                    "{} {} {} {}",
+                    flakey.device, flakey.offset, flakey.up, flakey.down
+                );
+                let features = flakey
+                    .features
+                    .iter()
+                    .map(FlakeyFeature::args)
+                    .collect::<Vec<_>>();
+                // the number of feature arguments, not of features.
+                let words = features.iter().map(|f| f.split(' ').count()).sum::<usize>();
+                if words > 0 {
+                    args.push_str(&format!(" {words} {}", features.join(" ")));
+                }
+                args
+            }
+            Target::Delay(delay) => [
+                Some(&delay.read),
+                delay.write.as_ref(),
+                delay.flush.as_ref(),
+            ]
+            .into_iter()
+            .flatten()
+            .map(|d| format!("{} {} {}", d.device, d.offset, d.delay_ms))
+            .collect::<Vec<_>>()
+            .join(" "),
+            Target::Dust {
+                device,
+                offset,
+                block_size,
+            } => format!("{device} {offset} {block_size}"),
+        }
+    }
+
+    /// Parse the arguments of a target of type `name`.
+    fn parse(name: &str, args: &[&str]) -> Result<Self> {
+        let expect = |n: usize| {
+            ensure!(
+                args.len() == n,
+                "{name} expects {n} arguments, got {}",
+                args.len()
+            );
+            Ok(())
+        };
+
+        Ok(match name {
+            "linear" => {
+                expect(2)?;
+                Target::Linear {
+                    device: args[0].into(),
+                    offset: parse_arg(args[1], "offset")?,
+                }
+            }
+            "error" => Target::Error,
+            "zero" => Target::Zero,
+            "flakey" => {
+                ensure!(
+                    args.len() >= 4,
+                    "flakey expects at least 4 arguments, got {}",
+                    args.len()
+                );
+                let features = match args.get(4) {
+                    Some(words) => {
+                        let words = parse_arg::<usize>(words, "number of feature arguments")?;
+                        ensure!(
+                            args.len() - 5 == words,
+                            "flakey expects {words} feature arguments, got {}",
+                            args.len() - 5
+                        );
+                        FlakeyFeature::parse(&args[5..])?
+                    }
+                    None => Vec::new(),
+                };
+                Target::Flakey(Flakey {
+                    device: args[0].into(),
+                    offset: parse_arg(args[1], "offset")?,
+                    up: parse_arg(args[2], "up interval")?,
+                    down: parse_arg(args[3], "down interval")?,
+                    features,
+                })
+            }
+            "delay" => {
+                ensure!(
+                    matches!(args.len(), 3 | 6 | 9),
+                    "delay expects 3, 6 or 9 arguments, got {}",
+                    args.len()
+                );
+                let mut devices = args
+                    .chunks(3)
+                    .map(|d| {
+                        Ok(DelayedDevice {
+                            device: d[0].into(),
+                            offset: parse_arg(d[1], "offset")?,
+                            delay_ms: parse_arg(d[2], "delay")?,
+                        })
+                    })
+                    .collect::<Result<Vec<_>>>()?
+                    .into_iter();
+                Target::Delay(Delay {
+                    read: devices.next().expect("there are at least 3 arguments"),
+                    write: devices.next(),
+                    flush: devices.next(),
+                })
+            }
+            "dust" => {
+                expect(3)?;
+                Target::Dust {
+                    device: args[0].into(),
+                    offset: parse_arg(args[1], "offset")?,
+                    block_size: parse_arg(args[2], "block size")?,
+                }
+            }
+            _ => bail!("unsupported target type `{name}`"),
+        })
+    }
+
+    /// Check the arguments that the kernel would reject.
+    fn validate(&self) -> Result<()> {
+        match self {
+            Target::Delay(delay) => ensure!(
+                delay.flush.is_none() || delay.write.is_some(),
+                "a delay target with a flush device needs a write device"
+            ),
+            Target::Dust { block_size, .. } => ensure!(
+                block_size.is_power_of_two() && *block_size >= 512,
+                "the block size of a dust target must be a power of 2 of at least 512, not {block_size}"
+            ),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Returns whether a segment of `length` sectors mapped to this target followed by a segment
+    /// mapped to `next` can be a single segment.
+    ///
+    /// Only linear, error and zero targets are merged, the others keep state per target (e.g.
+    /// the intervals of flakey, or the bad blocks of dust).
+    fn continues(&self, length: u64, next: &Target) -> bool {
+        match (self, next) {
+            (
+                Target::Linear { device, offset },
+                Target::Linear {
+                    device: next_device,
+                    offset: next_offset,
+                },
+            ) => device == next_device && offset.checked_add(length) == Some(*next_offset),
+            (Target::Error, Target::Error) | (Target::Zero, Target::Zero) => true,
+            _ => false,
+        }
+    }
+}
+
+impl FlakeyFeature {
+    fn args(&self) -> String {
+        match self {
+            FlakeyFeature::DropWrites => "drop_writes".into(),
+            FlakeyFeature::ErrorWrites => "error_writes".into(),
+            FlakeyFeature::ErrorReads => "error_reads".into(),
+            FlakeyFeature::CorruptBioByte {
+                nth_byte,
+                direction,
+                value,
+                flags,
+            } => {
+                let direction = match direction {
+                    BioDirection::Read => 'r',
+                    BioDirection::Write => 'w',
+                };
+                format!("corrupt_bio_byte {nth_byte} {direction} {value} {flags}")
+            }
+        }
+    }
+
+    /// Parse the feature arguments of a flakey target.
+    fn parse(mut args: &[&str]) -> Result<Vec<Self>> {
+        let mut features = Vec::new();
+        while let Some((&feature, rest)) = args.split_first() {
+            args = rest;
+            features.push(match feature {
+                "drop_writes" => FlakeyFeature::DropWrites,
+                "error_writes" => FlakeyFeature::ErrorWrites,
+                "error_reads" => FlakeyFeature::ErrorReads,
+                "corrupt_bio_byte" => {
+                    let [nth_byte, direction, value, flags, rest @ ..] = args else {
+                        bail!("corrupt_bio_byte expects 4 arguments");
+                    };
+                    args = rest;
+                    FlakeyFeature::CorruptBioByte {
+                        nth_byte: parse_arg(nth_byte, "byte")?,
+                        direction: match *direction {
+                            "r" => BioDirection::Read,
+                            "w" => BioDirection::Write,
+                            _ => bail!("invalid corrupt_bio_byte direction `{direction}`"),
+                        },
+                        value: parse_arg(value, "byte value")?,
+                        flags: parse_arg(flags, "bio flags")?,
+                    }
+                }
+                _ => bail!("unsupported flakey feature `{feature}`"),
+            });
+        }
+        Ok(features)
+    }
+}
+
+fn parse_arg<T: FromStr>(arg: &str, what: &str) -> Result<T>
+where
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    arg.parse().context(format!("invalid {what} `{arg}`"))
+}
+
+/// A range of sectors of the mapped device, and what it is mapped to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Segment {
+    pub sectors: Range<u64>,
+    pub target: Target,
+}
+
+impl Segment {
+    /// Returns the line of the segment in the form `devicemapper::DM::table_load` takes.
+    pub fn to_table_line(&self) -> TableLine {
+        (
+            self.sectors.start,
+            self.sectors.end - self.sectors.start,
+            self.target.name().into(),
+            self.target.args(),
+        )
+    }
+}
+
+impl fmt::Display for Segment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (start, length, name, args) = self.to_table_line();
+        write!(f, "{start} {length} {name}")?;
+        if !args.is_empty() {
+            write!(f, " {args}")?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Segment {
+    type Err = anyhow::Error;
+
+    /// Parse a line of a table, e.g. `0 2048 linear 8:16 0`.
+    fn from_str(line: &str) -> Result<Self> {
+        let words = line.split_whitespace().collect::<Vec<_>>();
+        let [start, length, name, args @ ..] = words.as_slice() else {
+            bail!("`{line}` is not a table line: <start> <length> <target> [<arguments>]");
+        };
+        let start = parse_arg::<u64>(start, "start sector")?;
+        let length = parse_arg::<u64>(length, "length")?;
+        let end = start
+            .checked_add(length)
+            .context(format!("{length} sectors at sector {start} overflow a u64"))?;
+        Ok(Segment {
+            sectors: start..end,
+            target: Target::parse(name, args).context(format!("invalid {name} target"))?,
+        })
+    }
+}
+
+/// A device-mapper table: segments that map the sectors of a device from sector 0, without
+/// gaps or overlaps.
+///
+/// Build one with [`DmTable::builder`], or parse the output of `dmsetup table`.
+///
+/// # Examples
+/// ```rust
+/// use ff::devicemapper::DmTable;
+///
+/// // fail 8 sectors in the middle of a device
+/// let table = DmTable::builder()
+///     .linear(0..1024, "/dev/sdb", 0)
+///     .error(1024..1032)
+///     .linear(1032..2048, "/dev/sdb", 1032)
+///     .build()
+///     .unwrap();
+/// assert_eq!(
+///     table.to_string(),
+///     "0 1024 linear /dev/sdb 0\n1024 8 error\n1032 1016 linear /dev/sdb 1032\n"
+/// );
+/// assert_eq!(table.to_string().parse::<DmTable>().unwrap(), table);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DmTable {
+    segments: Vec<Segment>,
+}
+
+impl DmTable {
+    pub fn builder() -> DmTableBuilder {
+        DmTableBuilder::default()
+    }
+
+    /// Returns the segments, in order.
+    pub fn segments(&self) -> &[Segment] {
+        &self.segments
+    }
+
+    /// Returns the number of sectors of the mapped device.
+    pub fn sectors(&self) -> u64 {
+        self.segments.last().map_or(0, |s| s.sectors.end)
+    }
+
+    /// Returns the table in the form `devicemapper::DM::table_load` takes.
+    pub fn to_table_lines(&self) -> Vec<TableLine> {
+        self.segments.iter().map(Segment::to_table_line).collect()
+    }
+}
+
+impl fmt::Display for DmTable {
+    /// Format the table the way `dmsetup table` prints it, a line per segment.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for segment in &self.segments {
+            writeln!(f, "{segment}")?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for DmTable {
+    type Err = anyhow::Error;
+
+    /// Parse a table the way `dmsetup table` prints it (or `dmsetup create` reads it), a line
+    /// per segment. The table is validated as [`DmTableBuilder::build`] does.
+    fn from_str(table: &str) -> Result<Self> {
+        let mut builder = DmTable::builder();
+        for (i, line) in table.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let segment = line
+                .parse::<Segment>()
+                .context(format!("invalid table line {}", i + 1))?;
+            builder = builder.target(segment.sectors, segment.target);
+        }
+        builder.build()
+    }
+}
+
+/// Builds a [`DmTable`] from segments added in any order, see [`DmTableBuilder::build`].
+#[derive(Debug, Clone, Default)]
+pub struct DmTableBuilder {
+    segments: Vec<Segment>,
+}
+
+impl DmTableBuilder {
+    /// Map `sectors` of the mapped device to `target`.
+    pub fn target(mut self, sectors: Range<u64>, target: Target) -> Self {
+        self.segments.push(Segment { sectors, target });
+        self
+    }
+
+    /// Map `sectors` to `device` from sector `offset`.
+    pub fn linear(self, sectors: Range<u64>, device: impl Into<String>, offset: u64) -> Self {
+        let device = device.into();
+        self.target(sectors, Target::Linear { device, offset })
+    }
+
+    /// Fail every I/O to `sectors`.
+    pub fn error(self, sectors: Range<u64>) -> Self {
+        self.target(sectors, Target::Error)
+    }
+
+    /// Read zeros from `sectors` and discard writes to them.
+    pub fn zero(self, sectors: Range<u64>) -> Self {
+        self.target(sectors, Target::Zero)
+    }
+
+    /// Map `sectors` to a flakey device.
+    pub fn flakey(self, sectors: Range<u64>, flakey: Flakey) -> Self {
+        self.target(sectors, Target::Flakey(flakey))
+    }
+
+    /// Map `sectors` to delayed devices.
+    pub fn delay(self, sectors: Range<u64>, delay: Delay) -> Self {
+        self.target(sectors, Target::Delay(delay))
+    }
+
+    /// Map `sectors` to `device` from sector `offset`, with a bad block list of blocks of
+    /// `block_size` bytes.
+    pub fn dust(
+        self,
+        sectors: Range<u64>,
+        device: impl Into<String>,
+        offset: u64,
+        block_size: u32,
+    ) -> Self {
+        let device = device.into();
+        self.target(
+            sectors,
+            Target::Dust {
+                device,
+                offset,
+                block_size,
+            },
+        )
+    }
+
+    /// Sort the segments, check that they map the device from sector 0 without gaps or
+    /// overlaps, and merge adjacent segments that map to the same target, e.g. two linear
+    /// segments that continue each other on the same device.
+    pub fn build(mut self) -> Result<DmTable> {
+        ensure!(!self.segments.is_empty(), "the table has no segments");
+        self.segments.sort_by_key(|s| s.sectors.start);
+
+        let mut segments: Vec<Segment> = Vec::with_capacity(self.segments.len());
+        for segment in self.segments {
+            let sectors = &segment.sectors;
+            ensure!(
+                sectors.start < sectors.end,
+                "segment {}..{} is empty",
+                sectors.start,
+                sectors.end
+            );
+            segment.target.validate()?;
+
+            let Some(last) = segments.last_mut() else {
+                ensure!(
+                    sectors.start == 0,
+                    "the table must start at sector 0, not {}",
+                    sectors.start
+                );
+                segments.push(segment);
+                continue;
+            };
+            ensure!(
+                sectors.start >= last.sectors.end,
+                "segments {}..{} and {}..{} overlap",
+                last.sectors.start,
+                last.sectors.end,
+                sectors.start,
+                sectors.end
+            );
+            ensure!(
+                sectors.start == last.sectors.end,
+                "sectors {}..{} are not mapped",
+                last.sectors.end,
+                sectors.start
+            );
+
+            let length = last.sectors.end - last.sectors.start;
+            if last.target.continues(length, &segment.target) {
+                last.sectors.end = segment.sectors.end;
+            } else {
+                segments.push(segment);
+            }
+        }
+
+        Ok(DmTable { segments })
+    }
+}
 
 /// Build a DM table that passes everything through linearly except a bad block ranges.
 pub fn dm_table_for_bad_range(
     device: PathBuf,
     total_blocks: u64,
     bad: Option<&[Range<u64>]>,
-) -> Vec<Segment> {
+) -> Vec<TableLine> {
     let device = device.to_string_lossy();
-    let sector_size = 512;
-    let fs_block_size = 1024;
-    assert!(sector_size > 0 && fs_block_size > 0);
-    assert!(
-        fs_block_size % sector_size == 0,
-        "fs_block_size must be a multiple of sector_size"
-    );
 
-    let Some(bad) = bad else {
-        return vec![(0, total_blocks, "linear".into(), format!("{device} 0"))];
-    };
     assert!(
-        !bad.is_empty(),
+        bad.is_none_or(|bad| !bad.is_empty()),
         "the list of bad ranges cannot be empty, pass None instead"
     );
-
-    let mut table = Vec::with_capacity(3);
-
-    let mut bad = bad.to_vec();
-    bad.sort_by_key(|r| r.start);
-
-    let bad_start = bad.first().unwrap().start;
-    let bad_end = bad.last().unwrap().end;
-
-    let mut linear_start = 0;
-    let mut alloc_lin_dev = |len: u64| {
-        let ret = format!("{device} {}", linear_start);
-        linear_start += len;
-        ret
-    };
-
-    // map [0 .. bad_start) to the start of `device`
-    if bad_start != 0 {
-        table.push((0, bad_start, "linear".into(), alloc_lin_dev(bad_start)));
+    // an empty device has no segments, which is not a valid table.
+    if total_blocks == 0 && bad.is_none() {
+        return vec![(0, 0, "linear".into(), format!("{device} 0"))];
     }
+    let mut bad = bad.map(<[_]>::to_vec).unwrap_or_default();
+    bad.sort_by_key(|r| r.start);
 
-    let mut last_range = bad.first().unwrap().to_owned();
-
+    let mut builder = DmTable::builder();
+    // the first block that is not mapped yet, and the next block of `device` to map.
+    let (mut next, mut linear_start) = (0, 0);
     for r in bad {
         assert!(
             r.start <= total_blocks,
@@ -67,41 +600,36 @@ pub fn dm_table_for_bad_range(
             "the start of the bad range is not less than the end"
         );
 
-        if last_range.start != r.start && last_range.end != r.start {
-            table.push((
-                last_range.end,
-                r.start - last_range.end, // length
-                "linear".into(),
-                alloc_lin_dev(r.start - last_range.end),
-            ));
+        // map [next .. r.start) to `device`
+        if r.start > next {
+            builder = builder.linear(next..r.start, device.clone(), linear_start);
+            linear_start += r.start - next;
+        }
+        // map [r.start .. r.end) to an `error` segment, bad ranges may overlap.
+        if r.end > next {
+            builder = builder.error(r.start.max(next)..r.end);
+            next = r.end;
         }
-        // map [r.start .. r.end) to an `error` segment.
-        table.push((
-            r.start,
-            r.end - r.start, // length
-            "error".into(),
-            String::new(),
-        ));
-
-        last_range = r;
     }
 
-    // map [bad_end .. total) `device` after the `error` segments
-    if bad_end != total_blocks {
-        table.push((
-            bad_end,
-            total_blocks - bad_end,
-            "linear".into(),
-            alloc_lin_dev(total_blocks - bad_end),
-        ));
+    // map [next .. total) to `device` after the `error` segments
+    if next < total_blocks {
+        builder = builder.linear(next..total_blocks, device, linear_start);
     }
 
-    table
+    builder
+        .build()
+        .expect("bad ranges within the device make a valid table")
+        .to_table_lines()
 }
 
 #[cfg(test)]
+#[allow(clippy::single_range_in_vec_init)]
 mod test {
-    use super::dm_table_for_bad_range;
+    use super::{
+        BioDirection, Delay, DelayedDevice, DmTable, Flakey, FlakeyFeature, Target,
+        dm_table_for_bad_range,
+    };
 
     #[test]
     pub fn it_creates_a_full_linear_table() {
@@ -118,6 +646,15 @@ mod test {
         )
     }
 
+    #[test]
+    pub fn it_creates_a_linear_table_for_an_empty_device() {
+        let table = dm_table_for_bad_range("/dev/test".into(), 0, None);
+        assert_eq!(
+            table,
+            vec![(0, 0, "linear".to_string(), "/dev/test 0".to_string())]
+        )
+    }
+
     #[test]
     pub fn it_creates_a_table_with_an_error_segment() {
         let total_blocks = 15000;
@@ -238,4 +775,208 @@ mod test {
             ]
         );
     }
+
+    #[test]
+    fn adjacent_and_overlapping_errors_are_merged() {
+        let total_blocks = 100;
+        let table = dm_table_for_bad_range(
+            "/dev/test".into(),
+            total_blocks,
+            Some(&[20..30, 10..20, 50..60, 55..70, 58..65]),
+        );
+        assert_eq!(
+            table,
+            vec![
+                (0, 10, "linear".into(), "/dev/test 0".into()),
+                (10, 20, "error".into(), "".into()),
+                (30, 20, "linear".into(), "/dev/test 10".into()),
+                (50, 20, "error".into(), "".into()),
+                (70, 30, "linear".into(), "/dev/test 30".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn builder_merges_adjacent_segments() {
+        let table = DmTable::builder()
+            .error(30..40)
+            .linear(10..20, "/dev/test", 110)
+            .linear(0..10, "/dev/test", 100)
+            .error(20..30)
+            .linear(40..50, "/dev/test", 120)
+            .zero(50..60)
+            .zero(60..70)
+            .build()
+            .unwrap();
+        assert_eq!(
+            table.to_table_lines(),
+            vec![
+                (0, 20, "linear".into(), "/dev/test 100".into()),
+                (20, 20, "error".into(), "".into()),
+                // does not continue the first linear segment on the device.
+                (40, 10, "linear".into(), "/dev/test 120".into()),
+                (50, 20, "zero".into(), "".into()),
+            ]
+        );
+        assert_eq!(table.sectors(), 70);
+    }
+
+    #[test]
+    fn builder_keeps_stateful_targets_apart() {
+        let flakey = Flakey {
+            device: "/dev/test".into(),
+            offset: 0,
+            up: 1,
+            down: 1,
+            features: vec![],
+        };
+        let table = DmTable::builder()
+            .flakey(0..10, flakey.clone())
+            .flakey(
+                10..20,
+                Flakey {
+                    offset: 10,
+                    ..flakey
+                },
+            )
+            .dust(20..30, "/dev/test", 20, 512)
+            .dust(30..40, "/dev/test", 30, 512)
+            .build()
+            .unwrap();
+        assert_eq!(table.segments().len(), 4);
+    }
+
+    #[test]
+    fn builder_rejects_invalid_tables() {
+        let err = |builder: super::DmTableBuilder| builder.build().unwrap_err().to_string();
+
+        assert_eq!(err(DmTable::builder()), "the table has no segments");
+        assert_eq!(
+            err(DmTable::builder().error(0..10).zero(5..20)),
+            "segments 0..10 and 5..20 overlap"
+        );
+        assert_eq!(
+            err(DmTable::builder().error(0..10).zero(12..20)),
+            "sectors 10..12 are not mapped"
+        );
+        assert_eq!(
+            err(DmTable::builder().error(5..10)),
+            "the table must start at sector 0, not 5"
+        );
+        assert_eq!(
+            err(DmTable::builder().error(0..10).zero(10..10)),
+            "segment 10..10 is empty"
+        );
+        assert_eq!(
+            err(DmTable::builder().dust(0..10, "/dev/test", 0, 1000)),
+            "the block size of a dust target must be a power of 2 of at least 512, not 1000"
+        );
+
+        let device = DelayedDevice {
+            device: "/dev/test".into(),
+            offset: 0,
+            delay_ms: 10,
+        };
+        let delay = Delay {
+            read: device.clone(),
+            write: None,
+            flush: Some(device),
+        };
+        assert_eq!(
+            err(DmTable::builder().delay(0..10, delay)),
+            "a delay target with a flush device needs a write device"
+        );
+    }
+
+    #[test]
+    fn table_round_trips_through_text() {
+        let device = |delay_ms| DelayedDevice {
+            device: "8:16".into(),
+            offset: 0,
+            delay_ms,
+        };
+        let table = DmTable::builder()
+            .linear(0..100, "8:16", 0)
+            .error(100..108)
+            .zero(108..116)
+            .flakey(
+                116..200,
+                Flakey {
+                    device: "8:16".into(),
+                    offset: 116,
+                    up: 5,
+                    down: 2,
+                    features: vec![
+                        FlakeyFeature::DropWrites,
+                        FlakeyFeature::CorruptBioByte {
+                            nth_byte: 32,
+                            direction: BioDirection::Read,
+                            value: 1,
+                            flags: 0,
+                        },
+                    ],
+                },
+            )
+            .delay(
+                200..300,
+                Delay {
+                    read: device(0),
+                    write: Some(device(100)),
+                    flush: None,
+                },
+            )
+            .dust(300..400, "8:16", 300, 4096)
+            .build()
+            .unwrap();
+
+        let text = "0 100 linear 8:16 0
+100 8 error
+108 8 zero
+116 84 flakey 8:16 116 5 2 6 drop_writes corrupt_bio_byte 32 r 1 0
+200 100 delay 8:16 0 0 8:16 0 100
+300 100 dust 8:16 300 4096
+";
+        assert_eq!(table.to_string(), text);
+        assert_eq!(text.parse::<DmTable>().unwrap(), table);
+    }
+
+    #[test]
+    fn parses_dmsetup_table() {
+        let table = "0 2048 flakey 7:0 0 0 10\n\n2048 2048 flakey 7:0 2048 1 1 1 error_reads\n"
+            .parse::<DmTable>()
+            .unwrap();
+        assert_eq!(table.sectors(), 4096);
+        let Target::Flakey(flakey) = &table.segments()[1].target else {
+            panic!("expected a flakey target");
+        };
+        assert_eq!(flakey.features, vec![FlakeyFeature::ErrorReads]);
+
+        // linear segments whose offsets would overflow are not merged.
+        let table = "0 10 linear 7:0 18446744073709551615\n10 10 linear 7:0 9\n"
+            .parse::<DmTable>()
+            .unwrap();
+        assert_eq!(table.segments().len(), 2);
+
+        let err = |table: &str| format!("{:#}", table.parse::<DmTable>().unwrap_err());
+        assert_eq!(
+            err("0 10 crypt aes 0 7:0 0"),
+            "invalid table line 1: invalid crypt target: unsupported target type `crypt`"
+        );
+        assert_eq!(
+            err("0 10 error\n10 10 flakey 7:0 0 1 1 2 drop_writes"),
+            "invalid table line 2: invalid flakey target: flakey expects 2 feature arguments, got 1"
+        );
+        assert_eq!(
+            err("0 10 linear 7:0"),
+            "invalid table line 1: invalid linear target: linear expects 2 arguments, got 1"
+        );
+        assert_eq!(
+            err("18446744073709551615 2 error"),
+            "invalid table line 1: 2 sectors at sector 18446744073709551615 overflow a u64"
+        );
+        assert_eq!(
+            err("0 ten error"),
+            "invalid table line 1: invalid length `ten`: invalid digit found in string"
+        );
+    }
 }
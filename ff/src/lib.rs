@@ -5,8 +5,10 @@ use nix::sys::utsname::uname;
 use statistical::{mean, median, standard_deviation};
 
 pub mod args;
+pub mod cgroup;
 pub mod devicemapper;
 pub mod fs;
+pub mod maps;
 pub mod mount;
 pub mod pagemap;
 
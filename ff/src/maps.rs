@@ -0,0 +1,204 @@
+//! Memory mappings of a process, from `/proc/PID/maps`.
+use crate::pagemap::{PageCategory, scan_page_map};
+use anyhow::{Context, Result, anyhow};
+use nix::unistd::Pid;
+use std::{
+    fs::{File, OpenOptions},
+    ops::Range,
+    path::PathBuf,
+    str::FromStr,
+};
+
+/// A single line of `/proc/PID/maps`, see `man 5 proc_pid_maps`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MapEntry {
+    /// Start address of the mapping.
+    pub start: u64,
+    /// End address of the mapping (exclusive).
+    pub end: u64,
+    /// Permissions, e.g. `r-xp` or `rw-s`.
+    pub perms: String,
+    /// Offset into the mapped file, in bytes.
+    pub offset: u64,
+    /// Major and minor device number of the mapped file.
+    pub dev: (u32, u32),
+    /// Inode of the mapped file, zero for anonymous mappings.
+    pub inode: u64,
+    /// The mapped file, or a pseudo-path such as `[heap]`. Deleted files end with ` (deleted)`.
+    pub pathname: Option<String>,
+}
+
+impl MapEntry {
+    /// Returns true if the mapping is backed by a file.
+    pub fn is_file(&self) -> bool {
+        self.inode != 0
+    }
+
+    /// Returns true if the mapping is shared (`MAP_SHARED`).
+    pub fn is_shared(&self) -> bool {
+        self.perms.ends_with('s')
+    }
+
+    /// Returns the virtual pages of the mapping, as indices into `/proc/PID/pagemap`.
+    pub fn virtual_pages(&self, vm_page_size: u64) -> Range<u64> {
+        self.start / vm_page_size..self.end / vm_page_size
+    }
+
+    /// Returns the file pages covered by the mapping, they may extend past the end of the file.
+    pub fn file_pages(&self, vm_page_size: u64) -> Range<u64> {
+        let start = self.offset / vm_page_size;
+        start..start + (self.end - self.start) / vm_page_size
+    }
+
+    /// Returns the runs of file pages of the mapping that process `pid` (or this process if
+    /// `None`) wrote to since its soft-dirty bits were cleared, see [`clear_soft_dirty`].
+    ///
+    /// [`clear_soft_dirty`]: crate::pagemap::clear_soft_dirty
+    ///
+    /// Only writes through a shared mapping reach the file, a private mapping writes to
+    /// anonymous copies of the pages, so private mappings have no written file pages. A
+    /// mapping created after the soft-dirty bits were cleared counts as written as a whole.
+    pub fn written_pages(&self, pid: Option<Pid>, vm_page_size: u64) -> Result<Vec<Range<u64>>> {
+        if !self.is_shared() {
+            return Ok(vec![]);
+        }
+
+        let virtual_pages = self.virtual_pages(vm_page_size);
+        let file_pages = self.file_pages(vm_page_size);
+        Ok(scan_page_map(
+            pid,
+            virtual_pages.clone(),
+            PageCategory::SOFT_DIRTY | PageCategory::FILE,
+        )?
+        .into_iter()
+        .map(|pages| {
+            pages.start - virtual_pages.start + file_pages.start
+                ..pages.end - virtual_pages.start + file_pages.start
+        })
+        .collect())
+    }
+
+    /// Open the mapped file of process `pid`.
+    ///
+    /// The file is opened through `/proc/PID/map_files`, which also works for deleted files and
+    /// files in another mount namespace, and falls back to the pathname otherwise.
+    pub fn open(&self, pid: Pid) -> Result<File> {
+        let map_file = format!("/proc/{pid}/map_files/{:x}-{:x}", self.start, self.end);
+        if let Ok(file) = OpenOptions::new().read(true).open(&map_file) {
+            return Ok(file);
+        }
+
+        let path = self
+            .pathname
+            .as_ref()
+            .filter(|_| self.is_file())
+            .ok_or(anyhow!(
+                "{:x}-{:x} is not a file mapping",
+                self.start,
+                self.end
+            ))?;
+        OpenOptions::new()
+            .read(true)
+            .open(path)
+            .context(format!("failed to open {path}"))
+    }
+
+    /// Returns the path of the mapped file, without the ` (deleted)` suffix.
+    pub fn path(&self) -> Option<PathBuf> {
+        self.pathname
+            .as_ref()
+            .map(|p| PathBuf::from(p.strip_suffix(" (deleted)").unwrap_or(p)))
+    }
+}
+
+impl FromStr for MapEntry {
+    type Err = anyhow::Error;
+
+    /// Parse a line of `/proc/PID/maps`, e.g.
+    /// `7f1c2a000000-7f1c2a021000 r--p 00000000 08:01 1052 /usr/lib/libc.so.6`
+    fn from_str(line: &str) -> Result<Self> {
+        let invalid = || anyhow!("`{line}` is not a valid maps entry");
+        let mut fields = line.splitn(6, ' ');
+        let mut next = || fields.next().ok_or_else(invalid);
+
+        let (start, end) = next()?.split_once('-').ok_or_else(invalid)?;
+        let perms = next()?.to_owned();
+        let offset = next()?;
+        let (major, minor) = next()?.split_once(':').ok_or_else(invalid)?;
+        let inode = next()?;
+        // the pathname is padded with spaces, and is missing for anonymous mappings.
+        let pathname = fields
+            .next()
+            .map(str::trim_start)
+            .filter(|p| !p.is_empty())
+            .map(str::to_owned);
+
+        let hex = |s: &str| u64::from_str_radix(s, 16).map_err(|_| invalid());
+        Ok(MapEntry {
+            start: hex(start)?,
+            end: hex(end)?,
+            perms,
+            offset: hex(offset)?,
+            dev: (
+                u32::from_str_radix(major, 16).map_err(|_| invalid())?,
+                u32::from_str_radix(minor, 16).map_err(|_| invalid())?,
+            ),
+            inode: inode.parse().map_err(|_| invalid())?,
+            pathname,
+        })
+    }
+}
+
+/// Returns the memory mappings of process `pid`.
+pub fn read_maps(pid: Pid) -> Result<Vec<MapEntry>> {
+    let path = format!("/proc/{pid}/maps");
+    std::fs::read_to_string(&path)
+        .context(format!("unable to read {path}"))?
+        .lines()
+        .map(MapEntry::from_str)
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{MapEntry, read_maps};
+    use nix::unistd::Pid;
+
+    #[test]
+    fn test_parse_map_entry() {
+        let entry: MapEntry = "7f1c2a000000-7f1c2a021000 r--s 00002000 08:1f 1052                       /var/lib/db/data file"
+            .parse()
+            .unwrap();
+        assert_eq!(entry.start, 0x7f1c2a000000);
+        assert_eq!(entry.end, 0x7f1c2a021000);
+        assert!(entry.is_file() && entry.is_shared());
+        assert_eq!(entry.offset, 0x2000);
+        assert_eq!(entry.dev, (8, 31));
+        assert_eq!(entry.inode, 1052);
+        assert_eq!(entry.pathname.as_deref(), Some("/var/lib/db/data file"));
+        assert_eq!(entry.file_pages(4096), 2..35);
+
+        let entry: MapEntry = "7ffd8e1f0000-7ffd8e211000 rw-p 00000000 00:00 0 "
+            .parse()
+            .unwrap();
+        assert!(!entry.is_file() && !entry.is_shared());
+        assert_eq!(entry.pathname, None);
+
+        let entry: MapEntry = "1000-2000 rw-s 00000000 00:01 7 /memfd:db (deleted)"
+            .parse()
+            .unwrap();
+        assert_eq!(entry.path().unwrap().to_str(), Some("/memfd:db"));
+
+        assert!("1000-2000 rw-s".parse::<MapEntry>().is_err());
+    }
+
+    #[test]
+    fn test_read_maps() {
+        let maps = read_maps(Pid::this()).unwrap();
+        let exe = std::env::current_exe().unwrap();
+        assert!(
+            maps.iter()
+                .any(|m| m.is_file() && m.path() == Some(exe.clone()))
+        );
+    }
+}
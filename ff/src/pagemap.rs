@@ -1,20 +1,37 @@
 //! inspect file page maps.
-use anyhow::{Context, Result, ensure};
+use anyhow::{Context, Result, bail, ensure};
 use bitflags::bitflags;
+pub use fiemap::FiemapExtentFlags;
 use nix::{
+    errno::Errno,
     libc::{
-        MADV_RANDOM, MAP_FAILED, MAP_PRIVATE, MAP_SHARED, POSIX_FADV_DONTNEED, PROT_NONE,
-        PROT_READ, madvise, mincore, mmap64, munmap, posix_fadvise64,
+        MADV_COLD, MADV_PAGEOUT, MADV_RANDOM, MADV_WILLNEED, MAP_ANONYMOUS, MAP_FAILED,
+        MAP_POPULATE, MAP_PRIVATE, MAP_SHARED, POSIX_FADV_DONTNEED, POSIX_FADV_WILLNEED, PROT_NONE,
+        PROT_READ, SYNC_FILE_RANGE_WAIT_AFTER, SYNC_FILE_RANGE_WAIT_BEFORE, SYNC_FILE_RANGE_WRITE,
+        SYS_move_pages, c_int, c_long, c_ulong, c_void, ioctl, madvise, mincore, mlock, mmap64,
+        munmap, posix_fadvise64, readahead, sync_file_range, syscall,
     },
-    sys::statfs::fstatfs,
-    unistd::sysconf,
+    sys::statfs::{TMPFS_MAGIC, fstatfs},
+    unistd::{Pid, Whence, lseek, sysconf},
 };
 use std::{
+    collections::BTreeMap,
     fs::{File, OpenOptions},
+    ops::Range,
     os::{
         fd::{AsFd, AsRawFd},
-        unix::fs::FileExt,
+        unix::fs::{FileExt, FileTypeExt},
     },
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    KernelVersion,
+    args::{
+        coalesce_ranges, fmt_page_ranges, intersect_ranges, parse_page_ranges, ranges_len,
+        subtract_ranges,
+    },
+    cgroup,
 };
 
 /// Convert the result of a C function to [`std::io::Error`]. A non-zero return is an error.
@@ -59,7 +76,12 @@ bitflags! {
 }
 
 bitflags! {
-   #[derive(Debug)]
+    /// A 64-bit entry in /proc/kpageflags
+    /// see: `man 5 proc_kpageflags` and `include/uapi/linux/kernel-page-flags.h`
+    ///
+    /// Bits 32 and up are exported to assist kernel hacking (`include/linux/kernel-page-flags.h`),
+    /// their meaning may change between releases, see [`KPageFlags::describe`].
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
     pub struct KPageFlags: u64 {
         const LOCKED        = 1 << 0;
         const ERROR         = 1 << 1;
@@ -87,270 +109,2425 @@ bitflags! {
         const BALLOON       = 1 << 23;
         const ZERO_PAGE     = 1 << 24;
         const IDLE          = 1 << 25;
+        const PGTABLE       = 1 << 26;
+
+        const RESERVED      = 1 << 32;
+        const MLOCKED       = 1 << 33;
+        /// `OWNER_2` since 6.12.
+        const MAPPEDTODISK  = 1 << 34;
+        const PRIVATE       = 1 << 35;
+        const PRIVATE_2     = 1 << 36;
+        const OWNER_PRIVATE = 1 << 37;
+        const ARCH          = 1 << 38;
+        const UNCACHED      = 1 << 39;
+        const SOFTDIRTY     = 1 << 40;
+        const ARCH_2        = 1 << 41;
     }
 }
 
-pub trait PageMapExt {
-    fn page_info(&self, page: u64) -> Result<(PageMapEntry, KPageFlags)>;
-    fn evict_pages(&self) -> Result<()>;
-    fn cached_pages(&self) -> Result<Vec<u64>>;
-    fn fs_block_size(&self) -> Result<u64>;
-    fn vm_pages_count(&self) -> Result<u64>;
+impl KPageFlags {
+    /// The flags that are only exported to assist kernel hacking.
+    pub const KERNEL_HACKING: KPageFlags = KPageFlags::from_bits_retain(!0 << 32);
+
+    /// Returns the name and a short description of every bit that is set, as interpreted by
+    /// `kernel`. Unknown bits are named `BIT_<n>`.
+    ///
+    /// Some bits were repurposed: 23 is `OFFLINE` since 5.0 (it was `BALLOON`), and 34 is
+    /// `OWNER_2` since 6.12 (it was `MAPPEDTODISK`).
+    pub fn describe(self, kernel: &KernelVersion) -> Vec<(String, &'static str)> {
+        (0..u64::BITS)
+            .filter(|bit| self.bits() & (1 << bit) != 0)
+            .map(|bit| {
+                let (name, description) = match Self::from_bits_retain(1 << bit) {
+                    Self::BALLOON if kernel.at_least(5, 0) => {
+                        ("OFFLINE", "logically offline, e.g. inflated in a balloon")
+                    }
+                    Self::MAPPEDTODISK if kernel.at_least(6, 12) => {
+                        ("OWNER_2", "owner defined flag (PG_owner_2)")
+                    }
+                    flag => match flag.iter_names().next() {
+                        Some((name, _)) => (name, flag.description()),
+                        None => return (format!("BIT_{bit}"), "unknown flag"),
+                    },
+                };
+                (name.to_owned(), description)
+            })
+            .collect()
+    }
+
+    /// A short description of a single known flag, from `man 5 proc_kpageflags`.
+    fn description(self) -> &'static str {
+        match self {
+            Self::LOCKED => "locked for exclusive access, e.g. under read/write IO",
+            Self::ERROR => "an IO error occurred",
+            Self::REFERENCED => "referenced since the last LRU list enqueue/requeue",
+            Self::UPTODATE => "has up-to-date data",
+            Self::DIRTY => "written to, contains new data",
+            Self::LRU => "in one of the LRU lists",
+            Self::ACTIVE => "in the active LRU list",
+            Self::SLAB => "managed by the slab allocator",
+            Self::WRITEBACK => "being written back to disk",
+            Self::RECLAIM => "will be reclaimed after its pageout IO completes",
+            Self::BUDDY => "a free block of the buddy allocator",
+            Self::MMAP => "mapped in a page table",
+            Self::ANON => "anonymous memory, not part of a file",
+            Self::SWAPCACHE => "has an associated swap entry",
+            Self::SWAPBACKED => "backed by swap or RAM (shmem, tmpfs, anonymous)",
+            Self::COMPOUND_HEAD => "first page of a compound page (large folio)",
+            Self::COMPOUND_TAIL => "other page of a compound page (large folio)",
+            Self::HUGE => "part of a HugeTLB page",
+            Self::UNEVICTABLE => "in the unevictable list (mlocked, ramfs, SHM_LOCK)",
+            Self::HWPOISON => "hardware detected memory corruption",
+            Self::NOPAGE => "no page frame exists at the address",
+            Self::KSM => "shared between processes by KSM",
+            Self::THP => "part of a transparent huge page",
+            Self::BALLOON => "part of a balloon",
+            Self::ZERO_PAGE => "the zero page, or the huge zero page",
+            Self::IDLE => "not accessed since it was marked idle",
+            Self::PGTABLE => "used as a page table",
+            Self::RESERVED => "reserved, e.g. the kernel image or firmware memory",
+            Self::MLOCKED => "locked in memory by mlock(2)",
+            Self::MAPPEDTODISK => "has blocks allocated on disk",
+            Self::PRIVATE => "has private data attached, e.g. buffer heads",
+            Self::PRIVATE_2 => "has a second private flag set, e.g. fscache",
+            Self::OWNER_PRIVATE => "owner defined flag (PG_owner_priv_1), e.g. PG_checked",
+            Self::ARCH => "architecture specific flag (PG_arch_1)",
+            Self::UNCACHED => "mapped uncached",
+            Self::SOFTDIRTY => "soft-dirty, see the SOFT_DIRTY bit of the page map entry",
+            Self::ARCH_2 => "architecture specific flag (PG_arch_2), e.g. MTE tags",
+            _ => "unknown flag",
+        }
+    }
 }
 
-impl PageMapExt for File {
-    fn fs_block_size(&self) -> Result<u64> {
-        let stats = fstatfs(self).context("failed to get stats for file")?;
-        Ok(stats.block_size() as u64)
+// cachestat(2) was added in 6.5 with the same number on all architectures (except alpha).
+// libc does not export it yet.
+const SYS_CACHESTAT: c_long = 451;
+
+/// `struct cachestat_range` from `include/uapi/linux/mman.h`
+#[repr(C)]
+struct CacheStatRange {
+    off: u64,
+    len: u64,
+}
+
+/// Page cache statistics of a file range, as returned by `cachestat(2)`.
+///
+/// All fields are in pages. see: `man 2 cachestat`
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CacheStat {
+    /// Number of pages in the page cache.
+    pub nr_cache: u64,
+    /// Number of dirty pages.
+    pub nr_dirty: u64,
+    /// Number of pages marked for writeback.
+    pub nr_writeback: u64,
+    /// Number of pages evicted from the page cache.
+    pub nr_evicted: u64,
+    /// Number of evicted pages that would have been in the working set had they not been
+    /// evicted (i.e. evicted recently).
+    pub nr_recently_evicted: u64,
+}
+
+/// Returns true if the running kernel supports `cachestat(2)` (Linux 6.5+).
+pub fn cachestat_supported() -> bool {
+    KernelVersion::current().at_least(6, 5)
+}
+
+// `_IOWR('f', 16, struct pm_scan_arg)` from `include/uapi/linux/fs.h`, added in 6.7.
+const PAGEMAP_SCAN: c_ulong = 0xc060_6610;
+
+// `_IO(0x00, 1)` and `_IO(0x00, 2)` from `include/uapi/linux/fs.h`, FIBMAP maps a file block to
+// a device block in the block size that FIGETBSZ returns.
+const FIBMAP: c_ulong = 1;
+const FIGETBSZ: c_ulong = 2;
+
+// `_IOR(0x12, 114, size_t)` from `include/uapi/linux/fs.h`, the size of a block device in bytes.
+const BLKGETSIZE64: c_ulong = 0x8008_1272;
+
+// `_IOWR('f', 11, struct fiemap)` from `include/uapi/linux/fs.h`.
+const FS_IOC_FIEMAP: c_ulong = 0xc020_660b;
+
+/// Number of extents to read with each `FS_IOC_FIEMAP` call.
+const FIEMAP_EXTENT_COUNT: usize = 32;
+
+/// `struct fiemap_extent` from `include/uapi/linux/fiemap.h`
+#[repr(C)]
+#[derive(Default, Clone, Copy)]
+struct FiemapExtentArg {
+    fe_logical: u64,
+    fe_physical: u64,
+    fe_length: u64,
+    fe_reserved64: [u64; 2],
+    fe_flags: u32,
+    fe_reserved: [u32; 3],
+}
+
+/// `struct fiemap` from `include/uapi/linux/fiemap.h`, with room for [`FIEMAP_EXTENT_COUNT`]
+/// extents.
+#[repr(C)]
+#[derive(Default)]
+struct FiemapArg {
+    fm_start: u64,
+    fm_length: u64,
+    fm_flags: u32,
+    fm_mapped_extents: u32,
+    fm_extent_count: u32,
+    fm_reserved: u32,
+    fm_extents: [FiemapExtentArg; FIEMAP_EXTENT_COUNT],
+}
+
+/// `struct pm_scan_arg` from `include/uapi/linux/fs.h`
+#[repr(C)]
+#[derive(Default)]
+struct PmScanArg {
+    size: u64,
+    flags: u64,
+    start: u64,
+    end: u64,
+    walk_end: u64,
+    vec: u64,
+    vec_len: u64,
+    max_pages: u64,
+    category_inverted: u64,
+    category_mask: u64,
+    category_anyof_mask: u64,
+    return_mask: u64,
+}
+
+/// `struct page_region` from `include/uapi/linux/fs.h`
+#[repr(C)]
+#[derive(Default, Clone, Copy)]
+struct PageRegion {
+    start: u64,
+    end: u64,
+    categories: u64,
+}
+
+bitflags! {
+    /// Page categories of the `PAGEMAP_SCAN` ioctl, see [`scan_page_map`].
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct PageCategory: u64 {
+        /// Write-protection through userfaultfd(2) is allowed.
+        const WPALLOWED  = 1 << 0;
+        /// The page is not write-protected through userfaultfd(2), i.e. it was written to.
+        const WRITTEN    = 1 << 1;
+        /// The page is a file page or shared anonymous page.
+        const FILE       = 1 << 2;
+        const PRESENT    = 1 << 3;
+        const SWAPPED    = 1 << 4;
+        /// The page is the shared zero page.
+        const PFNZERO    = 1 << 5;
+        const HUGE       = 1 << 6;
+        const SOFT_DIRTY = 1 << 7;
     }
+}
 
-    /// Returns the number of pages in a file.
-    fn vm_pages_count(&self) -> Result<u64> {
-        let vm_page = vm_page_size()?;
-        let len = self
-            .metadata()
-            .context("failed getting metadata for the file")?
-            .len();
+impl PageCategory {
+    /// Categories that can be derived from a [`PageMapEntry`] when `PAGEMAP_SCAN` is not
+    /// supported.
+    pub const FROM_PAGE_MAP: PageCategory = PageCategory::WRITTEN
+        .union(PageCategory::FILE)
+        .union(PageCategory::PRESENT)
+        .union(PageCategory::SWAPPED)
+        .union(PageCategory::SOFT_DIRTY);
 
-        Ok(len.div_ceil(vm_page as u64))
+    /// Returns the categories of the page behind `entry`, as `PAGEMAP_SCAN` would report them.
+    fn from_page_map(entry: &PageMapEntry) -> Self {
+        let mut categories = PageCategory::empty();
+        if entry.intersects(PageMapEntry::PRESENT | PageMapEntry::SWAPPED) {
+            categories.set(PageCategory::PRESENT, entry.contains(PageMapEntry::PRESENT));
+            categories.set(PageCategory::SWAPPED, entry.contains(PageMapEntry::SWAPPED));
+            categories.set(
+                PageCategory::WRITTEN,
+                !entry.contains(PageMapEntry::PTE_UFFD_WP_WR_PROTECTED),
+            );
+            categories.set(
+                PageCategory::FILE,
+                entry.contains(PageMapEntry::FILE_PAGE_OR_SHARED_ANON),
+            );
+            categories.set(
+                PageCategory::SOFT_DIRTY,
+                entry.contains(PageMapEntry::SOFT_DIRTY),
+            );
+        }
+        categories
     }
+}
 
-    /// Returns the page map entry and kernel flags for `page`.
+/// Returns true if the running kernel supports the `PAGEMAP_SCAN` ioctl (Linux 6.7+).
+///
+/// The ioctl is tried on the first page of this process rather than trusting the kernel
+/// version, it may have been backported, or disabled.
+pub fn pagemap_scan_supported() -> bool {
+    scan_page_map_ioctl(None, 0..1, PageCategory::PRESENT).is_ok_and(|runs| runs.is_some())
+}
+
+/// The page map entry and kernel page flags of a single file page.
+#[derive(Debug)]
+pub struct PageInfo {
+    /// Index of the page in the file.
+    pub page: u64,
+    pub pagemap: PageMapEntry,
+    pub kflags: KPageFlags,
+}
+
+/// An iterator over [`PageInfo`] records, see [`PageMapExt::pages_info`].
+pub struct PagesInfo {
+    inner: std::vec::IntoIter<PageInfo>,
+}
+
+impl Iterator for PagesInfo {
+    type Item = PageInfo;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.inner.size_hint()
+    }
+}
+
+impl ExactSizeIterator for PagesInfo {}
+
+/// A folio in the page cache: a single page, or a large folio (compound page) of a power of
+/// two number of pages, see [`PageMapExt::folios`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Folio {
+    /// The file pages of the folio.
+    pub pages: Range<u64>,
+    /// PFN of the first page.
+    pub pfn: u64,
+    /// Kernel page flags of the first page, the head page unless the folio is partial.
+    pub kflags: KPageFlags,
+    /// The folio starts before or ends after the inspected pages, so only part of it is known.
+    pub partial: bool,
+}
+
+impl Folio {
+    /// Returns the number of (known) pages in the folio.
+    pub fn nr_pages(&self) -> u64 {
+        self.pages.end - self.pages.start
+    }
+
+    /// Returns the order of the folio, i.e. it has `2^order` pages.
     ///
-    /// This will fault the page in to make it present.
-    fn page_info(&self, page: u64) -> Result<(PageMapEntry, KPageFlags)> {
-        let vm_page = vm_page_size()?;
-        let pages = self.vm_pages_count()?;
-        ensure!(
-            page < pages,
-            "page index {page} out of bounds; total pages = {pages}"
-        );
+    /// This is a lower bound for a partial folio.
+    pub fn order(&self) -> u32 {
+        self.nr_pages().ilog2()
+    }
 
-        // SAFETY: we have exclusive access to the file.
-        let mmap_address = unsafe {
-            mmap64(
-                std::ptr::null_mut(),
-                vm_page as _,
-                PROT_READ,
-                MAP_PRIVATE,
-                self.as_fd().as_raw_fd(),
-                (vm_page * page) as _,
-            )
+    /// Returns true if the folio is a transparent huge page.
+    pub fn is_thp(&self) -> bool {
+        self.kflags.contains(KPageFlags::THP)
+    }
+}
+
+/// Group `pages`, sorted by page index, into folios.
+///
+/// A page continues the folio of the previous page if it is a compound tail page, and both its
+/// index and its frame follow the previous page. A folio that starts with a tail page is
+/// partial, use [`PageMapExt::folios`] to also find folios that are cut at the end.
+pub fn group_folios(pages: &[PageInfo]) -> Result<Vec<Folio>> {
+    let mut folios: Vec<Folio> = Vec::new();
+    let mut last_pfn = None;
+
+    for info in pages {
+        let pfn = info
+            .pagemap
+            .pfn()?
+            .context(format!("the PFN for page {} is not present", info.page))?;
+        let tail = info.kflags.contains(KPageFlags::COMPOUND_TAIL);
+
+        match folios.last_mut() {
+            Some(folio)
+                if tail
+                    && folio.pages.end == info.page
+                    && last_pfn.and_then(|p: u64| p.checked_add(1)) == Some(pfn) =>
+            {
+                folio.pages.end += 1;
+            }
+            _ => folios.push(Folio {
+                pages: info.page..info.page + 1,
+                pfn,
+                kflags: info.kflags,
+                partial: tail,
+            }),
+        }
+        last_pfn = Some(pfn);
+    }
+
+    Ok(folios)
+}
+
+/// Returns the runs of `pages` that have data, using `lseek(2)` with `SEEK_DATA` and
+/// `SEEK_HOLE`.
+///
+/// Filesystems without hole support, and block devices, report the whole file as data.
+fn data_pages(file: &File, pages: Range<u64>) -> Result<Vec<Range<u64>>> {
+    // `blkdev_llseek` goes through `fixed_size_llseek`, which fails SEEK_DATA and SEEK_HOLE
+    // with EINVAL. A device has no holes, every block is data.
+    if file
+        .metadata()
+        .context("failed getting metadata for the file")?
+        .file_type()
+        .is_block_device()
+    {
+        return Ok(if pages.is_empty() {
+            vec![]
+        } else {
+            vec![pages]
+        });
+    }
+
+    let vm_page = vm_page_size()?;
+    let end = pages.end * vm_page;
+    let mut offset = pages.start * vm_page;
+    let mut runs = Vec::new();
+
+    while offset < end {
+        let data = match lseek(file, offset as i64, Whence::SeekData) {
+            Ok(data) => data as u64,
+            // no data past `offset`
+            Err(Errno::ENXIO) => break,
+            Err(e) => bail!("lseek(SEEK_DATA) failed at offset {offset}: {e}"),
         };
+        let hole = lseek(file, data as i64, Whence::SeekHole)
+            .context(format!("lseek(SEEK_HOLE) failed at offset {data}"))?
+            as u64;
 
-        ensure!(
-            mmap_address != MAP_FAILED,
-            "failed to mmap page `{}` for `{}`: {}",
-            page,
-            self.as_fd().as_raw_fd(),
-            std::io::Error::last_os_error()
-        );
+        let run = (data / vm_page).max(pages.start)..hole.div_ceil(vm_page).min(pages.end);
+        if !run.is_empty() {
+            runs.push(run);
+        }
+        offset = hole;
+    }
 
-        // Touching a non-present page (page fault) will trigger a readahead by the linux kernel, which
-        // will bring adjecent pages into cache.
-        //
-        // Note: The man page doesn't explicitly say that `MADV_RANDOM` is guaranteed to disable readahead,
-        // but it strongly suggets that. Also, at the time of writing this, the kernel function `do_sync_mmap_readahead` returns
-        // early if the VMA has the RAND_READ flag.
-        cvt!(unsafe { madvise(mmap_address, vm_page as _, MADV_RANDOM) })
-            .context("failed to disable readahead on mmaped region")?;
+    Ok(runs)
+}
 
-        // SAFETY: this is a valid aligned pointer.
-        unsafe {
-            // read one byte to cause a page fault and make the page present.
-            mmap_address.cast::<u8>().read_volatile();
+/// Returns the runs of `pages` that are fully covered by unwritten (preallocated) extents, using
+/// the `FS_IOC_FIEMAP` ioctl.
+///
+/// Filesystems without extent maps (e.g. tmpfs) have no unwritten extents.
+fn unwritten_pages(file: &File, pages: Range<u64>) -> Result<Vec<Range<u64>>> {
+    let vm_page = vm_page_size()?;
+    let Some(extents) = fiemap_extents(file, pages.start * vm_page..pages.end * vm_page)? else {
+        log::debug!("no extent map, assuming there are no unwritten extents");
+        return Ok(vec![]);
+    };
+
+    let mut runs: Vec<Range<u64>> = Vec::new();
+    for extent in extents
+        .iter()
+        .filter(|extent| extent.flags.contains(FiemapExtentFlags::UNWRITTEN))
+    {
+        let start = extent.logical.div_ceil(vm_page).max(pages.start);
+        let end = ((extent.logical + extent.length) / vm_page).min(pages.end);
+        match runs.last_mut() {
+            // adjacent extents, e.g. split at the maximum extent length.
+            Some(last) if last.end == start => last.end = end.max(start),
+            _ if start < end => runs.push(start..end),
+            _ => {}
         }
+    }
 
-        let pagemap_entry = get_page_map_entry((mmap_address as u64) / vm_page)?;
-        // ideally, this should never error because we faulted the page and made it "present".
-        let pfn = pagemap_entry.pfn()?.context(format!(
-            "the PFN for {} is not present",
-            self.as_fd().as_raw_fd()
-        ))?;
+    Ok(runs)
+}
 
-        let kpage_flags = get_kernel_page(pfn)?;
+/// A file extent and where it is on disk, see [`PageMapExt::physical_extents`].
+#[derive(Debug, Clone, Copy)]
+pub struct PhysicalExtent {
+    /// Offset of the extent in the file, in bytes.
+    pub logical: u64,
+    /// Offset of the extent on the device of the filesystem, in bytes, or `None` if it is not
+    /// known yet (e.g. delayed allocation).
+    pub physical: Option<u64>,
+    pub length: u64,
+    pub flags: FiemapExtentFlags,
+}
 
-        // SAFETY: we are unmapping an mmaped region that we no longer use or need.
-        cvt!(unsafe { munmap(mmap_address, vm_page as _) }).context(format!(
-            "failed to munmap page `{}` for `{}`",
-            page,
-            self.as_fd().as_raw_fd()
-        ))?;
+impl PhysicalExtent {
+    /// Returns the bytes of the file in the extent.
+    pub fn logical_range(&self) -> Range<u64> {
+        self.logical..self.logical + self.length
+    }
+}
+
+/// Returns the extents of `file` that overlap the bytes in `range`, using `FS_IOC_FIEMAP`.
+///
+/// Returns `None` if the filesystem has no extent maps.
+fn fiemap_extents(file: &File, range: Range<u64>) -> Result<Option<Vec<PhysicalExtent>>> {
+    let mut extents = Vec::new();
+    let mut start = range.start;
+    // the kernel only maps the extents that overlap [fm_start, fm_start + fm_length).
+    while start < range.end {
+        let mut arg = FiemapArg {
+            fm_start: start,
+            fm_length: range.end - start,
+            fm_extent_count: FIEMAP_EXTENT_COUNT as u32,
+            ..Default::default()
+        };
+        // SAFETY: FS_IOC_FIEMAP writes at most `fm_extent_count` extents to `fm_extents`.
+        if unsafe { ioctl(file.as_raw_fd(), FS_IOC_FIEMAP, &mut arg as *mut FiemapArg) } < 0 {
+            match Errno::last() {
+                Errno::EINTR => continue,
+                Errno::EOPNOTSUPP | Errno::ENOTTY => return Ok(None),
+                errno => bail!("FS_IOC_FIEMAP failed: {errno}"),
+            }
+        }
 
-        Ok((pagemap_entry, kpage_flags))
+        let mapped = &arg.fm_extents[..arg.fm_mapped_extents as usize];
+        extents.extend(mapped.iter().map(|extent| {
+            let flags = FiemapExtentFlags::from_bits_retain(extent.fe_flags);
+            PhysicalExtent {
+                logical: extent.fe_logical,
+                physical: (!flags.contains(FiemapExtentFlags::UNKNOWN))
+                    .then_some(extent.fe_physical),
+                length: extent.fe_length,
+                flags,
+            }
+        }));
+        match mapped.last() {
+            Some(last) if last.fe_flags & FiemapExtentFlags::LAST.bits() == 0 => {
+                start = last.fe_logical + last.fe_length;
+            }
+            _ => break,
+        }
     }
+    Ok(Some(extents))
+}
 
-    /// Returns a list of file pages present in the page cache.
-    ///
-    /// See `man 2 mincore` and `man 2 posix_fadvise`:
-    ///
-    /// > One can obtain a snapshot of which pages of a file are resident in
-    /// > the buffer cache by  opening  a  file,  mapping  it  with
-    /// > mmap(2), and then applying mincore(2) to the mapping.
-    fn cached_pages(&self) -> Result<Vec<u64>> {
-        let vm_page = vm_page_size()?;
-        let number_of_pages = self.vm_pages_count()?;
+/// Returns the extents of `file` that overlap the bytes in `range`, by mapping a block at a time
+/// with `FIBMAP`. This needs `CAP_SYS_RAWIO`.
+///
+/// Adjacent blocks are merged into one extent and marked [`FiemapExtentFlags::MERGED`], like the
+/// kernel does when it emulates `FS_IOC_FIEMAP` with `bmap`.
+fn fibmap_extents(file: &File, range: Range<u64>) -> Result<Vec<PhysicalExtent>> {
+    let mut block_size: c_int = 0;
+    // SAFETY: FIGETBSZ writes an int.
+    if unsafe { ioctl(file.as_raw_fd(), FIGETBSZ, &mut block_size as *mut c_int) } < 0 {
+        return Err(std::io::Error::last_os_error()).context("FIGETBSZ ioctl failed");
+    }
+    let block_size = block_size as u64;
 
-        if number_of_pages == 0 {
-            return Ok(vec![]);
+    let mut extents: Vec<PhysicalExtent> = Vec::new();
+    for block in range.start / block_size..range.end.div_ceil(block_size) {
+        let mut physical = c_int::try_from(block).context("the file is too large for FIBMAP")?;
+        // SAFETY: FIBMAP reads the file block from an int, and writes the device block to it.
+        if unsafe { ioctl(file.as_raw_fd(), FIBMAP, &mut physical as *mut c_int) } < 0 {
+            let e = std::io::Error::last_os_error();
+            if e.raw_os_error() == Some(Errno::EINVAL as i32) {
+                bail!("the filesystem supports neither FS_IOC_FIEMAP nor FIBMAP");
+            }
+            return Err(e).context(format!("FIBMAP failed for block {block}"));
         }
+        // 0 is a hole.
+        if physical == 0 {
+            continue;
+        }
+
+        let logical = block * block_size;
+        let physical = physical as u32 as u64 * block_size;
+        match extents.last_mut() {
+            Some(last)
+                if last.logical + last.length == logical
+                    && last.physical.and_then(|p| p.checked_add(last.length)) == Some(physical) =>
+            {
+                last.length += block_size;
+            }
+            _ => extents.push(PhysicalExtent {
+                logical,
+                physical: Some(physical),
+                length: block_size,
+                flags: FiemapExtentFlags::MERGED,
+            }),
+        }
+    }
+
+    Ok(extents)
+}
+
+/// How the pages of a file are backed on disk, see [`PageMapExt::data_layout`].
+///
+/// Every page is in exactly one of the lists, a page that is only partly data counts as data.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct DataLayout {
+    /// Runs of pages with data, as `SEEK_DATA` reports them.
+    pub data: Vec<Range<u64>>,
+    /// Runs of pages in unwritten (fallocated) extents, they read as zeros.
+    pub unwritten: Vec<Range<u64>>,
+    /// Runs of pages with nothing allocated.
+    pub holes: Vec<Range<u64>>,
+}
+
+impl DataLayout {
+    /// Returns whether any of the pages is a hole or unwritten.
+    pub fn is_sparse(&self) -> bool {
+        !self.holes.is_empty() || !self.unwritten.is_empty()
+    }
+
+    /// Returns the number of pages with data.
+    pub fn nr_data(&self) -> u64 {
+        ranges_len(&self.data)
+    }
+
+    /// Returns the number of pages in the sorted ranges `pages` that have data.
+    pub fn count_data(&self, pages: &[Range<u64>]) -> u64 {
+        ranges_len(&intersect_ranges(&self.data, pages))
+    }
+}
+
+/// A shared or private mapping of a file range that is unmapped on drop.
+#[derive(Debug)]
+pub struct Mapping {
+    addr: *mut c_void,
+    len: usize,
+}
 
+impl Mapping {
+    /// Map `len` bytes of `file` from `offset` (a multiple of the page size), with the `mmap(2)`
+    /// protection `prot` and `flags`.
+    pub fn new(file: &File, offset: u64, len: u64, prot: c_int, flags: c_int) -> Result<Self> {
         // SAFETY: we have exclusive access to the file.
-        let mmap_address = unsafe {
+        let addr = unsafe {
             mmap64(
                 std::ptr::null_mut(),
-                (vm_page * number_of_pages) as usize,
-                PROT_NONE,
-                MAP_SHARED,
-                self.as_fd().as_raw_fd(),
-                0,
+                len as _,
+                prot,
+                flags,
+                file.as_fd().as_raw_fd(),
+                offset as _,
             )
         };
 
         ensure!(
-            mmap_address != MAP_FAILED,
-            "failed to mmap file `{}`: {}",
-            self.as_raw_fd(),
+            addr != MAP_FAILED,
+            "mmap(2) failed: {}",
             std::io::Error::last_os_error()
         );
 
-        let mut vec = vec![0u8; number_of_pages as usize];
-
-        // SAFETY: vec is large enough and is a buffer of bytes
-        cvt!(unsafe {
-            mincore(
-                mmap_address,
-                (vm_page * number_of_pages) as usize,
-                vec.as_mut_ptr() as _,
-            )
+        Ok(Mapping {
+            addr,
+            len: len as usize,
         })
-        .context(format!("mincore(2) failed for `{}`", self.as_raw_fd(),))?;
+    }
 
-        // SAFETY: we are unmapping an mmaped region that we no longer use or need.
-        cvt!(unsafe { munmap(mmap_address, (vm_page * number_of_pages) as usize) }).context(
-            format!("failed to munmap page `{}`", self.as_fd().as_raw_fd()),
-        )?;
+    /// Returns the address the mapping starts at.
+    pub fn addr(&self) -> u64 {
+        self.addr as u64
+    }
 
-        Ok(vec
-            .iter()
-            .enumerate()
-            .filter_map(|(page_num, b)| {
-                // if the page is in page cache then it will not be zero,
-                // because the least significant bit will be set. See: `man 2 mincore`
-                if *b != 0 { Some(page_num as u64) } else { None }
-            })
-            .collect())
+    /// Returns the number of pages in the mapping.
+    pub fn pages(&self, vm_page: u64) -> u64 {
+        self.len as u64 / vm_page
     }
 
-    /// Attempt to evict all pages from page cache for the file.
-    ///
-    /// There is no way to know if the pages were actually evicted or not, because
-    /// posix_fadvise does not return this information.
+    /// Returns a pointer to the first byte of page `i` in the mapping.
     ///
-    /// The kernel function `remove_mapping` in `vmscan.c` states that eviction will fail if:
-    /// 1. The folio is under writeback
-    /// 2. The folio is referenced by someone else
-    /// 3. The folio is dirty.
-    fn evict_pages(&self) -> Result<()> {
-        let len = self
-            .metadata()
-            .context("failed getting metadata for the file")?
-            .len();
+    /// Reading through it faults the page in, writing requires `PROT_WRITE`.
+    pub fn page_ptr(&self, i: u64, vm_page: u64) -> *mut u8 {
+        debug_assert!(i < self.pages(vm_page));
+        // SAFETY: the offset is within the mapping.
+        unsafe { self.addr.cast::<u8>().add((i * vm_page) as usize) }
+    }
 
-        cvt!(unsafe { posix_fadvise64(self.as_raw_fd(), 0, len as _, POSIX_FADV_DONTNEED) })
-            .context("failed to evict pages")?;
-        Ok(())
+    /// Apply the `madvise(2)` `advice` to the whole mapping.
+    pub fn advise(&self, advice: c_int) -> std::io::Result<()> {
+        // SAFETY: the range is mapped.
+        cvt!(unsafe { madvise(self.addr, self.len, advice) })
     }
 }
 
-/// return `PageMapEntry` for `page` from /proc/self/pagemap
-pub fn get_page_map_entry(page: u64) -> Result<PageMapEntry> {
-    let pagemap_file = OpenOptions::new()
-        .read(true)
-        .open("/proc/self/pagemap")
-        .context("failed to open /proc/self/pagemap")?;
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        // SAFETY: we are unmapping an mmaped region that we no longer use or need.
+        if let Err(e) = cvt!(unsafe { munmap(self.addr, self.len) }) {
+            log::warn!("failed to munmap {:#x}: {e}", self.addr as usize);
+        }
+    }
+}
 
-    let mut buf = [0u8; 8];
-    pagemap_file
-        .read_exact_at(&mut buf, page * size_of::<PageMapEntry>() as u64)
-        .context("faild to read first entry")?;
+/// Number of pages that [`CachedRanges`] maps and checks with a single `mincore(2)` call, 1GiB
+/// of 4KiB pages. This bounds the memory it takes to scan a file of any size.
+const MINCORE_CHUNK_PAGES: u64 = 1 << 18;
 
-    let raw_entry = u64::from_ne_bytes(buf);
-    Ok(PageMapEntry::from_bits_retain(raw_entry))
+/// An iterator over the runs of cached pages of a file, see [`PageMapExt::cached_ranges`].
+///
+/// The file is mapped and checked a chunk at a time, and runs that cross a chunk boundary are
+/// joined, so every run is yielded once and runs are never adjacent.
+pub struct CachedRanges<'a> {
+    file: &'a File,
+    vm_page: u64,
+    chunk_pages: u64,
+    /// The pages that were not checked yet.
+    pages: Range<u64>,
+    /// The `mincore(2)` vector of the last chunk, reused for the next one.
+    vec: Vec<u8>,
+    /// The runs of the last chunk that were not yielded yet, in reverse order.
+    runs: Vec<Range<u64>>,
+    /// The last run, which the next run may continue.
+    pending: Option<Range<u64>>,
 }
 
-/// return `KPageFlags` for for the Physical Frame Number (PFN)
-pub fn get_kernel_page(pfn: u64) -> Result<KPageFlags> {
-    let pagemap_file = OpenOptions::new()
-        .read(true)
-        .open("/proc/kpageflags")
-        .context("failed to open /proc/kpageflags")?;
+impl<'a> CachedRanges<'a> {
+    fn new(file: &'a File, pages: Range<u64>, chunk_pages: u64) -> Result<Self> {
+        Ok(Self {
+            file,
+            vm_page: vm_page_size()?,
+            chunk_pages,
+            pages,
+            vec: Vec::new(),
+            runs: Vec::new(),
+            pending: None,
+        })
+    }
 
-    let mut buf = [0u8; 8];
-    pagemap_file
-        .read_exact_at(&mut buf, pfn * size_of::<KPageFlags>() as u64)
-        .context("faild to read first entry")?;
+    /// Check the next chunk of pages and collect its runs.
+    fn next_chunk(&mut self) -> Result<()> {
+        let chunk = self.pages.start..self.pages.end.min(self.pages.start + self.chunk_pages);
+        self.pages.start = chunk.end;
+
+        let mapping = Mapping::new(
+            self.file,
+            chunk.start * self.vm_page,
+            (chunk.end - chunk.start) * self.vm_page,
+            PROT_NONE,
+            MAP_SHARED,
+        )
+        .context(format!("failed to mmap file `{}`", self.file.as_raw_fd()))?;
 
-    let raw_entry = u64::from_ne_bytes(buf);
+        self.vec.resize(mapping.pages(self.vm_page) as usize, 0);
+        // SAFETY: vec is large enough and is a buffer of bytes
+        cvt!(unsafe { mincore(mapping.addr, mapping.len, self.vec.as_mut_ptr() as _) })
+            .context(format!("mincore(2) failed for `{}`", self.file.as_raw_fd()))?;
 
-    Ok(KPageFlags::from_bits_retain(raw_entry))
+        // if the page is in page cache then it will not be zero, because the least
+        // significant bit will be set. See: `man 2 mincore`
+        let mut page = chunk.start;
+        for run in self.vec.chunk_by(|a, b| (*a != 0) == (*b != 0)) {
+            let end = page + run.len() as u64;
+            if run[0] != 0 {
+                self.runs.push(page..end);
+            }
+            page = end;
+        }
+        self.runs.reverse();
+        Ok(())
+    }
 }
 
-pub enum PFNResult {}
-impl PageMapEntry {
-    pub fn pfn(&self) -> Result<Option<u64>> {
-        // docs: bits 0-54  page frame number (PFN) if present
-        let pfn_mask = (1u64 << 55) - 1;
-        let pfn = self.bits() & pfn_mask;
+impl CachedRanges<'_> {
+    /// Returns the number of cached pages, without keeping the runs.
+    pub fn nr_pages(self) -> Result<u64> {
+        self.map(|run| run.map(|run| run.end - run.start)).sum()
+    }
+}
 
-        if self.contains(PageMapEntry::PRESENT) {
-            ensure!(
-                pfn != 0,
-                "/proc/self/pagemap: PFNs are hidden (requires root or CAP_SYS_ADMIN). \
-                \n       Kernel Page Flags cannot be read without physical frame access."
-            );
-            Ok(Some(pfn))
+impl Iterator for CachedRanges<'_> {
+    type Item = Result<Range<u64>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(run) = self.runs.pop() {
+                match self.pending.take() {
+                    Some(pending) if pending.end == run.start => {
+                        self.pending = Some(pending.start..run.end);
+                    }
+                    pending => {
+                        self.pending = Some(run);
+                        if pending.is_some() {
+                            return pending.map(Ok);
+                        }
+                    }
+                }
+            } else if self.pages.is_empty() {
+                return self.pending.take().map(Ok);
+            } else if let Err(e) = self.next_chunk() {
+                // stop after an error.
+                self.pages.start = self.pages.end;
+                self.runs.clear();
+                return Some(Err(e));
+            }
+        }
+    }
+}
+
+/// The reason a page is still cached after an eviction attempt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum EvictionFailure {
+    /// The folio is locked (e.g. under I/O).
+    Locked,
+    /// The folio is under writeback.
+    Writeback,
+    /// The folio is dirty and has to be written back first.
+    Dirty,
+    /// The folio is unevictable (e.g. mlocked or on a ramfs).
+    Unevictable,
+    /// The folio is mapped by another process.
+    Mmap,
+    /// The reason cannot be determined (e.g. the folio is referenced, or the kernel page flags
+    /// are not readable).
+    Unknown,
+}
+
+impl EvictionFailure {
+    /// Classify why a page survived eviction from its kernel page flags.
+    pub fn from_page(pagemap: &PageMapEntry, kflags: &KPageFlags) -> Self {
+        if kflags.contains(KPageFlags::LOCKED) {
+            EvictionFailure::Locked
+        } else if kflags.contains(KPageFlags::WRITEBACK) {
+            EvictionFailure::Writeback
+        } else if kflags.contains(KPageFlags::DIRTY) {
+            EvictionFailure::Dirty
+        } else if kflags.contains(KPageFlags::UNEVICTABLE) {
+            EvictionFailure::Unevictable
+        } else if kflags.contains(KPageFlags::MMAP) && !pagemap.contains(PageMapEntry::EXCL_MAP) {
+            // the page is always mapped by us to read its flags, it is only mapped by someone
+            // else if our mapping is not exclusive.
+            EvictionFailure::Mmap
         } else {
-            Ok(None)
+            EvictionFailure::Unknown
         }
     }
 }
 
-impl std::fmt::Display for KPageFlags {
+impl std::fmt::Display for EvictionFailure {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        bitflags::parser::to_writer(self, f)
+        f.write_str(match self {
+            EvictionFailure::Locked => "locked",
+            EvictionFailure::Writeback => "writeback",
+            EvictionFailure::Dirty => "dirty",
+            EvictionFailure::Unevictable => "unevictable",
+            EvictionFailure::Mmap => "mapped",
+            EvictionFailure::Unknown => "unknown",
+        })
     }
 }
-impl std::fmt::Display for PageMapEntry {
+
+/// A method to remove pages from the page cache, see [`PageMapExt::evict_with`].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub enum EvictionStrategy {
+    /// `posix_fadvise(POSIX_FADV_DONTNEED)`, silently skips dirty, mapped and referenced folios.
+    #[default]
+    Fadvise,
+    /// `madvise(MADV_PAGEOUT)` on a shared mapping of the cached pages.
+    ///
+    /// Folios mapped by other processes are skipped, and the caller must own the file or have
+    /// write permission to it, otherwise the kernel ignores file pages.
+    PageOut,
+    /// `madvise(MADV_COLD)` on a shared mapping of the cached pages.
+    ///
+    /// This only deactivates the pages so they are reclaimed first under memory pressure, pages
+    /// are usually still cached right after.
+    Cold,
+    /// Proactive reclaim through `memory.reclaim` of a cgroup v2, see [`cgroup::reclaim`].
+    ///
+    /// Only pages charged to that cgroup are reclaimed, and they are not limited to the file.
+    MemcgReclaim(PathBuf),
+    /// Drop clean pages of all files through `/proc/sys/vm/drop_caches` (requires root).
+    DropCaches,
+}
+
+impl std::fmt::Display for EvictionStrategy {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        bitflags::parser::to_writer(self, f)
+        f.write_str(match self {
+            EvictionStrategy::Fadvise => "fadvise",
+            EvictionStrategy::PageOut => "pageout",
+            EvictionStrategy::Cold => "cold",
+            EvictionStrategy::MemcgReclaim(_) => "memcg",
+            EvictionStrategy::DropCaches => "drop-caches",
+        })
     }
 }
 
-pub fn vm_page_size() -> Result<u64> {
-    Ok(sysconf(nix::unistd::SysconfVar::PAGE_SIZE)
-        .context("failed to get sys page size")?
-        .expect("page_size should be a supported option") as u64)
+/// The state of a page in the page cache, see [`PageMapExt::page_states`].
+///
+/// States are ordered by how far the page is from being clean and cached.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PageState {
+    /// The page is not in the page cache.
+    Absent,
+    Clean,
+    Dirty,
+    /// The page is being written back, it may be dirty again.
+    Writeback,
 }
 
-#[cfg(test)]
-mod test {
-    use std::{
-        fs::{File, OpenOptions},
-        io::Write,
-        ops::{Deref, DerefMut},
-        path::Path,
-        time::{SystemTime, UNIX_EPOCH},
-    };
+/// A method to bring pages into the page cache, see [`PageMapExt::touch_range`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum TouchMethod {
+    /// `pread(2)` every page, the pages are cached when this returns.
+    #[default]
+    Read,
+    /// `readahead(2)`, blocks until the reads are submitted but not until they complete.
+    Readahead,
+    /// `posix_fadvise(POSIX_FADV_WILLNEED)`, asynchronous.
+    Fadvise,
+    /// `madvise(MADV_WILLNEED)` on a shared mapping of the pages, asynchronous.
+    Madvise,
+    /// `mmap(2)` the pages with `MAP_POPULATE`, the pages are cached when this returns.
+    ///
+    /// Like [`TouchMethod::Read`], the faults may read ahead pages past the range.
+    Populate,
+}
 
-    use crate::pagemap::{PageMapEntry, PageMapExt};
+impl std::fmt::Display for TouchMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            TouchMethod::Read => "read",
+            TouchMethod::Readahead => "readahead",
+            TouchMethod::Fadvise => "fadvise",
+            TouchMethod::Madvise => "madvise",
+            TouchMethod::Populate => "populate",
+        })
+    }
+}
 
-    pub struct TestFile(std::path::PathBuf, File);
-    impl Deref for TestFile {
-        type Target = File;
+/// Pages of a file that are locked in memory, see [`PageMapExt::lock_range`].
+///
+/// The pages are unlocked when this is dropped.
+pub struct LockedPages {
+    mapping: Mapping,
+}
+
+impl LockedPages {
+    /// Returns the number of locked pages.
+    pub fn pages(&self) -> Result<u64> {
+        Ok(self.mapping.pages(vm_page_size()?))
+    }
+
+    /// Lock the pages again.
+    ///
+    /// Memory locks are not inherited by a child created by fork(2), but the mapping is, so a
+    /// child must call this to keep the pages locked after the parent exits.
+    pub fn relock(&self) -> Result<()> {
+        // SAFETY: the range is mapped.
+        cvt!(unsafe { mlock(self.mapping.addr, self.mapping.len) })
+            .context("mlock(2) failed, the locked size may exceed RLIMIT_MEMLOCK (see `ulimit -l`)")
+    }
+}
+
+/// Options for [`PageMapExt::evict_range_verified`].
+#[derive(Debug, Default, Clone)]
+pub struct EvictOptions {
+    /// How to evict the pages.
+    pub strategy: EvictionStrategy,
+    /// Write back dirty pages and wait for writeback before every eviction attempt.
+    pub sync_first: bool,
+    /// How many times to retry evicting pages that survived the first attempt.
+    pub retries: u32,
+}
+
+/// The result of [`PageMapExt::evict_range_verified`].
+#[derive(Debug, Default)]
+pub struct EvictionReport {
+    /// Number of pages that were cached before eviction.
+    pub cached_before: u64,
+    /// Number of pages that were actually evicted.
+    pub evicted: u64,
+    /// Runs of pages that are still cached, and why.
+    pub survivors: Vec<(Range<u64>, EvictionFailure)>,
+    /// Number of eviction attempts made.
+    pub attempts: u32,
+}
+
+impl EvictionReport {
+    /// Returns the number of pages that are still cached.
+    pub fn nr_survivors(&self) -> u64 {
+        self.survivors
+            .iter()
+            .map(|(pages, _)| pages.end - pages.start)
+            .sum()
+    }
+}
+
+pub trait PageMapExt {
+    fn page_info(&self, page: u64) -> Result<(PageMapEntry, KPageFlags)>;
+    fn pages_info(&self, pages: Range<u64>) -> Result<PagesInfo>;
+    fn evict_pages(&self) -> Result<()>;
+    fn evict_range(&self, pages: Range<u64>) -> Result<()>;
+    fn evict_with(&self, pages: Range<u64>, strategy: &EvictionStrategy) -> Result<()>;
+    fn evict_range_verified(
+        &self,
+        pages: Range<u64>,
+        options: &EvictOptions,
+    ) -> Result<EvictionReport>;
+    fn sync_range(&self, pages: Range<u64>) -> Result<()>;
+    fn touch_range(&self, pages: Range<u64>, method: TouchMethod) -> Result<()>;
+    fn lock_range(&self, pages: Range<u64>) -> Result<LockedPages>;
+    fn mark_idle(&self, pages: Range<u64>) -> Result<IdlePages>;
+    fn folios(&self, pages: Range<u64>) -> Result<Vec<Folio>>;
+    fn cached_pages(&self) -> Result<Vec<u64>>;
+    fn cached_pages_in(&self, pages: Range<u64>) -> Result<Vec<u64>>;
+    fn cached_ranges(&self, pages: Range<u64>) -> Result<CachedRanges<'_>>;
+    fn swapped_pages(&self, pages: Range<u64>) -> Result<Vec<Range<u64>>>;
+    fn data_layout(&self, pages: Range<u64>) -> Result<DataLayout>;
+    fn physical_extents(&self, pages: Range<u64>) -> Result<Vec<PhysicalExtent>>;
+    fn numa_nodes(&self, pages: Range<u64>) -> Result<Vec<(Range<u64>, u32)>>;
+    fn page_states(
+        &self,
+        pages: Range<u64>,
+        use_cachestat: bool,
+    ) -> Result<Vec<(Range<u64>, PageState)>>;
+    fn cachestat(&self, range: Range<u64>) -> Result<CacheStat>;
+    fn fs_block_size(&self) -> Result<u64>;
+    fn vm_pages_count(&self) -> Result<u64>;
+    fn size(&self) -> Result<u64>;
+}
+
+impl PageMapExt for File {
+    fn fs_block_size(&self) -> Result<u64> {
+        let stats = fstatfs(self).context("failed to get stats for file")?;
+        Ok(stats.block_size() as u64)
+    }
+
+    /// Returns the number of pages in a file.
+    fn vm_pages_count(&self) -> Result<u64> {
+        let vm_page = vm_page_size()?;
+        Ok(self.size()?.div_ceil(vm_page as u64))
+    }
+
+    /// Returns the size of the file in bytes.
+    ///
+    /// The size of a block device is read with `BLKGETSIZE64`, its inode has a size of 0. The
+    /// pages of a block device are its own page cache (the buffer cache), which holds the blocks
+    /// that were read or written through the device rather than through files, like the
+    /// metadata of a filesystem on it.
+    fn size(&self) -> Result<u64> {
+        let metadata = self
+            .metadata()
+            .context("failed getting metadata for the file")?;
+        if !metadata.file_type().is_block_device() {
+            return Ok(metadata.len());
+        }
+
+        let mut size: u64 = 0;
+        // SAFETY: BLKGETSIZE64 writes a u64.
+        if unsafe { ioctl(self.as_raw_fd(), BLKGETSIZE64, &mut size as *mut u64) } < 0 {
+            return Err(std::io::Error::last_os_error()).context("BLKGETSIZE64 ioctl failed");
+        }
+        Ok(size)
+    }
+
+    /// Returns the page map entry and kernel flags for `page`.
+    ///
+    /// This will fault the page in to make it present.
+    fn page_info(&self, page: u64) -> Result<(PageMapEntry, KPageFlags)> {
+        let pages = self.vm_pages_count()?;
+        ensure!(
+            page < pages,
+            "page index {page} out of bounds; total pages = {pages}"
+        );
+
+        let info = self
+            .pages_info(page..page + 1)?
+            .next()
+            .expect("the range has exactly one page");
+
+        Ok((info.pagemap, info.kflags))
+    }
+
+    /// Returns the page map entry and kernel flags for every page in `pages`.
+    ///
+    /// Like [`PageMapExt::page_info`], this will fault the pages in to make them present, but the
+    /// range is mapped once, the page map entries are read in a single `pread` and the kernel
+    /// page flags of adjacent frames are read together.
+    fn pages_info(&self, pages: Range<u64>) -> Result<PagesInfo> {
+        let vm_page = vm_page_size()?;
+        let number_of_pages = self.vm_pages_count()?;
+        ensure!(
+            pages.end <= number_of_pages,
+            "page range {}..{} out of bounds; total pages = {number_of_pages}",
+            pages.start,
+            pages.end
+        );
+
+        if pages.is_empty() {
+            return Ok(PagesInfo {
+                inner: Vec::new().into_iter(),
+            });
+        }
+
+        let mapping = Mapping::new(
+            self,
+            pages.start * vm_page,
+            (pages.end - pages.start) * vm_page,
+            PROT_READ,
+            MAP_PRIVATE,
+        )
+        .context(format!(
+            "failed to mmap pages {}..{} for `{}`",
+            pages.start,
+            pages.end,
+            self.as_raw_fd()
+        ))?;
+
+        // Touching a non-present page (page fault) will trigger a readahead by the linux kernel, which
+        // will bring adjecent pages into cache.
+        //
+        // Note: The man page doesn't explicitly say that `MADV_RANDOM` is guaranteed to disable readahead,
+        // but it strongly suggets that. Also, at the time of writing this, the kernel function `do_sync_mmap_readahead` returns
+        // early if the VMA has the RAND_READ flag.
+        mapping
+            .advise(MADV_RANDOM)
+            .context("failed to disable readahead on mmaped region")?;
+
+        for i in 0..mapping.pages(vm_page) {
+            // SAFETY: this is a valid aligned pointer inside the mapping.
+            unsafe {
+                // read one byte to cause a page fault and make the page present.
+                mapping.page_ptr(i, vm_page).read_volatile();
+            }
+        }
+
+        let entries = get_page_map_entries(None, mapping.addr() / vm_page, mapping.pages(vm_page))?;
+
+        // ideally, this should never error because we faulted the pages and made them "present".
+        let pfns = entries
+            .iter()
+            .map(|entry| {
+                entry
+                    .pfn()?
+                    .context(format!("the PFN for {} is not present", self.as_raw_fd()))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let kflags = get_kernel_pages(&pfns)?;
+
+        drop(mapping);
+
+        Ok(PagesInfo {
+            inner: pages
+                .zip(entries.into_iter().zip(kflags))
+                .map(|(page, (pagemap, kflags))| PageInfo {
+                    page,
+                    pagemap,
+                    kflags,
+                })
+                .collect::<Vec<_>>()
+                .into_iter(),
+        })
+    }
+
+    /// Returns the folios of the pages in `pages`, see [`group_folios`].
+    ///
+    /// Like [`PageMapExt::pages_info`], this will fault the pages in, so `pages` should only
+    /// have cached pages. The page after `pages` is also inspected if it is cached, to find
+    /// out whether the last folio is cut.
+    fn folios(&self, pages: Range<u64>) -> Result<Vec<Folio>> {
+        let infos = self.pages_info(pages.clone())?.collect::<Vec<_>>();
+        let mut folios = group_folios(&infos)?;
+
+        if let Some(last) = folios.last_mut()
+            && !last.partial
+            && pages.end < self.vm_pages_count()?
+            && self.cached_pages_in(pages.end..pages.end + 1)? == [pages.end]
+        {
+            let (pagemap, kflags) = self.page_info(pages.end)?;
+            last.partial = kflags.contains(KPageFlags::COMPOUND_TAIL)
+                && pagemap.pfn()? == Some(last.pfn + last.nr_pages());
+        }
+
+        Ok(folios)
+    }
+
+    /// Returns a list of file pages present in the page cache.
+    ///
+    /// See `man 2 mincore` and `man 2 posix_fadvise`:
+    ///
+    /// > One can obtain a snapshot of which pages of a file are resident in
+    /// > the buffer cache by  opening  a  file,  mapping  it  with
+    /// > mmap(2), and then applying mincore(2) to the mapping.
+    fn cached_pages(&self) -> Result<Vec<u64>> {
+        self.cached_pages_in(0..self.vm_pages_count()?)
+    }
+
+    /// Returns a list of file pages in `pages` that are present in the page cache.
+    ///
+    /// This has an entry per cached page, prefer [`PageMapExt::cached_ranges`] for large files.
+    fn cached_pages_in(&self, pages: Range<u64>) -> Result<Vec<u64>> {
+        let mut cached = Vec::new();
+        for run in self.cached_ranges(pages)? {
+            cached.extend(run?);
+        }
+        Ok(cached)
+    }
+
+    /// Returns an iterator over the runs of pages in `pages` that are present in the page cache.
+    ///
+    /// The pages are checked with `mincore(2)` a chunk at a time, so the memory it takes does
+    /// not grow with the size of the file (see [`PageMapExt::cached_pages`]).
+    fn cached_ranges(&self, pages: Range<u64>) -> Result<CachedRanges<'_>> {
+        let number_of_pages = self.vm_pages_count()?;
+        ensure!(
+            pages.end <= number_of_pages,
+            "page range {}..{} out of bounds; total pages = {number_of_pages}",
+            pages.start,
+            pages.end
+        );
+        CachedRanges::new(self, pages, MINCORE_CHUNK_PAGES)
+    }
+
+    /// Returns the runs of pages in `pages` of a shmem file (tmpfs, memfd) that are swapped out.
+    ///
+    /// Swapping out a shmem page unmaps it, and its swap entry is kept in the page cache rather
+    /// than in the page tables, so the page map never has a [`PageLocation::Swapped`] entry for
+    /// it. Instead, a page is swapped out if `SEEK_DATA` finds data in it, but it is not cached
+    /// (pages in the swap cache are cached). Files on other filesystems have no swapped pages.
+    fn swapped_pages(&self, pages: Range<u64>) -> Result<Vec<Range<u64>>> {
+        let stats = fstatfs(self).context("failed to get stats for file")?;
+        // device nodes are on devtmpfs, which is tmpfs too.
+        let is_file = self
+            .metadata()
+            .context("failed getting metadata for the file")?
+            .is_file();
+        if !is_file || stats.filesystem_type() != TMPFS_MAGIC {
+            return Ok(vec![]);
+        }
+
+        let cached = self
+            .cached_ranges(pages.clone())?
+            .collect::<Result<Vec<_>>>()?;
+        Ok(subtract_ranges(&data_pages(self, pages)?, &cached))
+    }
+
+    /// Returns which pages in `pages` have data, which are unwritten and which are holes.
+    ///
+    /// `SEEK_DATA` treats unwritten extents as holes unless they have cached data, so they are
+    /// found with `FS_IOC_FIEMAP` instead. Pages in an unwritten extent with cached data
+    /// (e.g. dirty pages that were not written back yet) are data.
+    fn data_layout(&self, pages: Range<u64>) -> Result<DataLayout> {
+        let data = data_pages(self, pages.clone())?;
+        let unwritten = subtract_ranges(&unwritten_pages(self, pages.clone())?, &data);
+        let holes = subtract_ranges(&subtract_ranges(&[pages], &data), &unwritten);
+        Ok(DataLayout {
+            data,
+            unwritten,
+            holes,
+        })
+    }
+
+    /// Returns the extents that overlap `pages`, and where they are on disk. Holes have no
+    /// extent.
+    ///
+    /// The extents come from `FS_IOC_FIEMAP`, or from `FIBMAP` on filesystems without extent
+    /// maps, which needs root.
+    fn physical_extents(&self, pages: Range<u64>) -> Result<Vec<PhysicalExtent>> {
+        let vm_page = vm_page_size()?;
+        let range = pages.start * vm_page..pages.end * vm_page;
+        match fiemap_extents(self, range.clone())? {
+            Some(extents) => Ok(extents),
+            None => fibmap_extents(self, range),
+        }
+    }
+
+    /// Returns the runs of cached pages in `pages` that are on the same NUMA node, with the
+    /// node.
+    ///
+    /// The cached pages are faulted into a shared mapping, and their nodes are queried with
+    /// `move_pages(2)` without moving them, [`MOVE_PAGES_BATCH`] pages at a time. Faulting a page
+    /// that was evicted after the lookup reads it back in, it is then reported on the node it was
+    /// read into.
+    fn numa_nodes(&self, pages: Range<u64>) -> Result<Vec<(Range<u64>, u32)>> {
+        let vm_page = vm_page_size()?;
+        let mut cached = self.cached_ranges(pages.clone())?.peekable();
+        if cached.peek().is_none() {
+            return Ok(vec![]);
+        }
+
+        let mapping = Mapping::new(
+            self,
+            pages.start * vm_page,
+            (pages.end - pages.start) * vm_page,
+            PROT_READ,
+            MAP_SHARED,
+        )
+        .context(format!("failed to mmap file `{}`", self.as_raw_fd()))?;
+        // don't let the page faults read ahead non-cached pages.
+        mapping
+            .advise(MADV_RANDOM)
+            .context("failed to disable readahead on mmaped region")?;
+
+        let mut nodes = Vec::new();
+        let mut batch = Vec::with_capacity(MOVE_PAGES_BATCH);
+        for run in cached {
+            for page in run? {
+                batch.push(page);
+                if batch.len() == MOVE_PAGES_BATCH {
+                    query_nodes(&mapping, pages.start, &batch, &mut nodes)?;
+                    batch.clear();
+                }
+            }
+        }
+        query_nodes(&mapping, pages.start, &batch, &mut nodes)?;
+        Ok(nodes)
+    }
+
+    /// Returns the runs of cached pages in `pages` that are in the same state, pages that are
+    /// not cached ([`PageState::Absent`]) are left out.
+    ///
+    /// Cached pages are found through [`PageMapExt::cached_ranges`]. With `use_cachestat`, their
+    /// state is read through `cachestat(2)`, which leaves the pages alone. Otherwise it is read
+    /// from the kernel page flags (requires root), which faults the cached pages into a
+    /// temporary mapping: they are marked accessed, and a page evicted after the lookup is read
+    /// back in.
+    fn page_states(
+        &self,
+        pages: Range<u64>,
+        use_cachestat: bool,
+    ) -> Result<Vec<(Range<u64>, PageState)>> {
+        let mut states = Vec::new();
+        for range in self.cached_ranges(pages)? {
+            let range = range?;
+            if use_cachestat {
+                cachestat_states(self, range, &mut states)?;
+                continue;
+            }
+            let info = self
+                .pages_info(range)
+                .context("page states without cachestat(2) require root")?;
+            for info in info {
+                let state = if info.kflags.contains(KPageFlags::WRITEBACK) {
+                    PageState::Writeback
+                } else if info.kflags.contains(KPageFlags::DIRTY) {
+                    PageState::Dirty
+                } else {
+                    PageState::Clean
+                };
+                push_run(&mut states, info.page..info.page + 1, state);
+            }
+        }
+
+        Ok(states)
+    }
+
+    /// Returns page cache statistics for the byte `range` of the file.
+    ///
+    /// Unlike [`PageMapExt::page_info`], this does not map or fault any page, so it does
+    /// not perturb the page cache. An empty range (e.g. `5..5`) means from its start to the end
+    /// of the file, so `0..0` covers the whole file.
+    ///
+    /// Requires Linux 6.5+, see [`cachestat_supported`].
+    fn cachestat(&self, range: Range<u64>) -> Result<CacheStat> {
+        ensure!(
+            range.start <= range.end,
+            "invalid range {}..{}",
+            range.start,
+            range.end
+        );
+
+        let cstat_range = CacheStatRange {
+            off: range.start,
+            len: range.end - range.start,
+        };
+        let mut cstat = CacheStat::default();
+
+        // SAFETY: both pointers are valid for the duration of the call and the structs
+        // match the kernel layout.
+        let ret = unsafe {
+            syscall(
+                SYS_CACHESTAT,
+                self.as_raw_fd(),
+                &cstat_range as *const CacheStatRange,
+                &mut cstat as *mut CacheStat,
+                0,
+            )
+        };
+        cvt!(ret).context(format!("cachestat(2) failed for `{}`", self.as_raw_fd()))?;
+
+        Ok(cstat)
+    }
+
+    /// Attempt to evict all pages from page cache for the file.
+    ///
+    /// There is no way to know if the pages were actually evicted or not, because
+    /// posix_fadvise does not return this information. Use
+    /// [`PageMapExt::evict_range_verified`] to check residency afterwards.
+    ///
+    /// The kernel function `remove_mapping` in `vmscan.c` states that eviction will fail if:
+    /// 1. The folio is under writeback
+    /// 2. The folio is referenced by someone else
+    /// 3. The folio is dirty.
+    fn evict_pages(&self) -> Result<()> {
+        let len = self.size()?;
+
+        cvt!(unsafe { posix_fadvise64(self.as_raw_fd(), 0, len as _, POSIX_FADV_DONTNEED) })
+            .context("failed to evict pages")?;
+        Ok(())
+    }
+
+    /// Attempt to evict the pages in `pages` from page cache, see [`PageMapExt::evict_pages`].
+    fn evict_range(&self, pages: Range<u64>) -> Result<()> {
+        let vm_page = vm_page_size()?;
+        if pages.is_empty() {
+            // posix_fadvise treats a zero length as "until the end of the file".
+            return Ok(());
+        }
+
+        cvt!(unsafe {
+            posix_fadvise64(
+                self.as_raw_fd(),
+                (pages.start * vm_page) as _,
+                ((pages.end - pages.start) * vm_page) as _,
+                POSIX_FADV_DONTNEED,
+            )
+        })
+        .context(format!(
+            "failed to evict pages {}..{}",
+            pages.start, pages.end
+        ))?;
+        Ok(())
+    }
+
+    /// Attempt to evict the pages in `pages` from page cache using `strategy`.
+    ///
+    /// Some strategies are not limited to the file or to `pages`, see [`EvictionStrategy`].
+    fn evict_with(&self, pages: Range<u64>, strategy: &EvictionStrategy) -> Result<()> {
+        match strategy {
+            EvictionStrategy::Fadvise => self.evict_range(pages),
+            EvictionStrategy::PageOut => advise_cached_pages(self, pages, MADV_PAGEOUT)
+                .context("madvise(MADV_PAGEOUT) failed"),
+            EvictionStrategy::Cold => {
+                advise_cached_pages(self, pages, MADV_COLD).context("madvise(MADV_COLD) failed")
+            }
+            EvictionStrategy::MemcgReclaim(path) => {
+                cgroup::reclaim(path, (pages.end - pages.start) * vm_page_size()?)
+            }
+            EvictionStrategy::DropCaches => std::fs::write("/proc/sys/vm/drop_caches", "1\n")
+                .context("failed to write to /proc/sys/vm/drop_caches"),
+        }
+    }
+
+    /// Evict the pages in `pages` and check which pages are still cached afterwards.
+    ///
+    /// Pages that survive are retried up to [`EvictOptions::retries`] times, and are then
+    /// classified by their kernel page flags (see [`EvictionFailure`]). If the flags cannot be
+    /// read (they require root), dirty and writeback pages are still detected through
+    /// `cachestat(2)` when available.
+    fn evict_range_verified(
+        &self,
+        pages: Range<u64>,
+        options: &EvictOptions,
+    ) -> Result<EvictionReport> {
+        let mut report = EvictionReport {
+            cached_before: self.cached_ranges(pages.clone())?.nr_pages()?,
+            ..Default::default()
+        };
+
+        let mut remaining = report.cached_before;
+        let mut survivors = Vec::new();
+        while remaining > 0 && report.attempts <= options.retries {
+            if options.sync_first {
+                self.sync_range(pages.clone())?;
+            }
+            self.evict_with(pages.clone(), &options.strategy)?;
+            report.attempts += 1;
+
+            survivors = self
+                .cached_ranges(pages.clone())?
+                .collect::<Result<Vec<_>>>()?;
+            remaining = ranges_len(&survivors);
+        }
+
+        report.evicted = report.cached_before.saturating_sub(remaining);
+        report.survivors = classify_survivors(self, &survivors)?;
+
+        Ok(report)
+    }
+
+    /// Write back the dirty pages in `pages` and wait for the writeback to finish.
+    ///
+    /// This uses `sync_file_range(2)`, which does not flush the file metadata or the disk cache.
+    fn sync_range(&self, pages: Range<u64>) -> Result<()> {
+        let vm_page = vm_page_size()?;
+        if pages.is_empty() {
+            // sync_file_range treats a zero length as "until the end of the file".
+            return Ok(());
+        }
+
+        cvt!(unsafe {
+            sync_file_range(
+                self.as_raw_fd(),
+                (pages.start * vm_page) as _,
+                ((pages.end - pages.start) * vm_page) as _,
+                SYNC_FILE_RANGE_WAIT_BEFORE | SYNC_FILE_RANGE_WRITE | SYNC_FILE_RANGE_WAIT_AFTER,
+            )
+        })
+        .context(format!(
+            "failed to sync pages {}..{}",
+            pages.start, pages.end
+        ))?;
+        Ok(())
+    }
+
+    /// Bring the pages in `pages` into the page cache using `method`.
+    ///
+    /// Pages past the end of the file are ignored. Some methods only start the reads, see
+    /// [`TouchMethod`].
+    fn touch_range(&self, pages: Range<u64>, method: TouchMethod) -> Result<()> {
+        let vm_page = vm_page_size()?;
+        let pages = pages.start..pages.end.min(self.vm_pages_count()?);
+        if pages.is_empty() {
+            return Ok(());
+        }
+        let offset = pages.start * vm_page;
+        let len = (pages.end - pages.start) * vm_page;
+
+        match method {
+            TouchMethod::Read => {
+                let mut buf = vec![0u8; vm_page as usize * 256];
+                let mut pos = offset;
+                while pos < offset + len {
+                    let want = buf.len().min((offset + len - pos) as usize);
+                    let n = self
+                        .read_at(&mut buf[..want], pos)
+                        .context(format!("failed to read at offset {pos}"))?;
+                    if n == 0 {
+                        break;
+                    }
+                    pos += n as u64;
+                }
+            }
+            TouchMethod::Readahead => {
+                cvt!(unsafe { readahead(self.as_raw_fd(), offset as _, len as _) as c_int })
+                    .context("readahead(2) failed")?
+            }
+            TouchMethod::Fadvise => cvt!(unsafe {
+                posix_fadvise64(self.as_raw_fd(), offset as _, len as _, POSIX_FADV_WILLNEED)
+            })
+            .context("posix_fadvise(POSIX_FADV_WILLNEED) failed")?,
+            TouchMethod::Madvise => {
+                Mapping::new(self, offset, len, PROT_READ, MAP_SHARED)
+                    .context(format!("failed to mmap file `{}`", self.as_raw_fd()))?
+                    .advise(MADV_WILLNEED)
+                    .context("madvise(MADV_WILLNEED) failed")?;
+            }
+            TouchMethod::Populate => {
+                Mapping::new(self, offset, len, PROT_READ, MAP_SHARED | MAP_POPULATE)
+                    .context(format!("failed to mmap file `{}`", self.as_raw_fd()))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Bring the pages in `pages` into the page cache and lock them in memory with `mlock(2)`.
+    ///
+    /// Locked pages cannot be evicted until the returned [`LockedPages`] is dropped. Pages past
+    /// the end of the file are ignored.
+    fn lock_range(&self, pages: Range<u64>) -> Result<LockedPages> {
+        let vm_page = vm_page_size()?;
+        let pages = pages.start..pages.end.min(self.vm_pages_count()?);
+        ensure!(!pages.is_empty(), "there are no pages to lock");
+
+        let locked = LockedPages {
+            mapping: Mapping::new(
+                self,
+                pages.start * vm_page,
+                (pages.end - pages.start) * vm_page,
+                PROT_READ,
+                MAP_SHARED,
+            )
+            .context(format!("failed to mmap file `{}`", self.as_raw_fd()))?,
+        };
+        // don't let the page faults read ahead pages outside of the range.
+        locked
+            .mapping
+            .advise(MADV_RANDOM)
+            .context("failed to disable readahead on mmaped region")?;
+        locked.relock()?;
+        Ok(locked)
+    }
+
+    /// Mark the cached pages in `pages` idle, through `/sys/kernel/mm/page_idle/bitmap`.
+    ///
+    /// The kernel clears the idle flag of a page when it is read or written, or accessed
+    /// through a mapping. Use [`IdlePages::accessed`] after a while to find the pages that were
+    /// accessed in the meantime, i.e. the working set. This requires root and a kernel built
+    /// with `CONFIG_IDLE_PAGE_TRACKING`.
+    ///
+    /// The kernel tracks a large folio through its head page only, so the head of each folio
+    /// is marked and its flag stands for every page of the folio. Pages that are not on an LRU
+    /// list cannot be tracked, they are left in [`IdlePages::untracked`].
+    fn mark_idle(&self, pages: Range<u64>) -> Result<IdlePages> {
+        ensure!(
+            page_idle_supported(),
+            "idle page tracking is not supported (requires CONFIG_IDLE_PAGE_TRACKING)"
+        );
+
+        // the pages are faulted in to resolve their PFNs, and unmapped again before they are
+        // marked idle so this mapping does not count as an access.
+        let mut idle = IdlePages::default();
+        for range in self.cached_ranges(pages)? {
+            let infos = self.pages_info(range?)?.collect::<Vec<_>>();
+            for folio in group_folios(&infos)? {
+                let (head, kflags) = if folio.partial {
+                    folio_head(folio.pfn)?
+                } else {
+                    (folio.pfn, folio.kflags)
+                };
+                if kflags.contains(KPageFlags::LRU) {
+                    idle.pages.extend(folio.pages.clone());
+                    idle.heads.push((folio.pages, head));
+                } else {
+                    idle.untracked.extend(folio.pages);
+                }
+            }
+        }
+
+        let mut words = BTreeMap::<u64, u64>::new();
+        for (_, pfn) in &idle.heads {
+            *words.entry(pfn / 64).or_default() |= 1 << (pfn % 64);
+        }
+        let bitmap = OpenOptions::new()
+            .write(true)
+            .open(PAGE_IDLE_BITMAP)
+            .context(format!("failed to open {PAGE_IDLE_BITMAP}"))?;
+        let words = words.into_iter().collect::<Vec<_>>();
+        // the bitmap is written in 8-byte words, runs of adjacent words are written together.
+        for run in words.chunk_by(|(a, _), (b, _)| a + 1 == *b) {
+            let buf = run
+                .iter()
+                .flat_map(|(_, word)| word.to_ne_bytes())
+                .collect::<Vec<_>>();
+            bitmap
+                .write_all_at(&buf, run[0].0 * size_of::<u64>() as u64)
+                .context(format!("failed to mark PFN {} idle", run[0].0 * 64))?;
+        }
+
+        Ok(idle)
+    }
+}
+
+const PAGE_IDLE_BITMAP: &str = "/sys/kernel/mm/page_idle/bitmap";
+
+/// Returns true if the kernel supports idle page tracking (`CONFIG_IDLE_PAGE_TRACKING`).
+pub fn page_idle_supported() -> bool {
+    Path::new(PAGE_IDLE_BITMAP).exists()
+}
+
+/// Returns the PFN and the kernel page flags of the head page of the large folio that the
+/// tail page `pfn` belongs to.
+///
+/// The pages of a folio are physically contiguous, so the head is the closest compound head
+/// before `pfn`.
+fn folio_head(pfn: u64) -> Result<(u64, KPageFlags)> {
+    // the flags are read a batch at a time, a folio may span many batches.
+    const BATCH: u64 = 64;
+    let mut end = pfn;
+    while end > 0 {
+        let start = end.saturating_sub(BATCH);
+        let pfns = (start..end).collect::<Vec<_>>();
+        let kflags = get_kernel_pages(&pfns)?;
+        if let Some(i) = kflags
+            .iter()
+            .rposition(|flags| flags.contains(KPageFlags::COMPOUND_HEAD))
+        {
+            return Ok((pfns[i], kflags[i]));
+        }
+        end = start;
+    }
+    bail!("no compound head found for the tail page at PFN {pfn}")
+}
+
+/// Cached pages of a file that were marked idle, see [`PageMapExt::mark_idle`].
+#[derive(Debug, Default)]
+pub struct IdlePages {
+    /// The pages that were marked idle, in order.
+    pub pages: Vec<u64>,
+    /// The cached pages that could not be marked idle because they are not on an LRU list,
+    /// e.g. they were just read and are still in a per-CPU batch.
+    pub untracked: Vec<u64>,
+    /// The pages of each folio that was marked idle, and the PFN of its head page.
+    heads: Vec<(Range<u64>, u64)>,
+}
+
+impl IdlePages {
+    /// Returns the pages that were accessed since they were marked idle.
+    ///
+    /// The idle flag of a page that was evicted in the meantime is cleared too, so callers
+    /// should check that the accessed pages are still cached.
+    pub fn accessed(&self) -> Result<Vec<u64>> {
+        let bitmap = OpenOptions::new()
+            .read(true)
+            .open(PAGE_IDLE_BITMAP)
+            .context(format!("failed to open {PAGE_IDLE_BITMAP}"))?;
+
+        let mut accessed = Vec::new();
+        let mut word = None;
+        for (pages, pfn) in &self.heads {
+            let index = pfn / 64;
+            let bits = match word {
+                Some((i, bits)) if i == index => bits,
+                _ => {
+                    let mut buf = [0u8; size_of::<u64>()];
+                    bitmap
+                        .read_exact_at(&mut buf, index * size_of::<u64>() as u64)
+                        .context(format!("failed to read the idle flag of PFN {pfn}"))?;
+                    let bits = u64::from_ne_bytes(buf);
+                    word = Some((index, bits));
+                    bits
+                }
+            };
+            if bits & (1 << (pfn % 64)) == 0 {
+                accessed.extend(pages.clone());
+            }
+        }
+
+        Ok(accessed)
+    }
+}
+
+/// Number of pages whose NUMA node is queried with each `move_pages(2)` call.
+const MOVE_PAGES_BATCH: usize = 1 << 16;
+
+/// Fault in the pages `batch` of `mapping`, which maps the file from page `first`, and append
+/// the runs of them on the same NUMA node to `nodes`.
+fn query_nodes(
+    mapping: &Mapping,
+    first: u64,
+    batch: &[u64],
+    nodes: &mut Vec<(Range<u64>, u32)>,
+) -> Result<()> {
+    let vm_page = vm_page_size()?;
+    let ptrs = batch
+        .iter()
+        .map(|page| {
+            let ptr = mapping.page_ptr(page - first, vm_page);
+            // SAFETY: this is a valid aligned pointer inside the mapping.
+            unsafe { ptr.read_volatile() };
+            ptr as *mut c_void
+        })
+        .collect::<Vec<_>>();
+    let mut status = vec![0 as c_int; ptrs.len()];
+
+    // SAFETY: `ptrs` and `status` have the same length, and a null `nodes` only queries the
+    // nodes of the pages.
+    let ret = unsafe {
+        syscall(
+            SYS_move_pages,
+            0,
+            ptrs.len() as c_ulong,
+            ptrs.as_ptr(),
+            std::ptr::null::<c_int>(),
+            status.as_mut_ptr(),
+            0,
+        )
+    };
+    ensure!(
+        ret == 0,
+        "move_pages(2) failed: {}",
+        std::io::Error::last_os_error()
+    );
+
+    // a negative status is an errno, e.g. -ENOENT if the page is not present.
+    for (&page, node) in batch.iter().zip(status) {
+        if let Ok(node) = u32::try_from(node) {
+            push_run(nodes, page..page + 1, node);
+        }
+    }
+    Ok(())
+}
+
+/// Append `pages` with `value` to the sorted `runs`, extending the last run if it ends where
+/// `pages` starts and has the same value.
+fn push_run<T: PartialEq>(runs: &mut Vec<(Range<u64>, T)>, pages: Range<u64>, value: T) {
+    match runs.last_mut() {
+        Some((last, last_value)) if last.end == pages.start && *last_value == value => {
+            last.end = pages.end;
+        }
+        _ => runs.push((pages, value)),
+    }
+}
+
+/// Append the runs of the cached pages `pages` that are in the same state to `states`, using
+/// `cachestat(2)`, which does not fault the pages in.
+///
+/// The range is split in halves until each half is in a single state, so a run of clean pages
+/// takes a single call.
+fn cachestat_states(
+    file: &File,
+    pages: Range<u64>,
+    states: &mut Vec<(Range<u64>, PageState)>,
+) -> Result<()> {
+    let vm_page = vm_page_size()?;
+    let stat = file.cachestat(pages.start * vm_page..pages.end * vm_page)?;
+    let len = pages.end - pages.start;
+    let state = if stat.nr_writeback == len {
+        PageState::Writeback
+    } else if stat.nr_writeback == 0 && stat.nr_dirty == len {
+        PageState::Dirty
+    } else if stat.nr_writeback == 0 && stat.nr_dirty == 0 {
+        PageState::Clean
+    } else {
+        let middle = pages.start + len / 2;
+        cachestat_states(file, pages.start..middle, states)?;
+        return cachestat_states(file, middle..pages.end, states);
+    };
+    push_run(states, pages, state);
+    Ok(())
+}
+
+/// Map `pages` and apply `advice` to the mapping after faulting in the cached pages.
+///
+/// `MADV_PAGEOUT` and `MADV_COLD` only act on pages that are mapped, non-cached pages are not
+/// touched so they are not read into the page cache.
+fn advise_cached_pages(file: &File, pages: Range<u64>, advice: c_int) -> Result<()> {
+    let vm_page = vm_page_size()?;
+    let mut cached = file.cached_ranges(pages.clone())?.peekable();
+    if cached.peek().is_none() {
+        return Ok(());
+    }
+
+    let mapping = Mapping::new(
+        file,
+        pages.start * vm_page,
+        (pages.end - pages.start) * vm_page,
+        PROT_READ,
+        MAP_SHARED,
+    )
+    .context(format!("failed to mmap file `{}`", file.as_raw_fd()))?;
+
+    // don't let the page faults read ahead non-cached pages.
+    mapping
+        .advise(MADV_RANDOM)
+        .context("failed to disable readahead on mmaped region")?;
+
+    for run in cached {
+        for page in run? {
+            // SAFETY: this is a valid aligned pointer inside the mapping.
+            unsafe {
+                mapping
+                    .page_ptr(page - pages.start, vm_page)
+                    .read_volatile();
+            }
+        }
+    }
+
+    mapping.advise(advice)?;
+    Ok(())
+}
+
+/// Find out why the pages in the runs `survivors` were not evicted, as runs of pages that were
+/// not evicted for the same reason.
+///
+/// This runs after the last eviction attempt, so the survivors are classified by their kernel
+/// page flags when /proc/kpageflags is readable (it requires root). Otherwise only their dirty
+/// and writeback state is read through `cachestat(2)`.
+fn classify_survivors(
+    file: &File,
+    survivors: &[Range<u64>],
+) -> Result<Vec<(Range<u64>, EvictionFailure)>> {
+    let mut classified = Vec::new();
+    let kflags_readable = File::open("/proc/kpageflags").is_ok();
+
+    for range in survivors.iter().cloned() {
+        if kflags_readable {
+            file.pages_info(range)?.for_each(|info| {
+                let reason = EvictionFailure::from_page(&info.pagemap, &info.kflags);
+                push_run(&mut classified, info.page..info.page + 1, reason);
+            });
+        } else if cachestat_supported() {
+            let mut states = Vec::new();
+            cachestat_states(file, range, &mut states)?;
+            for (pages, state) in states {
+                let reason = match state {
+                    PageState::Writeback => EvictionFailure::Writeback,
+                    PageState::Dirty => EvictionFailure::Dirty,
+                    _ => EvictionFailure::Unknown,
+                };
+                push_run(&mut classified, pages, reason);
+            }
+        } else {
+            push_run(&mut classified, range, EvictionFailure::Unknown);
+        }
+    }
+
+    Ok(classified)
+}
+
+/// The page cache residency of a file at a point in time, see [`PageCacheSnapshot::capture`].
+///
+/// Snapshots are saved as text, one `key value` line per field and one block per file, with
+/// runs of pages formatted by [`fmt_page_ranges`]:
+///
+/// ```text
+/// path /var/lib/db/data
+/// pages 2560
+/// cached 0-9, 20
+/// dirty 3
+/// writeback
+/// ```
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct PageCacheSnapshot {
+    pub path: PathBuf,
+    /// Number of pages in the file.
+    pub pages: u64,
+    /// Runs of cached pages, including the dirty and writeback pages.
+    pub cached: Vec<Range<u64>>,
+    pub dirty: Vec<Range<u64>>,
+    pub writeback: Vec<Range<u64>>,
+}
+
+/// The difference between two snapshots of the same file, see [`PageCacheSnapshot::diff`].
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct SnapshotDiff {
+    /// Runs of pages that were brought into the page cache.
+    pub added: Vec<Range<u64>>,
+    /// Runs of pages that were evicted.
+    pub removed: Vec<Range<u64>>,
+    /// Runs of pages that became dirty.
+    pub dirtied: Vec<Range<u64>>,
+}
+
+impl SnapshotDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.dirtied.is_empty()
+    }
+}
+
+impl PageCacheSnapshot {
+    /// Record which pages of the file at `path` are cached, dirty or under writeback.
+    ///
+    /// The path is canonicalized so that snapshots taken from different directories can be
+    /// compared. The state of each page is read with [`PageMapExt::page_states`], through
+    /// `cachestat(2)` with `use_cachestat`.
+    pub fn capture<P: AsRef<Path>>(path: P, use_cachestat: bool) -> Result<Self> {
+        let path = path.as_ref();
+        let file = File::open(path).context(format!("failed to open {}", path.display()))?;
+        let states = file.page_states(0..file.vm_pages_count()?, use_cachestat)?;
+        let runs_in = |f: fn(PageState) -> bool| {
+            let mut runs: Vec<Range<u64>> = Vec::new();
+            for (pages, _) in states.iter().filter(|(_, state)| f(*state)) {
+                match runs.last_mut() {
+                    Some(last) if last.end == pages.start => last.end = pages.end,
+                    _ => runs.push(pages.clone()),
+                }
+            }
+            runs
+        };
+        let cached = runs_in(|_| true);
+        let dirty = runs_in(|s| s == PageState::Dirty);
+        let writeback = runs_in(|s| s == PageState::Writeback);
+
+        Ok(PageCacheSnapshot {
+            path: path.canonicalize().unwrap_or(path.to_owned()),
+            pages: file.vm_pages_count()?,
+            cached,
+            dirty,
+            writeback,
+        })
+    }
+
+    /// Compare this snapshot with a `newer` snapshot of the same file.
+    pub fn diff(&self, newer: &PageCacheSnapshot) -> SnapshotDiff {
+        SnapshotDiff {
+            added: subtract_ranges(&newer.cached, &self.cached),
+            removed: subtract_ranges(&self.cached, &newer.cached),
+            dirtied: subtract_ranges(&newer.dirty, &self.dirty),
+        }
+    }
+
+    /// Bring the recorded pages back into the page cache with `method`.
+    ///
+    /// Pages that are cached but not in the snapshot (including pages read ahead while
+    /// restoring) are evicted, so that the residency matches the snapshot as closely as
+    /// possible. Dirty and writeback state is not restored.
+    pub fn restore(&self, method: TouchMethod) -> Result<()> {
+        let file =
+            File::open(&self.path).context(format!("failed to open {}", self.path.display()))?;
+
+        for range in self.cached.iter().cloned() {
+            file.touch_range(range, method)?;
+        }
+
+        let cached = file
+            .cached_ranges(0..file.vm_pages_count()?)?
+            .collect::<Result<Vec<_>>>()?;
+        for range in subtract_ranges(&cached, &self.cached) {
+            file.evict_range(range)?;
+        }
+        Ok(())
+    }
+
+    /// Save `snapshots` to the file at `path`.
+    pub fn save<P: AsRef<Path>>(snapshots: &[PageCacheSnapshot], path: P) -> Result<()> {
+        let mut out = String::new();
+        for snapshot in snapshots {
+            let path = snapshot
+                .path
+                .to_str()
+                .context(format!("{} is not valid UTF-8", snapshot.path.display()))?;
+            ensure!(!path.contains('\n'), "{path:?} contains a newline");
+            out.push_str(&format!("{snapshot}\n"));
+        }
+
+        std::fs::write(&path, out).context(format!(
+            "failed to write snapshot to {}",
+            path.as_ref().display()
+        ))
+    }
+
+    /// Load the snapshots saved by [`PageCacheSnapshot::save`] to the file at `path`.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Vec<PageCacheSnapshot>> {
+        let text = std::fs::read_to_string(&path).context(format!(
+            "failed to read snapshot from {}",
+            path.as_ref().display()
+        ))?;
+        Self::parse(&text)
+    }
+
+    /// Parse the snapshots in `text`, see [`PageCacheSnapshot`] for the format.
+    pub fn parse(text: &str) -> Result<Vec<PageCacheSnapshot>> {
+        let mut snapshots = Vec::<PageCacheSnapshot>::new();
+        for (i, line) in text.lines().enumerate() {
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+            let context = || format!("invalid snapshot line {}: `{line}`", i + 1);
+
+            if key == "path" {
+                snapshots.push(PageCacheSnapshot {
+                    path: PathBuf::from(value),
+                    ..Default::default()
+                });
+                continue;
+            }
+
+            let snapshot = snapshots
+                .last_mut()
+                .with_context(|| format!("{}, expected a `path` line first", context()))?;
+            match key {
+                "pages" => snapshot.pages = value.parse().with_context(context)?,
+                "cached" => snapshot.cached = parse_page_ranges(value).with_context(context)?,
+                "dirty" => snapshot.dirty = parse_page_ranges(value).with_context(context)?,
+                "writeback" => {
+                    snapshot.writeback = parse_page_ranges(value).with_context(context)?
+                }
+                _ => bail!("{}, unknown key `{key}`", context()),
+            }
+        }
+        Ok(snapshots)
+    }
+}
+
+impl std::fmt::Display for PageCacheSnapshot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "path {}", self.path.display())?;
+        writeln!(f, "pages {}", self.pages)?;
+        writeln!(f, "cached {}", fmt_page_ranges(&self.cached))?;
+        writeln!(f, "dirty {}", fmt_page_ranges(&self.dirty))?;
+        writeln!(f, "writeback {}", fmt_page_ranges(&self.writeback))
+    }
+}
+
+/// return `PageMapEntry` for the virtual `page` of process `pid` from /proc/PID/pagemap, or
+/// from /proc/self/pagemap if `pid` is `None`.
+pub fn get_page_map_entry(pid: Option<Pid>, page: u64) -> Result<PageMapEntry> {
+    Ok(get_page_map_entries(pid, page, 1)?.remove(0))
+}
+
+/// return `count` consecutive `PageMapEntry`s starting at the virtual `page` of process `pid`
+/// (or this process if `None`), using a single read.
+///
+/// Reading the page map of another process requires ptrace access to it (see
+/// `man 2 ptrace`), and PFNs are hidden without CAP_SYS_ADMIN.
+pub fn get_page_map_entries(pid: Option<Pid>, page: u64, count: u64) -> Result<Vec<PageMapEntry>> {
+    let path = match pid {
+        Some(pid) => format!("/proc/{pid}/pagemap"),
+        None => "/proc/self/pagemap".to_owned(),
+    };
+    let pagemap_file = OpenOptions::new()
+        .read(true)
+        .open(&path)
+        .context(format!("failed to open {path}"))?;
+
+    let mut buf = vec![0u8; count as usize * size_of::<PageMapEntry>()];
+    pagemap_file
+        .read_exact_at(&mut buf, page * size_of::<PageMapEntry>() as u64)
+        .context(format!("failed to read {count} entries at page {page}"))?;
+
+    Ok(buf
+        .chunks_exact(size_of::<PageMapEntry>())
+        .map(|raw| {
+            PageMapEntry::from_bits_retain(u64::from_ne_bytes(raw.try_into().expect("8 bytes")))
+        })
+        .collect())
+}
+
+/// Clear the soft-dirty bits of the pages of process `pid` (or this process if `None`), by
+/// writing `4` to /proc/PID/clear_refs.
+///
+/// Pages written after this are soft-dirty again, see [`MapEntry::written_pages`]. This resets
+/// the soft-dirty state for every tracker of the process (e.g. CRIU), see
+/// `Documentation/admin-guide/mm/soft-dirty.rst`.
+///
+/// [`MapEntry::written_pages`]: crate::maps::MapEntry::written_pages
+pub fn clear_soft_dirty(pid: Option<Pid>) -> Result<()> {
+    let path = match pid {
+        Some(pid) => format!("/proc/{pid}/clear_refs"),
+        None => "/proc/self/clear_refs".to_owned(),
+    };
+    std::fs::write(&path, "4").context(format!("failed to write to {path}"))
+}
+
+/// Returns true if the kernel tracks soft-dirty bits (`CONFIG_MEM_SOFT_DIRTY`).
+///
+/// The kernel marks new mappings soft-dirty as a whole, so the page map entry of a new
+/// anonymous mapping is soft-dirty if the bits are tracked.
+pub fn soft_dirty_supported() -> Result<bool> {
+    let vm_page = vm_page_size()?;
+    // SAFETY: a new private mapping that is never accessed.
+    let addr = unsafe {
+        mmap64(
+            std::ptr::null_mut(),
+            vm_page as _,
+            PROT_READ,
+            MAP_PRIVATE | MAP_ANONYMOUS,
+            -1,
+            0,
+        )
+    };
+    ensure!(
+        addr != MAP_FAILED,
+        "mmap(2) failed: {}",
+        std::io::Error::last_os_error()
+    );
+
+    let entry = get_page_map_entry(None, addr as u64 / vm_page);
+    // SAFETY: the mapping is not used anymore.
+    cvt!(unsafe { munmap(addr, vm_page as _) }).context("munmap(2) failed")?;
+    Ok(entry?.contains(PageMapEntry::SOFT_DIRTY))
+}
+
+/// return the runs of virtual pages in `pages` of process `pid` (or this process if `None`)
+/// that are in all of `categories`.
+///
+/// The kernel is asked for the runs directly with the `PAGEMAP_SCAN` ioctl. If it is not
+/// supported (it fails with ENOTTY, or EINVAL), the page map entries are read in bulk instead
+/// and only the categories that can be derived from them are supported (see
+/// [`PageCategory::FROM_PAGE_MAP`]).
+pub fn scan_page_map(
+    pid: Option<Pid>,
+    pages: Range<u64>,
+    categories: PageCategory,
+) -> Result<Vec<Range<u64>>> {
+    if pages.is_empty() {
+        return Ok(vec![]);
+    }
+    match scan_page_map_ioctl(pid, pages.clone(), categories)? {
+        Some(runs) => Ok(runs),
+        None => {
+            log::debug!("PAGEMAP_SCAN is not supported, reading the page map entries");
+            scan_page_map_entries(pid, pages, categories)
+        }
+    }
+}
+
+/// Scan with the `PAGEMAP_SCAN` ioctl, returns `None` if the kernel does not support it, or
+/// not with these arguments.
+fn scan_page_map_ioctl(
+    pid: Option<Pid>,
+    pages: Range<u64>,
+    categories: PageCategory,
+) -> Result<Option<Vec<Range<u64>>>> {
+    let path = match pid {
+        Some(pid) => format!("/proc/{pid}/pagemap"),
+        None => "/proc/self/pagemap".to_owned(),
+    };
+    let pagemap_file = OpenOptions::new()
+        .read(true)
+        .open(&path)
+        .context(format!("failed to open {path}"))?;
+
+    let vm_page = vm_page_size()?;
+    let mut regions = vec![PageRegion::default(); 512];
+    let mut runs: Vec<Range<u64>> = Vec::new();
+    let mut start = pages.start * vm_page;
+    let end = pages.end * vm_page;
+
+    while start < end {
+        let mut arg = PmScanArg {
+            size: size_of::<PmScanArg>() as u64,
+            start,
+            end,
+            vec: regions.as_mut_ptr() as u64,
+            vec_len: regions.len() as u64,
+            category_mask: categories.bits(),
+            // regions are only split by the categories that are asked for, i.e. never.
+            return_mask: categories.bits(),
+            ..Default::default()
+        };
+        // SAFETY: `arg` points to a valid `vec` of `vec_len` regions.
+        let ret = unsafe {
+            ioctl(
+                pagemap_file.as_raw_fd(),
+                PAGEMAP_SCAN,
+                &mut arg as *mut PmScanArg,
+            )
+        };
+        if ret < 0 {
+            match Errno::last() {
+                Errno::EINTR => continue,
+                Errno::ENOTTY | Errno::EINVAL => return Ok(None),
+                errno => bail!("PAGEMAP_SCAN ioctl failed: {errno}"),
+            }
+        }
+
+        for region in &regions[..ret as usize] {
+            let run = region.start / vm_page..region.end / vm_page;
+            // a run may have been split between two calls.
+            match runs.last_mut() {
+                Some(last) if last.end == run.start => last.end = run.end,
+                _ => runs.push(run),
+            }
+        }
+        ensure!(
+            arg.walk_end > start,
+            "PAGEMAP_SCAN made no progress at {start:#x}"
+        );
+        start = arg.walk_end;
+    }
+
+    Ok(Some(runs))
+}
+
+fn scan_page_map_entries(
+    pid: Option<Pid>,
+    pages: Range<u64>,
+    categories: PageCategory,
+) -> Result<Vec<Range<u64>>> {
+    ensure!(
+        PageCategory::FROM_PAGE_MAP.contains(categories),
+        "scanning for {categories:?} requires the PAGEMAP_SCAN ioctl (Linux 6.7+)"
+    );
+
+    // read at most 512KiB of entries at a time.
+    const CHUNK: u64 = 1 << 16;
+    let mut matching = Vec::new();
+    let mut start = pages.start;
+    while start < pages.end {
+        let count = CHUNK.min(pages.end - start);
+        matching.extend(
+            (start..)
+                .zip(get_page_map_entries(pid, start, count)?)
+                .filter(|(_, entry)| PageCategory::from_page_map(entry).contains(categories))
+                .map(|(page, _)| page),
+        );
+        start += count;
+    }
+
+    Ok(coalesce_ranges(&matching))
+}
+
+/// return `KPageFlags` for for the Physical Frame Number (PFN)
+pub fn get_kernel_page(pfn: u64) -> Result<KPageFlags> {
+    Ok(get_kernel_pages(&[pfn])?.remove(0))
+}
+
+/// return `KPageFlags` for each PFN in `pfns`, in the same order.
+///
+/// /proc/kpageflags is opened once, and runs of consecutive PFNs (e.g. the pages of a large
+/// folio) are read together.
+pub fn get_kernel_pages(pfns: &[u64]) -> Result<Vec<KPageFlags>> {
+    Ok(read_kpage_entries("/proc/kpageflags", pfns)?
+        .into_iter()
+        .map(KPageFlags::from_bits_retain)
+        .collect())
+}
+
+/// return the number of times each PFN in `pfns` is mapped, from /proc/kpagecount.
+pub fn get_kernel_page_counts(pfns: &[u64]) -> Result<Vec<u64>> {
+    read_kpage_entries("/proc/kpagecount", pfns)
+}
+
+/// return the inode of the memory cgroup that each PFN in `pfns` is charged to, from
+/// /proc/kpagecgroup. The inode is zero if the page is not charged to a cgroup.
+///
+/// Use [`cgroup::cgroup_inodes`] to resolve the inodes to cgroup paths.
+pub fn get_kernel_page_cgroups(pfns: &[u64]) -> Result<Vec<u64>> {
+    read_kpage_entries("/proc/kpagecgroup", pfns)
+}
+
+/// Read the 64-bit entry of each PFN in `pfns` from one of the /proc/kpage* files.
+fn read_kpage_entries(path: &str, pfns: &[u64]) -> Result<Vec<u64>> {
+    let kpage_file = OpenOptions::new()
+        .read(true)
+        .open(path)
+        .context(format!("failed to open {path}"))?;
+
+    let mut entries = Vec::with_capacity(pfns.len());
+    let mut buf = Vec::new();
+
+    for run in pfns.chunk_by(|a, b| a.checked_add(1) == Some(*b)) {
+        buf.resize(size_of_val(run), 0);
+        kpage_file
+            .read_exact_at(&mut buf, run[0] * size_of::<u64>() as u64)
+            .context(format!("failed to read {path} for PFN {}", run[0]))?;
+
+        entries.extend(
+            buf.chunks_exact(size_of::<u64>())
+                .map(|raw| u64::from_ne_bytes(raw.try_into().expect("8 bytes"))),
+        );
+    }
+
+    Ok(entries)
+}
+
+pub enum PFNResult {}
+/// Where the page of a [`PageMapEntry`] is, see [`PageMapEntry::location`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PageLocation {
+    /// In memory, at page frame `pfn`.
+    Present { pfn: u64 },
+    /// In swap area `ty`, at page `offset` of the area.
+    ///
+    /// Pages under migration have a swap entry too, `offset` is their PFN.
+    Swapped { ty: u8, offset: u64 },
+    /// Not mapped, e.g. never faulted in, or a shmem page that was swapped out (see
+    /// [`PageMapExt::swapped_pages`]).
+    NotPresent,
+}
+
+impl PageMapEntry {
+    /// Decode where the page is.
+    ///
+    /// Fails if the PFN or swap entry is hidden, which needs root or CAP_SYS_ADMIN.
+    pub fn location(&self) -> Result<PageLocation> {
+        // docs: bits 0-54  page frame number (PFN) if present
+        //       bits 0-4   swap type if swapped
+        //       bits 5-54  swap offset if swapped
+        let frame = self.bits() & ((1u64 << 55) - 1);
+
+        if self.contains(PageMapEntry::PRESENT) {
+            ensure!(
+                frame != 0,
+                "/proc/self/pagemap: PFNs are hidden (requires root or CAP_SYS_ADMIN). \
+                \n       Kernel Page Flags cannot be read without physical frame access."
+            );
+            Ok(PageLocation::Present { pfn: frame })
+        } else if self.contains(PageMapEntry::SWAPPED) {
+            // the first page of a swap area is its header, a zero offset means it is hidden.
+            ensure!(
+                frame != 0,
+                "/proc/self/pagemap: swap entries are hidden (requires root or CAP_SYS_ADMIN)."
+            );
+            Ok(PageLocation::Swapped {
+                ty: (frame & 0x1f) as u8,
+                offset: frame >> 5,
+            })
+        } else {
+            Ok(PageLocation::NotPresent)
+        }
+    }
+
+    pub fn pfn(&self) -> Result<Option<u64>> {
+        match self.location()? {
+            PageLocation::Present { pfn } => Ok(Some(pfn)),
+            _ => Ok(None),
+        }
+    }
+}
+
+impl std::fmt::Display for KPageFlags {
+    /// Writes the names of the set flags, unknown bits are written as `BIT_<n>`.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let unknown = self.bits() & !Self::all().bits();
+        let names = self
+            .iter_names()
+            .map(|(name, _)| name.to_owned())
+            .chain(
+                (0..u64::BITS)
+                    .filter(|bit| unknown & (1 << bit) != 0)
+                    .map(|bit| format!("BIT_{bit}")),
+            )
+            .collect::<Vec<_>>();
+        write!(f, "{}", names.join(" | "))
+    }
+}
+impl std::fmt::Display for PageMapEntry {
+    /// Writes the names of the set flags, followed by the PFN or swap entry.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut names = self
+            .iter_names()
+            .map(|(name, _)| name.to_owned())
+            .collect::<Vec<_>>();
+        match self.location() {
+            Ok(PageLocation::Present { pfn }) => names.push(format!("pfn {pfn:#x}")),
+            Ok(PageLocation::Swapped { ty, offset }) => {
+                names.push(format!("swap {ty} offset {offset:#x}"))
+            }
+            Ok(PageLocation::NotPresent) => {}
+            Err(_) => names.push("hidden".to_owned()),
+        }
+        write!(f, "{}", names.join(" | "))
+    }
+}
+
+pub fn vm_page_size() -> Result<u64> {
+    Ok(sysconf(nix::unistd::SysconfVar::PAGE_SIZE)
+        .context("failed to get sys page size")?
+        .expect("page_size should be a supported option") as u64)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        fs::{File, OpenOptions},
+        io::Write,
+        ops::{Deref, DerefMut},
+        os::unix::fs::FileExt,
+        path::Path,
+        time::{SystemTime, UNIX_EPOCH},
+    };
+
+    use nix::fcntl::{FallocateFlags, fallocate};
+    use nix::libc::{MAP_PRIVATE, MAP_SHARED, PROT_READ, PROT_WRITE};
+    use nix::unistd::Pid;
+
+    use anyhow::Result;
+
+    use crate::KernelVersion;
+    use crate::cgroup::{ScratchCgroup, cgroup_inodes};
+    use crate::maps::read_maps;
+    use crate::pagemap::{
+        CachedRanges, EvictOptions, EvictionFailure, EvictionStrategy, FiemapExtentFlags, Folio,
+        KPageFlags, MINCORE_CHUNK_PAGES, Mapping, PageCacheSnapshot, PageCategory, PageInfo,
+        PageLocation, PageMapEntry, PageMapExt, PageState, PhysicalExtent, TouchMethod,
+        cachestat_supported, clear_soft_dirty, get_kernel_page, get_kernel_page_cgroups,
+        get_kernel_page_counts, get_kernel_pages, group_folios, pagemap_scan_supported,
+        scan_page_map, scan_page_map_entries, scan_page_map_ioctl, soft_dirty_supported,
+        vm_page_size,
+    };
+
+    pub struct TestFile(std::path::PathBuf, File);
+    impl TestFile {
+        fn new() -> Self {
+            let nanos = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap()
+                .as_nanos();
+            std::fs::create_dir_all(Path::new("..").join("target")).unwrap();
+            let filename = Path::new("..").join(format!("target/test-{}.txt", nanos));
+            let file = OpenOptions::new()
+                .create(true)
+                .truncate(true)
+                .write(true)
+                .read(true)
+                .open(&filename)
+                .unwrap();
+
+            TestFile(filename, file)
+        }
+    }
+    impl Deref for TestFile {
+        type Target = File;
 
         fn deref(&self) -> &Self::Target {
-            return &self.1;
+            &self.1
         }
     }
     impl DerefMut for TestFile {
@@ -382,6 +2559,764 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_cachestat() {
+        if !cachestat_supported() {
+            return;
+        }
+        let mut file = TestFile::new();
+        file.write_all(&[0u8; 0x3000]).unwrap();
+
+        // three dirty pages
+        let stat = file.cachestat(0..0).unwrap();
+        assert_eq!(stat.nr_cache, 3);
+        assert_eq!(stat.nr_dirty, 3);
+
+        file.sync_all().unwrap();
+        let stat = file.cachestat(0..0).unwrap();
+        assert_eq!(stat.nr_cache, 3);
+        assert_eq!(stat.nr_dirty, 0);
+
+        // only the second page
+        let stat = file.cachestat(0x1000..0x2000).unwrap();
+        assert_eq!(stat.nr_cache, 1);
+
+        file.evict_pages().unwrap();
+        let stat = file.cachestat(0..0).unwrap();
+        assert_eq!(stat.nr_cache, 0);
+    }
+
+    #[test]
+    fn test_cached_pages_in_and_evict_range() {
+        let mut file = TestFile::new();
+        // write one page at a time, so that the pages are not cached in one large folio that
+        // cannot be partially evicted.
+        for _ in 0..5 {
+            file.write_all(&[0u8; 0x1000]).unwrap();
+        }
+        file.sync_all().unwrap();
+
+        assert_eq!(file.cached_pages().unwrap(), vec![0, 1, 2, 3, 4]);
+        assert_eq!(file.cached_pages_in(1..3).unwrap(), vec![1, 2]);
+        assert!(file.cached_pages_in(2..6).is_err());
+
+        file.evict_range(1..3).unwrap();
+        assert_eq!(file.cached_pages().unwrap(), vec![0, 3, 4]);
+        assert_eq!(file.cached_pages_in(1..4).unwrap(), vec![3]);
+        assert_eq!(file.cached_pages_in(2..2).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_cached_ranges() {
+        let mut file = TestFile::new();
+        for _ in 0..8 {
+            file.write_all(&[0u8; 0x1000]).unwrap();
+        }
+        file.sync_all().unwrap();
+        file.evict_range(0..1).unwrap();
+        file.evict_range(5..7).unwrap();
+
+        let ranges = |pages, chunk_pages| {
+            CachedRanges::new(&file, pages, chunk_pages)
+                .unwrap()
+                .collect::<Result<Vec<_>>>()
+                .unwrap()
+        };
+        // runs that cross a chunk boundary are joined.
+        for chunk_pages in [1, 2, 3, 8, MINCORE_CHUNK_PAGES] {
+            assert_eq!(ranges(0..8, chunk_pages), vec![1..5, 7..8], "{chunk_pages}");
+        }
+        assert_eq!(ranges(2..6, 3), vec![2..5]);
+        assert_eq!(ranges(5..7, 1), vec![]);
+        assert_eq!(ranges(3..3, 1), vec![]);
+
+        assert_eq!(file.cached_ranges(0..8).unwrap().nr_pages().unwrap(), 5);
+        assert!(file.cached_ranges(0..9).is_err());
+    }
+
+    #[test]
+    fn test_evict_range_verified() {
+        let mut file = TestFile::new();
+        file.write_all(&[0u8; 0x2000]).unwrap();
+
+        // MADV_COLD does not write dirty pages back, so they survive it dirty (or under
+        // writeback, if the flusher got to them).
+        let options = EvictOptions {
+            strategy: EvictionStrategy::Cold,
+            ..Default::default()
+        };
+        let report = file.evict_range_verified(0..2, &options).unwrap();
+        assert_eq!(report.cached_before, 2);
+        assert_eq!(report.evicted, 0);
+        assert_eq!(report.attempts, 1);
+        assert_eq!(report.nr_survivors(), 2);
+        if cachestat_supported() {
+            assert!(report.survivors.iter().all(|(_, reason)| matches!(
+                reason,
+                EvictionFailure::Dirty | EvictionFailure::Writeback
+            )));
+        }
+
+        let options = EvictOptions {
+            sync_first: true,
+            retries: 2,
+            ..Default::default()
+        };
+        let report = file.evict_range_verified(0..2, &options).unwrap();
+        assert_eq!(report.cached_before, 2);
+        assert_eq!(report.evicted, 2);
+        assert!(report.survivors.is_empty());
+        assert_eq!(file.cached_pages().unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_evict_with_pageout() {
+        let mut file = TestFile::new();
+        for _ in 0..4 {
+            file.write_all(&[1u8; 0x1000]).unwrap();
+        }
+        file.sync_all().unwrap();
+        file.evict_range(0..1).unwrap();
+
+        // deactivating pages does not evict them
+        file.evict_with(0..4, &EvictionStrategy::Cold).unwrap();
+        assert_eq!(file.cached_pages().unwrap(), vec![1, 2, 3]);
+
+        let options = EvictOptions {
+            strategy: EvictionStrategy::PageOut,
+            ..Default::default()
+        };
+        let report = file.evict_range_verified(1..3, &options).unwrap();
+        assert_eq!(report.evicted, 2);
+        // MADV_PAGEOUT does not fault in non-cached pages
+        assert_eq!(file.cached_pages().unwrap(), vec![3]);
+    }
+
+    #[test]
+    fn test_touch_range() {
+        let mut file = TestFile::new();
+        for _ in 0..4 {
+            file.write_all(&[1u8; 0x1000]).unwrap();
+        }
+        file.sync_all().unwrap();
+
+        for method in [TouchMethod::Read, TouchMethod::Populate] {
+            file.evict_pages().unwrap();
+            file.touch_range(1..3, method).unwrap();
+            // readahead may bring in the surrounding pages too
+            let cached = file.cached_pages().unwrap();
+            assert!(cached.contains(&1) && cached.contains(&2), "{method}");
+        }
+
+        // these only start the reads
+        for method in [
+            TouchMethod::Readahead,
+            TouchMethod::Fadvise,
+            TouchMethod::Madvise,
+        ] {
+            file.evict_pages().unwrap();
+            file.touch_range(0..8, method).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_lock_range() {
+        let mut file = TestFile::new();
+        for _ in 0..4 {
+            file.write_all(&[1u8; 0x1000]).unwrap();
+        }
+        file.sync_all().unwrap();
+        file.evict_pages().unwrap();
+
+        let locked = file.lock_range(0..2).unwrap();
+        assert_eq!(locked.pages().unwrap(), 2);
+        assert_eq!(file.cached_pages().unwrap(), vec![0, 1]);
+
+        // locked pages cannot be evicted
+        file.evict_pages().unwrap();
+        assert_eq!(file.cached_pages().unwrap(), vec![0, 1]);
+
+        drop(locked);
+        file.evict_pages().unwrap();
+        assert!(file.cached_pages().unwrap().is_empty());
+
+        assert!(file.lock_range(4..8).is_err());
+    }
+
+    #[test]
+    fn test_group_folios() {
+        let page = |page: u64, pfn: u64, kflags: KPageFlags| PageInfo {
+            page,
+            pagemap: PageMapEntry::from_bits_retain(PageMapEntry::PRESENT.bits() | pfn),
+            kflags,
+        };
+        let head = KPageFlags::COMPOUND_HEAD;
+        let tail = KPageFlags::COMPOUND_TAIL;
+
+        let folios = group_folios(&[
+            // a tail page of a folio that started before the range
+            page(0, 99, tail),
+            page(1, 200, KPageFlags::empty()),
+            page(2, 300, head),
+            page(3, 301, tail),
+            page(4, 302, tail),
+            page(5, 303, tail),
+            // a tail page of another folio
+            page(6, 500, tail),
+            page(8, 400, KPageFlags::empty()),
+        ])
+        .unwrap();
+
+        let folio = |pages, pfn, kflags, partial| Folio {
+            pages,
+            pfn,
+            kflags,
+            partial,
+        };
+        assert_eq!(
+            folios,
+            vec![
+                folio(0..1, 99, tail, true),
+                folio(1..2, 200, KPageFlags::empty(), false),
+                folio(2..6, 300, head, false),
+                folio(6..7, 500, tail, true),
+                folio(8..9, 400, KPageFlags::empty(), false),
+            ]
+        );
+        assert_eq!(folios[2].order(), 2);
+    }
+
+    #[test]
+    fn test_scan_page_map() {
+        let mut file = TestFile::new();
+        for _ in 0..4 {
+            file.write_all(&[1u8; 0x1000]).unwrap();
+        }
+        file.sync_all().unwrap();
+
+        // map and fault in all 4 pages
+        let locked = file.lock_range(0..4).unwrap();
+        let vm_page = vm_page_size().unwrap();
+        let first = locked.mapping.addr() / vm_page;
+        let pages = first..first + 4;
+
+        let present = PageCategory::PRESENT | PageCategory::FILE;
+        let expected = vec![pages.clone()];
+        assert_eq!(
+            scan_page_map(None, pages.clone(), present).unwrap(),
+            expected
+        );
+        assert_eq!(
+            scan_page_map_entries(None, pages.clone(), present).unwrap(),
+            expected
+        );
+        assert!(
+            scan_page_map(None, pages.clone(), PageCategory::SWAPPED)
+                .unwrap()
+                .is_empty()
+        );
+        assert!(
+            scan_page_map(None, pages.start..pages.start, present)
+                .unwrap()
+                .is_empty()
+        );
+
+        if !pagemap_scan_supported() {
+            assert!(scan_page_map(None, pages.clone(), PageCategory::HUGE).is_err());
+        }
+
+        // unknown categories fail with EINVAL, like an unsupported ioctl fails with ENOTTY.
+        let unknown = PageCategory::from_bits_retain(1 << 63);
+        assert_eq!(scan_page_map_ioctl(None, pages, unknown).unwrap(), None);
+    }
+
+    #[test]
+    fn test_page_states() {
+        if !cachestat_supported() {
+            return;
+        }
+        let mut file = TestFile::new();
+        for _ in 0..4 {
+            file.write_all(&[1u8; 0x1000]).unwrap();
+        }
+        file.sync_all().unwrap();
+        file.evict_range(0..1).unwrap();
+        file.write_at(&[2u8; 0x1000], 0x2000).unwrap();
+
+        assert_eq!(
+            file.page_states(0..4, true).unwrap(),
+            vec![
+                (1..2, PageState::Clean),
+                (2..3, PageState::Dirty),
+                (3..4, PageState::Clean)
+            ]
+        );
+        assert_eq!(
+            file.page_states(2..3, true).unwrap(),
+            vec![(2..3, PageState::Dirty)]
+        );
+        file.sync_all().unwrap();
+        assert_eq!(
+            file.page_states(0..4, true).unwrap(),
+            vec![(1..4, PageState::Clean)]
+        );
+    }
+
+    #[test]
+    #[ignore]
+    fn test_page_states_from_kernel_flags_run_as_root() {
+        let mut file = TestFile::new();
+        for _ in 0..4 {
+            file.write_all(&[1u8; 0x1000]).unwrap();
+        }
+        file.sync_all().unwrap();
+        file.evict_range(0..1).unwrap();
+        file.write_at(&[2u8; 0x1000], 0x2000).unwrap();
+
+        assert_eq!(
+            file.page_states(0..4, false).unwrap(),
+            vec![
+                (1..2, PageState::Clean),
+                (2..3, PageState::Dirty),
+                (3..4, PageState::Clean)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let mut file = TestFile::new();
+        for _ in 0..8 {
+            file.write_all(&[1u8; 0x1000]).unwrap();
+        }
+        file.sync_all().unwrap();
+        file.evict_range(2..4).unwrap();
+        file.evict_range(6..8).unwrap();
+
+        let before = PageCacheSnapshot::capture(&file.0, cachestat_supported()).unwrap();
+        assert_eq!(before.pages, 8);
+        assert_eq!(before.cached, vec![0..2, 4..6]);
+
+        let text = before.to_string();
+        assert_eq!(
+            PageCacheSnapshot::parse(&text).unwrap(),
+            vec![before.clone()]
+        );
+        assert!(PageCacheSnapshot::parse("pages 8").is_err());
+        assert!(PageCacheSnapshot::parse("path /a\nfoo 1").is_err());
+
+        file.evict_range(0..1).unwrap();
+        file.write_at(&[2u8; 0x1000], 0x6000).unwrap();
+        let after = PageCacheSnapshot::capture(&file.0, cachestat_supported()).unwrap();
+        let diff = before.diff(&after);
+        assert_eq!(diff.added, vec![6..7]);
+        assert_eq!(diff.removed, vec![0..1]);
+        assert_eq!(diff.dirtied, vec![6..7]);
+
+        file.sync_all().unwrap();
+        before.restore(TouchMethod::Read).unwrap();
+        assert_eq!(file.cached_pages().unwrap(), vec![0, 1, 4, 5]);
+    }
+
+    #[test]
+    #[ignore]
+    fn test_evict_with_memcg_run_as_root() {
+        let mut file = TestFile::new();
+        file.write_all(&[1u8; 0x4000]).unwrap();
+        file.sync_all().unwrap();
+        file.evict_pages().unwrap();
+
+        let mut scratch = ScratchCgroup::create(format!("ff-test-{}", std::process::id())).unwrap();
+        scratch.enter().unwrap();
+        // the pages are charged to the scratch cgroup
+        let mut buf = [0u8; 0x4000];
+        file.read_exact_at(&mut buf, 0).unwrap();
+        assert_eq!(file.cached_pages().unwrap(), vec![0, 1, 2, 3]);
+
+        let strategy = EvictionStrategy::MemcgReclaim(scratch.path().to_path_buf());
+        file.evict_with(0..4, &strategy).unwrap();
+        assert_eq!(file.cached_pages().unwrap(), vec![]);
+    }
+
+    #[test]
+    #[ignore]
+    fn test_kpage_count_and_cgroup_run_as_root() {
+        let mut file = TestFile::new();
+        file.write_all(&[1u8; 0x2000]).unwrap();
+        file.sync_all().unwrap();
+        file.evict_pages().unwrap();
+
+        let mut scratch =
+            ScratchCgroup::create(format!("ff-kpage-{}", std::process::id())).unwrap();
+        scratch.enter().unwrap();
+        // the pages are charged to the scratch cgroup, and stay mapped until `locked` is dropped
+        let locked = file.lock_range(0..2).unwrap();
+
+        let pfns = file
+            .pages_info(0..2)
+            .unwrap()
+            .map(|info| info.pagemap.pfn().unwrap().unwrap())
+            .collect::<Vec<_>>();
+        assert!(
+            get_kernel_page_counts(&pfns)
+                .unwrap()
+                .iter()
+                .all(|count| *count >= 1)
+        );
+
+        let inodes = cgroup_inodes().unwrap();
+        for ino in get_kernel_page_cgroups(&pfns).unwrap() {
+            assert_eq!(inodes.get(&ino).map(|p| p.as_path()), Some(scratch.path()));
+        }
+
+        drop(locked);
+        file.evict_pages().unwrap();
+    }
+
+    #[test]
+    #[ignore]
+    fn test_mark_idle_run_as_root() {
+        let mut file = TestFile::new();
+        file.write_all(&[1u8; 0x4000]).unwrap();
+        file.sync_all().unwrap();
+        file.evict_pages().unwrap();
+        file.touch_range(0..4, TouchMethod::Read).unwrap();
+        // mlock(2) drains the per-CPU LRU batches, so the pages just read are on the LRU.
+        drop(file.lock_range(0..4).unwrap());
+        // the flag of a large folio is shared by all its pages, look the folio up before the
+        // pages are marked idle since this faults them in.
+        let folio = file
+            .folios(0..4)
+            .unwrap()
+            .into_iter()
+            .find(|folio| folio.pages.contains(&2))
+            .unwrap();
+
+        let idle = file.mark_idle(0..4).unwrap();
+        assert_eq!(idle.pages, vec![0, 1, 2, 3]);
+        assert!(idle.untracked.is_empty());
+        assert_eq!(idle.accessed().unwrap(), Vec::<u64>::new());
+
+        let mut buf = [0u8; 1];
+        file.read_exact_at(&mut buf, 0x2000).unwrap();
+        assert_eq!(idle.accessed().unwrap(), folio.pages.collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_written_pages() {
+        if !soft_dirty_supported().unwrap() {
+            return;
+        }
+        let vm_page = vm_page_size().unwrap();
+        let mut file = TestFile::new();
+        file.write_all(&[1u8; 0x4000]).unwrap();
+
+        let shared =
+            Mapping::new(&file, 0, 4 * vm_page, PROT_READ | PROT_WRITE, MAP_SHARED).unwrap();
+        let private =
+            Mapping::new(&file, 0, 4 * vm_page, PROT_READ | PROT_WRITE, MAP_PRIVATE).unwrap();
+        let entry = |mapping: &Mapping| {
+            read_maps(Pid::this())
+                .unwrap()
+                .into_iter()
+                .find(|m| m.start == mapping.addr())
+                .unwrap()
+        };
+
+        clear_soft_dirty(None).unwrap();
+        // SAFETY: both pages are mapped and writable.
+        unsafe {
+            shared.page_ptr(2, vm_page).write_volatile(2);
+            private.page_ptr(1, vm_page).write_volatile(2);
+        }
+        assert_eq!(
+            entry(&shared).written_pages(None, vm_page).unwrap(),
+            vec![2..3]
+        );
+        assert_eq!(
+            entry(&private).written_pages(None, vm_page).unwrap(),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn test_page_location() {
+        let present = PageMapEntry::from_bits_retain(PageMapEntry::PRESENT.bits() | 0x45be1);
+        assert_eq!(
+            present.location().unwrap(),
+            PageLocation::Present { pfn: 0x45be1 }
+        );
+        assert_eq!(present.pfn().unwrap(), Some(0x45be1));
+        assert_eq!(present.to_string(), "PRESENT | pfn 0x45be1");
+
+        // swap area 2, offset 0x1234
+        let swapped =
+            PageMapEntry::from_bits_retain(PageMapEntry::SWAPPED.bits() | 0x1234 << 5 | 2);
+        assert_eq!(
+            swapped.location().unwrap(),
+            PageLocation::Swapped {
+                ty: 2,
+                offset: 0x1234
+            }
+        );
+        assert_eq!(swapped.pfn().unwrap(), None);
+        assert_eq!(swapped.to_string(), "SWAPPED | swap 2 offset 0x1234");
+
+        assert!(PageMapEntry::SWAPPED.location().is_err());
+        assert!(PageMapEntry::PRESENT.location().is_err());
+        assert_eq!(
+            PageMapEntry::SOFT_DIRTY.location().unwrap(),
+            PageLocation::NotPresent
+        );
+    }
+
+    #[test]
+    fn test_numa_nodes() {
+        let mut file = TestFile::new();
+        file.write_all(&[1u8; 0x4000]).unwrap();
+        file.sync_all().unwrap();
+        file.evict_pages().unwrap();
+        file.touch_range(1..3, TouchMethod::Read).unwrap();
+
+        let nodes = file.numa_nodes(0..4).unwrap();
+        assert_eq!(
+            nodes
+                .iter()
+                .map(|(pages, _)| pages.clone())
+                .collect::<Vec<_>>(),
+            vec![1..3]
+        );
+        // every page is on node 0 of a single node machine.
+        let online = std::fs::read_to_string("/sys/devices/system/node/online").unwrap();
+        if online.trim() == "0" {
+            assert!(nodes.iter().all(|(_, node)| *node == 0));
+        }
+        assert_eq!(file.cached_pages().unwrap(), vec![1, 2]);
+    }
+
+    #[test]
+    fn test_swapped_pages() {
+        // not a shmem file
+        let mut file = TestFile::new();
+        file.write_all(&[1u8; 0x3000]).unwrap();
+        assert_eq!(file.swapped_pages(0..3).unwrap(), vec![]);
+
+        let path = format!("/dev/shm/ff-swapped-{}", std::process::id());
+        let mut shm = OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .read(true)
+            .write(true)
+            .open(&path)
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        // a page, a hole and a page, nothing is swapped out without memory pressure.
+        shm.write_all(&[1u8; 0x1000]).unwrap();
+        shm.write_all_at(&[1u8; 0x1000], 0x2000).unwrap();
+        assert_eq!(super::data_pages(&shm, 0..3).unwrap(), vec![0..1, 2..3]);
+        assert_eq!(shm.swapped_pages(0..3).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_data_layout() {
+        let file = TestFile::new();
+        // data, a hole, an unwritten extent, data and a hole at the end.
+        file.write_all_at(&[1u8; 0x1000], 0).unwrap();
+        fallocate(&*file, FallocateFlags::empty(), 0x3000, 0x2000).unwrap();
+        file.write_all_at(&[1u8; 0x1000], 0x5000).unwrap();
+        file.set_len(0x8000).unwrap();
+
+        let layout = file.data_layout(0..8).unwrap();
+        assert_eq!(layout.data, vec![0..1, 5..6]);
+        assert_eq!(layout.unwritten, vec![3..5]);
+        assert_eq!(layout.holes, vec![1..3, 6..8]);
+        assert!(layout.is_sparse());
+        assert_eq!(layout.nr_data(), 2);
+        assert_eq!(layout.count_data(&[0..2, 3..4, 5..6, 7..8]), 2);
+
+        // writing to an unwritten extent makes it data.
+        file.write_all_at(&[1u8; 0x1000], 0x3000).unwrap();
+        let layout = file.data_layout(2..6).unwrap();
+        assert_eq!(layout.data, vec![3..4, 5..6]);
+        assert_eq!(layout.unwritten, vec![4..5]);
+        assert_eq!(layout.holes, vec![2..3]);
+
+        let dense = TestFile::new();
+        dense.write_all_at(&[1u8; 0x2000], 0).unwrap();
+        assert!(!dense.data_layout(0..2).unwrap().is_sparse());
+    }
+
+    #[test]
+    fn test_physical_extents() {
+        let file = TestFile::new();
+        file.write_all_at(&[1u8; 0x2000], 0).unwrap();
+        fallocate(&*file, FallocateFlags::empty(), 0x3000, 0x1000).unwrap();
+        file.sync_all().unwrap();
+
+        let extents = file.physical_extents(0..4).unwrap();
+        // the data may be in more than one extent, but it is all on disk.
+        let (unwritten, data) = extents
+            .split_last()
+            .expect("the data and the unwritten extent");
+        assert_eq!(data.iter().map(|e| e.length).sum::<u64>(), 0x2000);
+        assert!(data.iter().all(|e| e.physical.is_some()));
+        assert_eq!(unwritten.logical_range(), 0x3000..0x4000);
+        assert!(unwritten.flags.contains(FiemapExtentFlags::UNWRITTEN));
+
+        // an extent outside of the pages is left out.
+        let extents = file.physical_extents(3..4).unwrap();
+        assert_eq!(extents.len(), 1);
+        assert_eq!(extents[0].physical, unwritten.physical);
+        assert!(file.physical_extents(2..3).unwrap().is_empty());
+    }
+
+    #[test]
+    #[ignore]
+    fn test_fibmap_extents_run_as_root() {
+        let file = TestFile::new();
+        file.write_all_at(&[1u8; 0x2000], 0).unwrap();
+        file.write_all_at(&[1u8; 0x1000], 0x3000).unwrap();
+        file.sync_all().unwrap();
+
+        let fiemap = file.physical_extents(0..4).unwrap();
+        let fibmap = super::fibmap_extents(&file, 0..0x4000).unwrap();
+        // the same blocks, without the extent flags.
+        let blocks = |extents: &[PhysicalExtent]| {
+            extents
+                .iter()
+                .map(|e| (e.logical_range(), e.physical))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(blocks(&fiemap), blocks(&fibmap));
+        assert!(
+            fibmap
+                .iter()
+                .all(|e| e.flags.bits() == FiemapExtentFlags::MERGED.bits())
+        );
+    }
+
+    #[test]
+    fn test_kpageflags_display_and_describe() {
+        let flags = KPageFlags::from_bits_retain(
+            (KPageFlags::UPTODATE | KPageFlags::MMAP | KPageFlags::PRIVATE).bits() | 1 << 50,
+        );
+        assert_eq!(flags.to_string(), "UPTODATE | MMAP | PRIVATE | BIT_50");
+        assert_eq!(KPageFlags::empty().to_string(), "");
+
+        let names = |flags: KPageFlags, major, minor| {
+            flags
+                .describe(&KernelVersion {
+                    major,
+                    minor,
+                    patch: 0,
+                })
+                .into_iter()
+                .map(|(name, _)| name)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            names(flags, 6, 1),
+            ["UPTODATE", "MMAP", "PRIVATE", "BIT_50"]
+        );
+
+        let repurposed = KPageFlags::BALLOON | KPageFlags::MAPPEDTODISK;
+        assert_eq!(names(repurposed, 4, 19), ["BALLOON", "MAPPEDTODISK"]);
+        assert_eq!(names(repurposed, 6, 1), ["OFFLINE", "MAPPEDTODISK"]);
+        assert_eq!(names(repurposed, 6, 12), ["OFFLINE", "OWNER_2"]);
+
+        assert!(KPageFlags::KERNEL_HACKING.contains(KPageFlags::MLOCKED | KPageFlags::ARCH_2));
+        assert!(!KPageFlags::KERNEL_HACKING.intersects(KPageFlags::PGTABLE));
+    }
+
+    #[test]
+    fn test_eviction_failure_from_page() {
+        let exclusive = PageMapEntry::PRESENT | PageMapEntry::EXCL_MAP;
+        assert_eq!(
+            EvictionFailure::from_page(&exclusive, &(KPageFlags::DIRTY | KPageFlags::WRITEBACK)),
+            EvictionFailure::Writeback
+        );
+        assert_eq!(
+            EvictionFailure::from_page(&exclusive, &(KPageFlags::UPTODATE | KPageFlags::MMAP)),
+            EvictionFailure::Unknown
+        );
+        assert_eq!(
+            EvictionFailure::from_page(&PageMapEntry::PRESENT, &KPageFlags::MMAP),
+            EvictionFailure::Mmap
+        );
+    }
+
+    #[test]
+    #[ignore]
+    fn test_evict_range_verified_classifies_survivors_run_as_root() {
+        let vm_page = vm_page_size().unwrap();
+        let mut file = TestFile::new();
+        // write one page at a time, so that the pages are not cached in one large folio.
+        for _ in 0..4 {
+            file.write_all(&[1u8; 0x1000]).unwrap();
+        }
+        file.sync_all().unwrap();
+
+        // POSIX_FADV_DONTNEED skips mapped pages, a mapping of page 1 alone (so that fault-around
+        // does not map its neighbours) keeps it cached.
+        let mapping = Mapping::new(&file, vm_page, vm_page, PROT_READ, MAP_SHARED).unwrap();
+        // SAFETY: the page is mapped and readable.
+        unsafe {
+            mapping.page_ptr(0, vm_page).read_volatile();
+        }
+
+        let report = file
+            .evict_range_verified(0..4, &EvictOptions::default())
+            .unwrap();
+        assert_eq!(report.cached_before, 4);
+        assert_eq!(report.evicted, 3);
+        assert_eq!(report.survivors, vec![(1..2, EvictionFailure::Mmap)]);
+    }
+
+    #[test]
+    #[ignore]
+    fn test_pages_info_matches_page_info_run_as_root() {
+        let mut file = TestFile::new();
+        // write one page at a time, so that the pages are not cached in one large folio.
+        for _ in 0..8 {
+            file.write_all(&[1u8; 0x1000]).unwrap();
+        }
+        file.sync_all().unwrap();
+        file.evict_range(2..5).unwrap();
+        assert_eq!(file.cached_pages().unwrap(), vec![0, 1, 5, 6, 7]);
+
+        // flags that do not change from one lookup to the next, unlike e.g. REFERENCED.
+        let stable = KPageFlags::UPTODATE
+            | KPageFlags::DIRTY
+            | KPageFlags::COMPOUND_HEAD
+            | KPageFlags::COMPOUND_TAIL;
+
+        // the evicted pages are faulted in, so every page has a frame.
+        let infos = file.pages_info(0..8).unwrap().collect::<Vec<_>>();
+        assert_eq!(
+            infos.iter().map(|info| info.page).collect::<Vec<_>>(),
+            (0..8).collect::<Vec<_>>()
+        );
+        let pfns = infos
+            .iter()
+            .map(|info| info.pagemap.pfn().unwrap().unwrap())
+            .collect::<Vec<_>>();
+
+        for (info, pfn) in infos.iter().zip(&pfns) {
+            let (pagemap, kflags) = file.page_info(info.page).unwrap();
+            assert_eq!(pagemap.pfn().unwrap(), Some(*pfn), "page {}", info.page);
+            assert_eq!(kflags & stable, info.kflags & stable, "page {}", info.page);
+        }
+
+        // reading the flags of many frames at once gives the flags of each frame.
+        for (kflags, pfn) in get_kernel_pages(&pfns).unwrap().into_iter().zip(&pfns) {
+            assert_eq!(
+                kflags & stable,
+                get_kernel_page(*pfn).unwrap() & stable,
+                "PFN {pfn}"
+            );
+        }
+    }
+
     #[test]
     #[ignore]
     fn test_out_of_bounds_page_info_run_as_root() {
@@ -395,6 +3330,7 @@ mod test {
             filename.to_path_buf(),
             OpenOptions::new()
                 .create(true)
+                .truncate(true)
                 .write(true)
                 .read(true)
                 .open(filename)